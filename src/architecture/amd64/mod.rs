@@ -4,7 +4,10 @@
 //!
 
 // internal crate
-use crate::interrupts::{gdt, idt, PICS};
+use crate::{
+    interrupts::{gdt, idt, PICS},
+    syscall, time,
+};
 
 // external crates
 use x86_64::instructions;
@@ -14,12 +17,17 @@ use x86_64::instructions;
 /// The default steps are :
 /// - init GDT : `Global Descriptor Table`
 /// - init IDT : `Interrupt Descriptor Table`
+/// - enable the `syscall` instruction
 /// - init PICs chips : `Programmable Interrupt Controller`
+/// - init PIT chip : `Programmable Interval Timer`, to fire the timer interrupt
+///   at `time::TICK_FREQUENCY`
 /// - enable interrupts with asm instruction `sti`
 pub fn init() {
     gdt::init();
     idt::init();
+    syscall::init();
     unsafe { PICS.lock().initialize() };
+    time::init();
     instructions::interrupts::enable();
 }
 
@@ -0,0 +1,134 @@
+//! This module builds minimal ELF executables, used by the tests running user
+//! programs.
+//!
+//! The file header is followed by the program headers, and then by the code,
+//! which is the entry point. The executables are run in children of the
+//! kernel with `run`, which collects their final state.
+//!
+
+// internal crate
+use crate::{
+    memory::layout::USER_SPACE_START,
+    task::{
+        elf::{PF_R, PF_X, PT_LOAD},
+        exec,
+        process::{self, Pid, ProcessState},
+    },
+};
+
+// external crates
+use alloc::vec::Vec;
+
+/// Size of the file header.
+const FILE_HEADER_SIZE: usize = 64;
+/// Size of a program header.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Offset of the code in the executables built by `executable`.
+pub const CODE_OFFSET: usize = code_offset(1);
+
+/// A program header of an executable.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    /// Offset of the segment in the file.
+    pub offset: u64,
+    pub address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+
+impl Segment {
+    /// A loadable segment of the given `flags` at `address`, starting at the
+    /// beginning of the file.
+    pub fn load(flags: u32, address: u64, file_size: u64, memory_size: u64) -> Self {
+        Segment {
+            kind: PT_LOAD,
+            flags,
+            offset: 0,
+            address,
+            file_size,
+            memory_size,
+            align: 0x1000,
+        }
+    }
+}
+
+/// Returns the offset of the code in an executable with `segments` program
+/// headers.
+pub const fn code_offset(segments: usize) -> usize {
+    FILE_HEADER_SIZE + segments * PROGRAM_HEADER_SIZE
+}
+
+/// Build an executable made of a single segment containing `code`, loaded at
+/// the start of the user space.
+pub fn executable(code: &[u8]) -> Vec<u8> {
+    let size = (CODE_OFFSET + code.len()) as u64;
+    let segment = Segment::load(PF_R | PF_X, USER_SPACE_START, size, size);
+    executable_with(code, &[segment])
+}
+
+/// Build an executable with the given program headers, followed by `code`.
+///
+/// The file is expected to be loaded at the start of the user space : the
+/// entry point is `USER_SPACE_START + code_offset(segments.len())`.
+pub fn executable_with(code: &[u8], segments: &[Segment]) -> Vec<u8> {
+    let entry = USER_SPACE_START + code_offset(segments.len()) as u64;
+
+    let mut image = Vec::new();
+    // file header
+    image.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    image.extend_from_slice(&[0; 8]);
+    image.extend_from_slice(&2u16.to_le_bytes()); // executable
+    image.extend_from_slice(&0x3eu16.to_le_bytes()); // x86_64
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&entry.to_le_bytes());
+    image.extend_from_slice(&(FILE_HEADER_SIZE as u64).to_le_bytes()); // program headers
+    image.extend_from_slice(&0u64.to_le_bytes()); // section headers
+    image.extend_from_slice(&0u32.to_le_bytes());
+    image.extend_from_slice(&(FILE_HEADER_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+    image.extend_from_slice(&[0; 6]);
+
+    // program headers
+    for segment in segments {
+        image.extend_from_slice(&segment.kind.to_le_bytes());
+        image.extend_from_slice(&segment.flags.to_le_bytes());
+        image.extend_from_slice(&segment.offset.to_le_bytes());
+        image.extend_from_slice(&segment.address.to_le_bytes());
+        image.extend_from_slice(&segment.address.to_le_bytes());
+        image.extend_from_slice(&segment.file_size.to_le_bytes());
+        image.extend_from_slice(&segment.memory_size.to_le_bytes());
+        image.extend_from_slice(&segment.align.to_le_bytes());
+    }
+
+    image.extend_from_slice(code);
+    image
+}
+
+/// Write the displacement from the end of the 4 bytes at `displacement` to
+/// `target` in `code`.
+pub fn patch(code: &mut [u8], displacement: usize, target: usize) {
+    let value = (target - (displacement + 4)) as u32;
+    code[displacement..displacement + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// ! ------------- running -------------
+
+/// Wait for the child `pid` of the kernel to terminate, collect it, and
+/// returns its final state.
+pub fn wait(pid: Pid) -> ProcessState {
+    match process::wait(Some(pid), true) {
+        Ok(Some(terminated)) if terminated.pid == pid => terminated.state,
+        result => panic!("unexpected wait result {:?}", result),
+    }
+}
+
+/// Run the given executable without arguments nor environment, and returns
+/// its final state.
+pub fn run(image: &[u8]) -> ProcessState {
+    wait(exec::spawn(image, &[], &[]).expect("spawn failed"))
+}
@@ -4,6 +4,9 @@
 //! needs to be loaded in the kernel even if not in test.
 //!
 
+// submodules export
+pub mod executable;
+
 // ! ------------- unit tests -------------
 
 // internal crate
@@ -40,8 +43,9 @@ entry_point!(test_kernel_main);
 /// Entry point of integration tests.
 #[cfg(test)]
 #[no_mangle]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     super::init();
+    crate::memory::init(boot_info);
     crate::test_main();
     loop {}
 }
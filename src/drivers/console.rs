@@ -0,0 +1,106 @@
+//! The console used by user programs : output goes to the VGA buffer and the
+//! serial port, input comes from the keyboard.
+//!
+
+// internal crate
+use super::{serial::SERIAL1, vga::WRITER};
+use crate::task::{
+    file::{File, FileError},
+    wait_queue::{Interrupted, WaitQueue},
+};
+
+// external crates
+use alloc::{collections::VecDeque, string::String, sync::Arc};
+use core::fmt::Write;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Maximum number of characters typed but not read yet.
+const INPUT_CAPACITY: usize = 1024;
+
+lazy_static! {
+    /// The characters typed but not read yet.
+    ///
+    /// It must only be locked with interrupts disabled.
+    static ref INPUT: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+}
+
+/// The threads waiting for input.
+static READERS: WaitQueue = WaitQueue::new();
+
+/// Add a typed character to the input, dropping it if the input is full.
+///
+/// Called by the keyboard interrupt handler.
+pub fn push_input(character: char) {
+    let mut buffer = [0; 4];
+    interrupts::without_interrupts(|| {
+        let mut input = INPUT.lock();
+        for &byte in character.encode_utf8(&mut buffer).as_bytes() {
+            if input.len() < INPUT_CAPACITY {
+                input.push_back(byte);
+            }
+        }
+    });
+    READERS.wake_all();
+}
+
+/// Read typed characters into `buffer`, blocking until at least one is
+/// available.
+///
+/// Returns the number of bytes read, or an error if the wait is interrupted by
+/// a signal.
+pub fn read(buffer: &mut [u8]) -> Result<usize, Interrupted> {
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+    let mut read = 0;
+    READERS.wait_until_interruptible(|| {
+        let mut input = INPUT.lock();
+        while read < buffer.len() {
+            match input.pop_front() {
+                Some(byte) => buffer[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        read > 0
+    })?;
+    Ok(read)
+}
+
+/// Write `bytes` to the VGA buffer and the serial port.
+///
+/// Invalid UTF-8 sequences are replaced.
+pub fn write(bytes: &[u8]) {
+    let text = String::from_utf8_lossy(bytes);
+    interrupts::without_interrupts(|| {
+        WRITER.lock().write_string(&text);
+        SERIAL1
+            .lock()
+            .write_str(&text)
+            .expect("Printing to serial failed");
+    });
+}
+
+/// The console as a file, given to user programs as their standard input and
+/// outputs.
+pub struct Console;
+
+impl Console {
+    /// Returns a new reference to the console file.
+    pub fn file() -> Arc<dyn File> {
+        Arc::new(Console)
+    }
+}
+
+impl File for Console {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, FileError> {
+        Ok(read(buffer)?)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize, FileError> {
+        write(data);
+        Ok(data.len())
+    }
+}
@@ -3,5 +3,7 @@
 
 // submodules export
 pub mod cmos;
+pub mod console;
+pub mod pit;
 pub mod serial;
 pub mod vga;
@@ -0,0 +1,32 @@
+//! This module permits to configure the `Programmable Interval Timer`.
+//!
+//! The channel 0 of the PIT is wired to the first line of the PICs, and is the
+//! source of the hardware timer interrupt.
+//!
+
+// external crates
+use x86_64::instructions::port::Port;
+
+/// Frequency of the oscillator driving the PIT, in Hz.
+const BASE_FREQUENCY: u32 = 1_193_182;
+/// Data port of the channel 0.
+const CHANNEL_0_PORT: u16 = 0x40;
+/// Mode/command port of the PIT.
+const COMMAND_PORT: u16 = 0x43;
+
+/// Set the frequency of the timer interrupt, in Hz.
+///
+/// The actual frequency is an approximation, as the PIT can only divide its
+/// base frequency by an integer.
+pub fn set_frequency(frequency: u32) {
+    let divisor = (BASE_FREQUENCY / frequency).max(1).min(0xffff) as u16;
+
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut data: Port<u8> = Port::new(CHANNEL_0_PORT);
+    unsafe {
+        // channel 0, lobyte/hibyte access, mode 3 (square wave), binary
+        command.write(0x36);
+        data.write((divisor & 0xff) as u8);
+        data.write((divisor >> 8) as u8);
+    }
+}
@@ -1,13 +1,24 @@
 //! Defines functions to be called when an exception occurs.
 //!
+//! Page faults only map user pages lazily. The kernel heap is deliberately
+//! not grown by the page fault handler : a fault taken while the heap or the
+//! frame allocator is locked could not be resolved, so the heap maps its new
+//! pages itself when its allocator runs out of memory, see `memory::heap`.
+//!
 
 // internal crate
-use crate::println;
+use super::trap::TrapFrame;
+use crate::{
+    memory::{address_space::FaultError, layout, user},
+    println,
+    task::{process, signal},
+};
 
 // external crates
 use x86_64::{
     registers::control::Cr2,
     structures::idt::{InterruptStackFrame, PageFaultErrorCode},
+    VirtAddr,
 };
 
 // ! ------------- exceptions handlers -------------
@@ -19,18 +30,78 @@ pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStac
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+/// Exception handler for the divide error exception.
+///
+/// A fault of user code sends `SIGFPE` to its process, a fault of the kernel
+/// triggers a kernel panic.
+pub(super) fn divide_error(frame: &mut TrapFrame) {
+    if frame.is_user() {
+        signal::force(signal::SIGFPE);
+        return;
+    }
+    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", frame);
+}
+
+/// Exception handler for the invalid opcode exception.
+///
+/// A fault of user code sends `SIGILL` to its process, a fault of the kernel
+/// triggers a kernel panic.
+pub(super) fn invalid_opcode(frame: &mut TrapFrame) {
+    if frame.is_user() {
+        signal::force(signal::SIGILL);
+        return;
+    }
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", frame);
+}
+
+/// Exception handler for the general protection fault exception.
+///
+/// A fault of user code sends `SIGSEGV` to its process, a fault of the kernel
+/// triggers a kernel panic.
+pub(super) fn general_protection(frame: &mut TrapFrame) {
+    if frame.is_user() {
+        signal::force(signal::SIGSEGV);
+        return;
+    }
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}", frame);
+}
+
 /// Exception handler for the page fault exception.
 ///
-/// By default, print to screen and trigger a kernel panic.
-pub extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: &mut InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
+/// A fault on a user page which is reserved but not mapped yet is resolved by
+/// mapping it, and the faulting code resumes. Otherwise, a fault of user code
+/// sends `SIGSEGV` to its process, or `SIGBUS` if no frame could back the
+/// page. Faults of the kernel while accessing user memory are recovered
+/// through the exception fixup table. Otherwise, print to screen and trigger a
+/// kernel panic : this includes faults in `KernelRegion::Heap`, whose pages
+/// are all mapped by the heap when it grows.
+pub(super) fn page_fault(frame: &mut TrapFrame) {
+    let addr = Cr2::read();
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+    let fault = if layout::is_user_range(addr.as_u64(), 1) {
+        process::handle_page_fault(addr, error_code)
+    } else {
+        Err(FaultError::NotReserved)
+    };
+    if fault.is_ok() {
+        return;
+    }
+
+    if frame.is_user() {
+        match fault {
+            Err(FaultError::OutOfMemory) => signal::force(signal::SIGBUS),
+            _ => signal::force(signal::SIGSEGV),
+        }
+        return;
+    }
+    if let Some(fixup) = user::search_fixup(VirtAddr::new(frame.rip)) {
+        frame.rip = fixup.as_u64();
+        return;
+    }
+
     panic!(
         "EXCEPTION: PAGE FAULT\nAccessed address: {:?}\nError code: {:?}\n{:#?}",
-        Cr2::read(),
-        error_code,
-        stack_frame
+        addr, error_code, frame
     );
 }
 
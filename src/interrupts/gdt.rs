@@ -2,11 +2,15 @@
 //!
 
 // external crates
+use core::cell::UnsafeCell;
 use lazy_static::lazy_static;
 use x86_64::{
-    instructions::{segmentation::set_cs, tables::load_tss},
+    instructions::{
+        segmentation::{load_ss, set_cs},
+        tables::load_tss,
+    },
     structures::{
-        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        gdt::{Descriptor, DescriptorFlags, GlobalDescriptorTable, SegmentSelector},
         tss::TaskStateSegment,
     },
     VirtAddr,
@@ -17,11 +21,19 @@ use x86_64::{
 /// This index of the double fault exception in the `Interrupt Stack Table`.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// A wrapper around the `Task State Segment`, which is modified at each context
+/// switch.
+struct TaskStateSegmentCell(UnsafeCell<TaskStateSegment>);
+
+// the TSS is only modified with interrupts disabled
+unsafe impl Sync for TaskStateSegmentCell {}
+
 lazy_static! {
     /// The `Task State Segment`.
     ///
-    /// We use it to store the pointer to the `Interrupt Stack Table`.
-    static ref TSS: TaskStateSegment = {
+    /// We use it to store the pointer to the `Interrupt Stack Table`, and the
+    /// kernel stack used when an interrupt occurs in user mode.
+    static ref TSS: TaskStateSegmentCell = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
             const STACK_SIZE: usize = 4096;
@@ -31,24 +43,48 @@ lazy_static! {
             // stack_end :
             stack_start + STACK_SIZE
         };
-        tss
+        TaskStateSegmentCell(UnsafeCell::new(tss))
     };
 }
 
+/// Set the stack used when an interrupt occurs in user mode.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that interrupts
+/// are disabled, and that the stack is valid until the next call.
+pub unsafe fn set_kernel_stack(stack_end: VirtAddr) {
+    (*TSS.0.get()).privilege_stack_table[0] = stack_end;
+}
+
 // ! ------------- gdt -------------
 
 lazy_static! {
     /// The `Global Descriptor Table`.
     ///
-    /// We use it to load the `Task State Segment`.
+    /// We use it to load the `Task State Segment` and the user segments.
+    ///
+    /// The order of the segments is the one expected by the `syscall` and
+    /// `sysret` instructions : kernel code, kernel data, user data, user code.
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let kernel_data_segment = Descriptor::UserSegment(
+            (DescriptorFlags::USER_SEGMENT | DescriptorFlags::PRESENT | DescriptorFlags::WRITABLE)
+                .bits(),
+        );
+
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let data_selector = gdt.add_entry(kernel_data_segment);
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*TSS.0.get() }));
         (
             gdt,
             Selectors {
                 code_selector,
+                data_selector,
+                user_data_selector,
+                user_code_selector,
                 tss_selector,
             },
         )
@@ -58,14 +94,33 @@ lazy_static! {
 /// A struct used to load the `Global Descriptor Table`.
 struct Selectors {
     code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
 
+/// Returns the selector of the kernel code segment.
+pub fn kernel_code_selector() -> SegmentSelector {
+    GDT.1.code_selector
+}
+
+/// Returns the selector of the user code segment.
+pub fn user_code_selector() -> SegmentSelector {
+    GDT.1.user_code_selector
+}
+
+/// Returns the selector of the user data segment.
+pub fn user_data_selector() -> SegmentSelector {
+    GDT.1.user_data_selector
+}
+
 /// Init the `Global Descriptor Table`.
 pub fn init() {
     GDT.0.load();
     unsafe {
         set_cs(GDT.1.code_selector);
+        load_ss(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
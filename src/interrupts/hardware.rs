@@ -2,7 +2,8 @@
 //!
 
 // internal crate
-use crate::{clear_screen, print};
+use super::trap::TrapFrame;
+use crate::{clear_screen, drivers::console, print};
 
 // external crates
 use lazy_static::lazy_static;
@@ -45,14 +46,20 @@ impl InterruptIndex {
 
 // TODO be able to "register" handler for interrupts (at compile-time?)
 
-/// Interrupt handler for the hardware timer interruption.
+/// Interrupt handler for the hardware timer interruption, called through
+/// `trap::nit_trap_timer`.
 ///
-/// By default, do nothing.
-pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+/// Counts a new tick, charged to the current process, runs the expired timers
+/// and preempts the current thread at the end of its time slice.
+pub(super) fn timer_interrupt(frame: &mut TrapFrame) {
+    // notify first, as the handler may switch to another thread
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8())
     }
+    crate::task::process::account_tick(frame.is_user());
+    crate::time::tick();
+    crate::task::scheduler::tick();
 }
 
 /// Interrupt handler for the hardware keyboard interruption.
@@ -75,7 +82,10 @@ pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut Inte
         if let Some(key) = keyboard.process_keyevent(key_event) {
             match key {
                 DecodedKey::Unicode('\u{8}') => clear_screen!(),
-                DecodedKey::Unicode(character) => print!("{}", character),
+                DecodedKey::Unicode(character) => {
+                    print!("{}", character);
+                    console::push_input(character);
+                }
                 DecodedKey::RawKey(key) => print!("{:?}", key),
             }
         }
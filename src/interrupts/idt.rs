@@ -2,9 +2,10 @@
 //!
 
 // internal crate
-use super::{exceptions, gdt, hardware};
+use super::{exceptions, gdt, hardware, trap};
 
 // external crates
+use core::mem;
 use lazy_static::lazy_static;
 use x86_64::structures::idt::InterruptDescriptorTable;
 
@@ -25,11 +26,37 @@ lazy_static! {
         }
         //breakpoint
         idt.breakpoint.set_handler_fn(exceptions::breakpoint_handler);
-        //page fault
-        idt.page_fault.set_handler_fn(exceptions::page_fault_handler);
-        // load interrupts handlers
-        //timer
-        idt[hardware::InterruptIndex::Timer.as_usize()].set_handler_fn(hardware::timer_interrupt_handler);
+        // the handlers building a `TrapFrame` are assembly stubs, which do not
+        // follow the `x86-interrupt` calling convention : only their address
+        // is stored in the table
+        unsafe {
+            //divide error
+            idt.divide_error
+                .set_handler_fn(mem::transmute(
+                    trap::nit_trap_divide_error as unsafe extern "C" fn(),
+                ));
+            //invalid opcode
+            idt.invalid_opcode
+                .set_handler_fn(mem::transmute(
+                    trap::nit_trap_invalid_opcode as unsafe extern "C" fn(),
+                ));
+            //general protection fault
+            idt.general_protection_fault
+                .set_handler_fn(mem::transmute(
+                    trap::nit_trap_general_protection as unsafe extern "C" fn(),
+                ));
+            //page fault
+            idt.page_fault
+                .set_handler_fn(mem::transmute(
+                    trap::nit_trap_page_fault as unsafe extern "C" fn(),
+                ));
+            // load interrupts handlers
+            //timer
+            idt[hardware::InterruptIndex::Timer.as_usize()]
+                .set_handler_fn(mem::transmute(
+                    trap::nit_trap_timer as unsafe extern "C" fn(),
+                ));
+        }
         //keyboard
         idt[hardware::InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(hardware::keyboard_interrupt_handler);
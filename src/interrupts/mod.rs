@@ -10,6 +10,7 @@ mod hardware;
 // public submodules
 pub mod gdt;
 pub mod idt;
+pub mod trap;
 
 // submodules exports
 pub use hardware::PICS;
@@ -0,0 +1,170 @@
+//! Defines the `TrapFrame`, the state of a thread saved when it enters the
+//! kernel through a system call, an exception or an interrupt.
+//!
+
+// internal crate
+use super::{
+    exceptions,
+    hardware::{self, InterruptIndex},
+};
+use crate::task::signal;
+
+/// The registers of an interrupted thread, saved on its kernel stack.
+///
+/// The last fields are laid out like the frame pushed by the processor on
+/// interrupts, so that the thread can be resumed with `iretq`.
+#[derive(Debug, Clone, Default)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// The interrupt vector, or `SYSCALL_VECTOR` for system calls.
+    pub vector: u64,
+    /// The error code pushed by the processor, or 0.
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// The pseudo interrupt vector of system calls.
+pub const SYSCALL_VECTOR: u64 = 0x80;
+
+impl TrapFrame {
+    /// Returns `true` if the thread was running in user mode.
+    pub fn is_user(&self) -> bool {
+        self.cs & 0x3 == 3
+    }
+}
+
+/// Vector of the divide error exception.
+pub const DIVIDE_ERROR_VECTOR: u64 = 0;
+/// Vector of the invalid opcode exception.
+pub const INVALID_OPCODE_VECTOR: u64 = 6;
+/// Vector of the general protection fault exception.
+pub const GENERAL_PROTECTION_VECTOR: u64 = 13;
+/// Vector of the page fault exception.
+pub const PAGE_FAULT_VECTOR: u64 = 14;
+
+// ! ------------- entry points -------------
+
+// The exceptions and interrupts which may come from user mode build a
+// `TrapFrame`, so that signals can be delivered to the interrupted thread
+// before it resumes. The processor pushes the error code of some exceptions
+// only : the other stubs push 0 instead.
+global_asm!(
+    r#"
+.intel_syntax noprefix
+
+.global nit_trap_divide_error
+nit_trap_divide_error:
+    push 0
+    push 0
+    jmp nit_trap_common
+
+.global nit_trap_invalid_opcode
+nit_trap_invalid_opcode:
+    push 0
+    push 6
+    jmp nit_trap_common
+
+.global nit_trap_general_protection
+nit_trap_general_protection:
+    push 13
+    jmp nit_trap_common
+
+.global nit_trap_page_fault
+nit_trap_page_fault:
+    push 14
+    jmp nit_trap_common
+
+.global nit_trap_timer
+nit_trap_timer:
+    push 0
+    push 32
+    jmp nit_trap_common
+
+nit_trap_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    cld
+    call nit_trap_handler
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    add rsp, 16
+    iretq
+
+.att_syntax prefix
+"#
+);
+
+extern "C" {
+    pub(super) fn nit_trap_divide_error();
+    pub(super) fn nit_trap_invalid_opcode();
+    pub(super) fn nit_trap_general_protection();
+    pub(super) fn nit_trap_page_fault();
+    pub(super) fn nit_trap_timer();
+}
+
+/// Called by the entry points with interrupts disabled.
+#[no_mangle]
+extern "C" fn nit_trap_handler(frame: &mut TrapFrame) {
+    match frame.vector {
+        DIVIDE_ERROR_VECTOR => exceptions::divide_error(frame),
+        INVALID_OPCODE_VECTOR => exceptions::invalid_opcode(frame),
+        GENERAL_PROTECTION_VECTOR => exceptions::general_protection(frame),
+        PAGE_FAULT_VECTOR => exceptions::page_fault(frame),
+        vector if vector == u64::from(InterruptIndex::Timer.as_u8()) => {
+            hardware::timer_interrupt(frame)
+        }
+        vector => panic!("unexpected trap vector {}\n{:#?}", vector, frame),
+    }
+
+    if frame.is_user() {
+        signal::deliver(frame);
+    }
+}
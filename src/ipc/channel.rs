@@ -0,0 +1,268 @@
+//! This module contains channels : bidirectional queues of messages between
+//! two endpoints.
+//!
+//! A message carries bytes and handles to kernel objects, which are moved from
+//! the handle table of the sender to the one of the receiver. When an endpoint
+//! is dropped, the other one is notified : pending and future operations fail
+//! with `ChannelError::PeerClosed` once the queue is empty.
+//!
+
+// internal crate
+use crate::task::{handle::KernelObject, wait_queue::WaitQueue};
+
+// external crates
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::mem;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Maximum number of bytes in a message.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+/// Maximum number of handles in a message.
+pub const MAX_MESSAGE_HANDLES: usize = 16;
+/// Maximum number of messages queued in each direction.
+pub const MAX_QUEUED_MESSAGES: usize = 64;
+
+/// An error occuring while using a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelError {
+    /// The other endpoint was closed.
+    PeerClosed,
+    /// The operation would block, in non-blocking mode.
+    WouldBlock,
+    /// The message has too many bytes or handles.
+    MessageTooLarge,
+    /// The next message does not fit in the given capacities : it stays
+    /// queued.
+    BufferTooSmall,
+    /// A blocking operation was interrupted by a signal.
+    Interrupted,
+}
+
+/// A message sent through a channel.
+#[derive(Default)]
+pub struct Message {
+    pub data: Vec<u8>,
+    pub handles: Vec<KernelObject>,
+}
+
+/// The messages and the state of both endpoints.
+struct State {
+    /// The messages sent to each endpoint.
+    queues: [VecDeque<Message>; 2],
+    /// Whether each endpoint has been closed.
+    closed: [bool; 2],
+}
+
+/// The state shared by both endpoints.
+struct Shared {
+    /// It must only be locked with interrupts disabled.
+    state: Mutex<State>,
+    /// The threads waiting for a message to each endpoint.
+    readable: [WaitQueue; 2],
+    /// The threads waiting for space in the queue of each endpoint.
+    writable: [WaitQueue; 2],
+}
+
+/// An endpoint of a channel.
+pub struct Endpoint {
+    shared: Arc<Shared>,
+    side: usize,
+}
+
+/// Create a channel, returning its two endpoints.
+pub fn create() -> (Arc<Endpoint>, Arc<Endpoint>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queues: [VecDeque::new(), VecDeque::new()],
+            closed: [false, false],
+        }),
+        readable: [WaitQueue::new(), WaitQueue::new()],
+        writable: [WaitQueue::new(), WaitQueue::new()],
+    });
+    let first = Endpoint {
+        shared: shared.clone(),
+        side: 0,
+    };
+    let second = Endpoint { shared, side: 1 };
+    (Arc::new(first), Arc::new(second))
+}
+
+impl Endpoint {
+    /// Returns the side of the other endpoint.
+    fn peer(&self) -> usize {
+        1 - self.side
+    }
+
+    /// Returns `true` if both endpoints belong to the same channel.
+    pub fn same_channel(&self, other: &Endpoint) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    /// Send `message` to the other endpoint.
+    ///
+    /// If the queue is full, blocks until there is space if `blocking` is set,
+    /// unless a signal arrives. On failure, the message is returned with the
+    /// error.
+    pub fn send(&self, message: Message, blocking: bool) -> Result<(), (ChannelError, Message)> {
+        if message.data.len() > MAX_MESSAGE_SIZE || message.handles.len() > MAX_MESSAGE_HANDLES {
+            return Err((ChannelError::MessageTooLarge, message));
+        }
+
+        let peer = self.peer();
+        let mut message = Some(message);
+        let mut error = None;
+        let waited = self.shared.writable[peer].wait_until_interruptible(|| {
+            let mut state = self.shared.state.lock();
+            if state.closed[peer] {
+                error = Some(ChannelError::PeerClosed);
+            } else if state.queues[peer].len() < MAX_QUEUED_MESSAGES {
+                state.queues[peer].push_back(message.take().unwrap());
+            } else if !blocking {
+                error = Some(ChannelError::WouldBlock);
+            } else {
+                return false;
+            }
+            true
+        });
+        if waited.is_err() {
+            error = Some(ChannelError::Interrupted);
+        }
+
+        match error {
+            Some(error) => Err((error, message.unwrap())),
+            None => {
+                self.shared.readable[peer].wake_one();
+                Ok(())
+            }
+        }
+    }
+
+    /// Receive the next message sent to this endpoint, having at most
+    /// `max_data` bytes and `max_handles` handles.
+    ///
+    /// If no message is queued, blocks until one arrives if `blocking` is set,
+    /// unless a signal arrives.
+    pub fn receive(
+        &self,
+        blocking: bool,
+        max_data: usize,
+        max_handles: usize,
+    ) -> Result<Message, ChannelError> {
+        let (side, peer) = (self.side, self.peer());
+        let mut result = None;
+        let waited = self.shared.readable[side].wait_until_interruptible(|| {
+            let mut state = self.shared.state.lock();
+            let front = state.queues[side]
+                .front()
+                .map(|message| (message.data.len(), message.handles.len()));
+            result = match front {
+                Some((data, handles)) if data > max_data || handles > max_handles => {
+                    Some(Err(ChannelError::BufferTooSmall))
+                }
+                Some(_) => state.queues[side].pop_front().map(Ok),
+                None if state.closed[peer] => Some(Err(ChannelError::PeerClosed)),
+                None if !blocking => Some(Err(ChannelError::WouldBlock)),
+                None => None,
+            };
+            result.is_some()
+        });
+        if waited.is_err() {
+            return Err(ChannelError::Interrupted);
+        }
+
+        let result = result.unwrap();
+        if result.is_ok() {
+            self.shared.writable[side].wake_one();
+        }
+        result
+    }
+
+    /// Put `message`, received from this endpoint but not consumed, back at
+    /// the front of its queue.
+    ///
+    /// The queue may then hold one more message than `MAX_QUEUED_MESSAGES`.
+    pub fn requeue(&self, message: Message) {
+        interrupts::without_interrupts(|| {
+            self.shared.state.lock().queues[self.side].push_front(message)
+        });
+        self.shared.readable[self.side].wake_one();
+    }
+
+    /// Returns the number of messages waiting to be received.
+    pub fn pending(&self) -> usize {
+        interrupts::without_interrupts(|| self.shared.state.lock().queues[self.side].len())
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let peer = self.peer();
+        // the messages sent to this endpoint will never be received
+        let messages = interrupts::without_interrupts(|| {
+            let mut state = self.shared.state.lock();
+            state.closed[self.side] = true;
+            mem::take(&mut state.queues[self.side])
+        });
+        drop(messages);
+        self.shared.readable[peer].wake_all();
+        self.shared.writable[peer].wake_all();
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_channel_messages() {
+    serial_print!("test_channel_messages... ");
+    let (first, second) = create();
+    let message = Message {
+        data: alloc::vec![1, 2, 3],
+        handles: Vec::new(),
+    };
+    assert!(first.send(message, false).is_ok());
+    assert_eq!(second.pending(), 1);
+    assert_eq!(
+        second.receive(false, 2, 0).err(),
+        Some(ChannelError::BufferTooSmall)
+    );
+    let received = second.receive(false, MAX_MESSAGE_SIZE, 0).ok().unwrap();
+    second.requeue(received);
+    assert_eq!(second.pending(), 1);
+    let received = second.receive(false, MAX_MESSAGE_SIZE, 0).ok().unwrap();
+    assert_eq!(received.data, [1, 2, 3]);
+    assert_eq!(
+        second.receive(false, MAX_MESSAGE_SIZE, 0).err(),
+        Some(ChannelError::WouldBlock)
+    );
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_channel_peer_closed() {
+    serial_print!("test_channel_peer_closed... ");
+    let (first, second) = create();
+    let transferred = create().0;
+    let message = Message {
+        data: Vec::new(),
+        handles: alloc::vec![KernelObject::Channel(transferred)],
+    };
+    assert!(second.send(message, false).is_ok());
+    drop(second);
+    // the message sent before the peer closed is still received
+    let received = first.receive(true, 0, MAX_MESSAGE_HANDLES).ok().unwrap();
+    assert_eq!(received.handles.len(), 1);
+    assert_eq!(
+        first.receive(true, 0, 0).err(),
+        Some(ChannelError::PeerClosed)
+    );
+    assert!(matches!(
+        first.send(Message::default(), true),
+        Err((ChannelError::PeerClosed, _))
+    ));
+    serial_println!("[ok]");
+}
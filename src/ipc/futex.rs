@@ -0,0 +1,174 @@
+//! This module contains futexes : queues of threads waiting on a `u32` of
+//! user memory, on top of which user programs build their locks.
+//!
+//! A thread waits only if the value still is the expected one, checked while
+//! the futex table is locked : a wake following the change of the value
+//! cannot be missed. Futexes are keyed by the physical address of the value,
+//! so that the processes mapping the same shared memory use the same futex.
+//!
+
+// internal crate
+use crate::{
+    memory::user::{self, Access},
+    task::{
+        scheduler, signal,
+        thread::{ThreadId, ThreadState},
+    },
+    time,
+};
+
+// external crates
+use alloc::{collections::BTreeMap, vec::Vec};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::{instructions::interrupts, PhysAddr};
+
+/// An error occuring while using a futex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    /// The address is not aligned, or not mapped in the current process.
+    BadAddress,
+    /// The value was not the expected one.
+    WouldBlock,
+    /// The deadline was reached before a wake.
+    TimedOut,
+    /// The wait was interrupted by a signal.
+    Interrupted,
+}
+
+impl From<user::BadUserAddress> for FutexError {
+    fn from(_: user::BadUserAddress) -> Self {
+        FutexError::BadAddress
+    }
+}
+
+lazy_static! {
+    /// The waiting threads of each futex, in arrival order.
+    ///
+    /// It must only be locked with interrupts disabled.
+    static ref FUTEXES: Mutex<BTreeMap<PhysAddr, Vec<ThreadId>>> = Mutex::new(BTreeMap::new());
+}
+
+/// Returns the key of the futex at the user address `addr`.
+fn key(addr: u64) -> Result<PhysAddr, FutexError> {
+    if addr % 4 != 0 {
+        return Err(FutexError::BadAddress);
+    }
+    Ok(user::translate(addr, Access::Read)?)
+}
+
+/// Remove `thread` from the waiters of `key`.
+///
+/// Returns `false` if it was not waiting, because a wake removed it.
+fn remove_waiter(key: PhysAddr, thread: ThreadId) -> bool {
+    let mut futexes = FUTEXES.lock();
+    let waiters = match futexes.get_mut(&key) {
+        Some(waiters) => waiters,
+        None => return false,
+    };
+    let len = waiters.len();
+    waiters.retain(|&waiter| waiter != thread);
+    let removed = waiters.len() != len;
+    if waiters.is_empty() {
+        futexes.remove(&key);
+    }
+    removed
+}
+
+/// Block the current thread on the futex at the user address `addr`, if it
+/// holds `expected`, until it is woken or the tick count reaches `deadline`.
+pub fn wait(addr: u64, expected: u32, deadline: Option<u64>) -> Result<(), FutexError> {
+    let key = key(addr)?;
+    let (thread, _) = scheduler::current();
+
+    // no wake nor timer can run between the check of the value and the block
+    interrupts::without_interrupts(|| {
+        {
+            let mut futexes = FUTEXES.lock();
+            if user::read_u32(addr)? != expected {
+                return Err(FutexError::WouldBlock);
+            }
+            futexes.entry(key).or_insert_with(Vec::new).push(thread);
+        }
+
+        let timer = deadline.map(|deadline| {
+            time::schedule_at(deadline, move || {
+                scheduler::unblock(thread);
+            })
+        });
+        let result = loop {
+            // the thread is not a waiter anymore once woken
+            let waiting = FUTEXES
+                .lock()
+                .get(&key)
+                .map_or(false, |waiters| waiters.contains(&thread));
+            let error = if !waiting {
+                break Ok(());
+            } else if deadline.map_or(false, |deadline| time::ticks() >= deadline) {
+                FutexError::TimedOut
+            } else if signal::has_pending() {
+                FutexError::Interrupted
+            } else {
+                scheduler::block_current();
+                continue;
+            };
+            break if remove_waiter(key, thread) {
+                Err(error)
+            } else {
+                Ok(())
+            };
+        };
+        if let Some(timer) = timer {
+            time::cancel(timer);
+        }
+        result
+    })
+}
+
+/// Wake at most `count` threads waiting on the futex at the user address
+/// `addr`.
+///
+/// Returns the number of woken threads.
+pub fn wake(addr: u64, count: usize) -> Result<usize, FutexError> {
+    let key = key(addr)?;
+    interrupts::without_interrupts(|| {
+        let mut futexes = FUTEXES.lock();
+        let waiters = match futexes.get_mut(&key) {
+            Some(waiters) => waiters,
+            None => return Ok(0),
+        };
+
+        let mut woken = 0;
+        while woken < count && !waiters.is_empty() {
+            let thread = waiters.remove(0);
+            // the threads of killed processes may still be queued
+            match scheduler::state(thread) {
+                None | Some(ThreadState::Exited) => continue,
+                Some(_) => {
+                    scheduler::unblock(thread);
+                    woken += 1;
+                }
+            }
+        }
+        if waiters.is_empty() {
+            futexes.remove(&key);
+        }
+        Ok(woken)
+    })
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_futex_kernel_address() {
+    serial_print!("test_futex_kernel_address... ");
+    let value = 0u32;
+    let addr = &value as *const u32 as u64;
+    assert_eq!(wait(addr, 0, None), Err(FutexError::BadAddress));
+    assert_eq!(wake(addr, 1), Err(FutexError::BadAddress));
+    serial_println!("[ok]");
+}
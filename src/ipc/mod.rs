@@ -0,0 +1,11 @@
+//! This module contains the inter-process communication primitives.
+//!
+//! User programs access channels through handles, see `task::handle`, and
+//! futexes through the addresses of their values. Pipes are files, named by
+//! file descriptors, see `task::file`.
+//!
+
+// public submodules
+pub mod channel;
+pub mod futex;
+pub mod pipe;
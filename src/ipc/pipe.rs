@@ -0,0 +1,190 @@
+//! This module contains anonymous pipes : byte streams from a writing end to a
+//! reading end.
+//!
+//! Reads block until some bytes are written, and return 0 once the buffer is
+//! empty and every writing end is closed. Writes block until there is space,
+//! and fail with `FileError::BrokenPipe` once the reading end is closed,
+//! sending `SIGPIPE` to the writer. Writes of at most `PIPE_BUF` bytes are
+//! atomic : they are never interleaved with other writes.
+//!
+
+// internal crate
+use crate::task::{
+    file::{File, FileError},
+    process::{self, KERNEL_PID},
+    signal::{self, SIGPIPE},
+    wait_queue::WaitQueue,
+};
+
+// external crates
+use alloc::{collections::VecDeque, sync::Arc};
+use core::mem;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Number of bytes a pipe can hold.
+pub const PIPE_CAPACITY: usize = 4096;
+/// Maximum number of bytes of an atomic write.
+pub const PIPE_BUF: usize = 512;
+
+/// The bytes and the state of both ends.
+struct State {
+    /// The bytes written but not read yet.
+    buffer: VecDeque<u8>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+/// The state shared by both ends.
+struct Shared {
+    /// It must only be locked with interrupts disabled.
+    state: Mutex<State>,
+    /// The threads waiting for bytes to read.
+    readable: WaitQueue,
+    /// The threads waiting for space to write.
+    writable: WaitQueue,
+}
+
+/// The reading end of a pipe.
+pub struct PipeReader {
+    shared: Arc<Shared>,
+}
+
+/// The writing end of a pipe.
+pub struct PipeWriter {
+    shared: Arc<Shared>,
+}
+
+/// Create a pipe, returning its reading and writing ends.
+pub fn create() -> (Arc<PipeReader>, Arc<PipeWriter>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buffer: VecDeque::new(),
+            reader_closed: false,
+            writer_closed: false,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    let reader = PipeReader {
+        shared: shared.clone(),
+    };
+    let writer = PipeWriter { shared };
+    (Arc::new(reader), Arc::new(writer))
+}
+
+impl File for PipeReader {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, FileError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let mut read = 0;
+        self.shared.readable.wait_until_interruptible(|| {
+            let mut state = self.shared.state.lock();
+            while read < buffer.len() {
+                match state.buffer.pop_front() {
+                    Some(byte) => buffer[read] = byte,
+                    None => break,
+                }
+                read += 1;
+            }
+            read > 0 || state.writer_closed
+        })?;
+        if read > 0 {
+            self.shared.writable.wake_all();
+        }
+        Ok(read)
+    }
+
+    fn write(&self, _data: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::NotWritable)
+    }
+}
+
+impl File for PipeWriter {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, FileError> {
+        Err(FileError::NotReadable)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize, FileError> {
+        let mut written = 0;
+        let mut broken = false;
+        let waited = self.shared.writable.wait_until_interruptible(|| {
+            let mut state = self.shared.state.lock();
+            if state.reader_closed {
+                broken = true;
+                return true;
+            }
+            let space = PIPE_CAPACITY - state.buffer.len();
+            // small writes wait until they fit entirely
+            if data.len() <= PIPE_BUF && space < data.len() {
+                return false;
+            }
+            let count = space.min(data.len() - written);
+            state.buffer.extend(&data[written..written + count]);
+            written += count;
+            drop(state);
+            // the readers must run to make space for the rest
+            if count > 0 {
+                self.shared.readable.wake_all();
+            }
+            written == data.len()
+        });
+
+        if broken && written == 0 {
+            let pid = process::current();
+            if pid != KERNEL_PID {
+                // the process cannot be gone while one of its threads runs
+                let _ = signal::send(pid, SIGPIPE);
+            }
+            return Err(FileError::BrokenPipe);
+        }
+        match waited {
+            // the bytes written before the signal or the close are not lost
+            _ if written > 0 => Ok(written),
+            Err(interrupted) => Err(interrupted.into()),
+            Ok(()) => Ok(0),
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let buffer = interrupts::without_interrupts(|| {
+            let mut state = self.shared.state.lock();
+            state.reader_closed = true;
+            mem::take(&mut state.buffer)
+        });
+        drop(buffer);
+        self.shared.writable.wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| self.shared.state.lock().writer_closed = true);
+        self.shared.readable.wake_all();
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_pipe_end_of_file() {
+    serial_print!("test_pipe_end_of_file... ");
+    let (reader, writer) = create();
+    assert_eq!(writer.write(b"hello"), Ok(5));
+    assert_eq!(reader.write(b"x"), Err(FileError::NotWritable));
+    assert_eq!(writer.read(&mut [0; 1]), Err(FileError::NotReadable));
+    drop(writer);
+    // the bytes written before the writer closed are still read
+    let mut buffer = [0; 8];
+    assert_eq!(reader.read(&mut buffer), Ok(5));
+    assert_eq!(&buffer[..5], b"hello");
+    assert_eq!(reader.read(&mut buffer), Ok(0));
+    serial_println!("[ok]");
+}
@@ -71,6 +71,8 @@ You can start by reading [TODO](./TODO.md) and [ROADMAP](./ROADMAP.md). You can
     custom_test_frameworks,
     // exceptions
     abi_x86_interrupt,
+    // context switch
+    global_asm,
     // allocators
     const_fn,
     alloc_layout_extra,
@@ -85,4 +87,8 @@ extern crate alloc;
 pub mod architecture;
 pub mod drivers;
 pub mod interrupts;
+pub mod ipc;
 pub mod memory;
+pub mod syscall;
+pub mod task;
+pub mod time;
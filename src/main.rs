@@ -24,7 +24,10 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     phase!(architecture::init(); "kernel init");
 
     // ! ------------- heap -------------
-    let (_, _) = phase!(memory::init(boot_info); "heap init");
+    phase!(memory::init(boot_info); "heap init");
+
+    // ! ------------- tasks -------------
+    phase!(task::init(); "tasking init");
 
     // ! ------------- main -------------
 
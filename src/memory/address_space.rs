@@ -0,0 +1,665 @@
+//! This module contains the `AddressSpace` of a process.
+//!
+//! Every address space owns a level 4 page table : the entries covering the
+//! user space belong to it, while the other ones are copied from the kernel
+//! page table, so that the kernel half is shared between every address space.
+//!
+//! The pages of a region need not be mapped when it is reserved : the missing
+//! ones are mapped by `handle_fault` on their first access.
+//!
+
+// internal crate
+use super::{kernel_level_4_frame, layout, phys_to_virt, shared::SharedMemory};
+
+// external crates
+use alloc::{sync::Arc, vec::Vec};
+use core::{mem, ptr};
+use x86_64::{
+    instructions::tlb,
+    registers::control::{Cr3, Cr3Flags},
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            mapper::MapToError, FrameAllocator, FrameDeallocator, Page, PageTable, PageTableEntry,
+            PageTableFlags, PhysFrame, Size4KiB,
+        },
+    },
+    VirtAddr,
+};
+
+/// The memory mapped by a region.
+#[derive(Debug, Clone)]
+pub enum Backing {
+    /// Frames owned by the address space, freed when unmapped.
+    Anonymous,
+    /// The frames of a shared memory object, starting at `offset` bytes.
+    Shared {
+        memory: Arc<SharedMemory>,
+        offset: u64,
+    },
+}
+
+/// A range of pages reserved in the user space of an address space.
+#[derive(Debug, Clone)]
+pub struct Region {
+    /// Start of the region, page aligned.
+    pub start: VirtAddr,
+    /// End of the region, page aligned and excluded.
+    pub end: VirtAddr,
+    /// Flags of the pages of the region.
+    pub flags: PageTableFlags,
+    /// The memory mapped by the region.
+    pub backing: Backing,
+}
+
+impl Region {
+    /// Returns `true` if the region contains the given address.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns `true` if the region overlaps the range `start..end`.
+    pub fn overlaps(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+/// An error returned when accessing an unmapped user address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotMapped(pub VirtAddr);
+
+/// An error returned when a page fault is not resolved by mapping a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// The address lies in no reserved region.
+    NotReserved,
+    /// The region does not allow the access.
+    Denied,
+    /// No frame could be allocated to back the page.
+    OutOfMemory,
+    /// The address space could not be accessed without waiting for a lock.
+    Unavailable,
+}
+
+/// The address space of a process.
+///
+/// Dropping it frees every frame mapped in its user space, as well as the page
+/// tables themselves.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    /// The reserved regions, sorted by address.
+    regions: Vec<Region>,
+    /// Number of user pages mapped.
+    resident_pages: u64,
+    /// Highest number of user pages mapped at once.
+    peak_resident_pages: u64,
+}
+
+impl AddressSpace {
+    /// Create a new address space, with an empty user space.
+    pub fn new() -> Result<Self, MapToError<Size4KiB>> {
+        let level_4_frame = super::with_frame_allocator(|frame_allocator| {
+            frame_allocator.allocate_frame()
+        })
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let table = unsafe { table_mut(level_4_frame) };
+        let kernel_table = unsafe { table_mut(kernel_level_4_frame()) };
+        for (index, entry) in table.iter_mut().enumerate() {
+            if layout::USER_LEVEL_4_ENTRIES.contains(&index) {
+                entry.set_unused();
+            } else {
+                let kernel_entry = &kernel_table[index];
+                entry.set_addr(kernel_entry.addr(), kernel_entry.flags());
+            }
+        }
+
+        Ok(AddressSpace {
+            level_4_frame,
+            regions: Vec::new(),
+            resident_pages: 0,
+            peak_resident_pages: 0,
+        })
+    }
+
+    /// Returns the frame of the level 4 page table.
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns the number of user pages mapped.
+    pub fn resident_pages(&self) -> u64 {
+        self.resident_pages
+    }
+
+    /// Returns the highest number of user pages mapped at once.
+    pub fn peak_resident_pages(&self) -> u64 {
+        self.peak_resident_pages
+    }
+
+    /// Returns the number of bytes of the reserved regions.
+    pub fn reserved_size(&self) -> u64 {
+        self.regions
+            .iter()
+            .map(|region| region.end - region.start)
+            .sum()
+    }
+
+    /// Returns `true` if this address space is the one currently loaded.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Load this address space in `CR3`.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the code
+    /// and the stack in use are mapped in the kernel half.
+    pub unsafe fn activate(&self) {
+        Cr3::write(self.level_4_frame, Cr3Flags::empty());
+    }
+
+    /// Map the user `page` to `frame` with the given `flags`, creating the
+    /// missing page tables.
+    ///
+    /// The intermediate tables are created user accessible : the `Mapper`
+    /// implementations of the `x86_64` crate only create kernel ones.
+    pub fn map(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert!(
+            layout::is_user_range(page.start_address().as_u64(), Page::<Size4KiB>::SIZE),
+            "page is not in the user space"
+        );
+
+        let table_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let mut table = unsafe { table_mut(self.level_4_frame) };
+        for &index in &[page.p4_index(), page.p3_index(), page.p2_index()] {
+            let entry = &mut table[index];
+            if entry.is_unused() {
+                let frame = frame_allocator
+                    .allocate_frame()
+                    .ok_or(MapToError::FrameAllocationFailed)?;
+                unsafe { table_mut(frame).zero() };
+                entry.set_frame(frame, table_flags);
+            }
+            let next = entry
+                .frame()
+                .map_err(|_| MapToError::ParentEntryHugePage)?;
+            table = unsafe { table_mut(next) };
+        }
+
+        let entry = &mut table[page.p1_index()];
+        if let Ok(mapped) = entry.frame() {
+            return Err(MapToError::PageAlreadyMapped(mapped));
+        }
+        entry.set_frame(frame, flags);
+        self.flush(page);
+        self.resident_pages += 1;
+        self.peak_resident_pages = self.peak_resident_pages.max(self.resident_pages);
+        Ok(())
+    }
+
+    /// Map the user `page` to a newly allocated frame filled with zeros.
+    ///
+    /// Returns the allocated frame. On failure, the frame is given back to
+    /// `frame_allocator`.
+    pub fn map_zeroed<A>(
+        &mut self,
+        page: Page,
+        flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<PhysFrame, MapToError<Size4KiB>>
+    where
+        A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+    {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe {
+            let frame_ptr: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+            ptr::write_bytes(frame_ptr, 0, Page::<Size4KiB>::SIZE as usize);
+        }
+        if let Err(error) = self.map(page, frame, flags, frame_allocator) {
+            frame_allocator.deallocate_frame(frame);
+            return Err(error);
+        }
+        Ok(frame)
+    }
+
+    /// Resolve a page fault at `addr`, caused by the access described by
+    /// `error_code`, by mapping its page if the region containing it allows
+    /// the access.
+    ///
+    /// Anonymous pages are backed by a zeroed frame, shared ones by the frame
+    /// of their object. If the page is already mapped, only its stale TLB
+    /// entry is flushed.
+    pub fn handle_fault<A>(
+        &mut self,
+        addr: VirtAddr,
+        error_code: PageFaultErrorCode,
+        frame_allocator: &mut A,
+    ) -> Result<(), FaultError>
+    where
+        A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+    {
+        let region = self.region(addr).ok_or(FaultError::NotReserved)?;
+        let flags = region.flags;
+        let denied = flags.is_empty()
+            || error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+            || (error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+                && !flags.contains(PageTableFlags::WRITABLE))
+            || (error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+                && flags.contains(PageTableFlags::NO_EXECUTE));
+        if denied {
+            return Err(FaultError::Denied);
+        }
+
+        let page = Page::containing_address(addr);
+        let shared_frame = match &region.backing {
+            Backing::Anonymous => None,
+            Backing::Shared { memory, offset } => {
+                let index =
+                    (offset + (page.start_address() - region.start)) / Page::<Size4KiB>::SIZE;
+                Some(memory.frames()[index as usize])
+            }
+        };
+        if self.translate(page).is_some() {
+            self.flush(page);
+            return Ok(());
+        }
+        match shared_frame {
+            Some(frame) => self.map(page, frame, flags, frame_allocator),
+            None => self.map_zeroed(page, flags, frame_allocator).map(|_| ()),
+        }
+        .map_err(|_| FaultError::OutOfMemory)
+    }
+
+    /// Change the flags of the given mapped user page.
+    pub fn update_flags(&mut self, page: Page, flags: PageTableFlags) -> Result<(), NotMapped> {
+        let entry = unsafe { self.leaf_entry(page) }
+            .filter(|entry| !entry.is_unused())
+            .ok_or(NotMapped(page.start_address()))?;
+        entry.set_flags(flags);
+        self.flush(page);
+        Ok(())
+    }
+
+    /// Unmap the given user page.
+    ///
+    /// Returns the frame it was mapped to, which is not deallocated.
+    pub fn unmap(&mut self, page: Page) -> Option<PhysFrame> {
+        let entry = unsafe { self.leaf_entry(page)? };
+        let frame = entry.frame().ok()?;
+        entry.set_unused();
+        self.flush(page);
+        self.resident_pages -= 1;
+        Some(frame)
+    }
+
+    /// Returns the frame `page` is mapped to, with the flags of the mapping.
+    pub fn translate(&self, page: Page) -> Option<(PhysFrame, PageTableFlags)> {
+        let entry = unsafe { self.leaf_entry(page)? };
+        let frame = entry.frame().ok()?;
+        Some((frame, entry.flags()))
+    }
+
+    /// Copy `data` to the user memory starting at `addr`.
+    ///
+    /// The copy goes through the physical memory mapping : it works whether the
+    /// address space is loaded or not, and ignores the write protection of the
+    /// pages.
+    pub fn write(&mut self, addr: VirtAddr, data: &[u8]) -> Result<(), NotMapped> {
+        let mut written = 0;
+        while written < data.len() {
+            let addr = addr + written;
+            let page = Page::<Size4KiB>::containing_address(addr);
+            let (frame, _) = self.translate(page).ok_or(NotMapped(addr))?;
+
+            let offset = (addr - page.start_address()) as usize;
+            let len = (Page::<Size4KiB>::SIZE as usize - offset).min(data.len() - written);
+            unsafe {
+                let dst: *mut u8 = phys_to_virt(frame.start_address() + offset).as_mut_ptr();
+                ptr::copy_nonoverlapping(data[written..].as_ptr(), dst, len);
+            }
+            written += len;
+        }
+        Ok(())
+    }
+
+    /// Returns the reserved regions, sorted by address.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Returns the region containing the given address.
+    pub fn region(&self, addr: VirtAddr) -> Option<&Region> {
+        self.regions.iter().find(|region| region.contains(addr))
+    }
+
+    /// Reserve the given region.
+    ///
+    /// Returns the region overlapping it, if any, in which case nothing is
+    /// reserved.
+    pub fn add_region(&mut self, region: Region) -> Result<(), Region> {
+        if let Some(other) = self
+            .regions
+            .iter()
+            .find(|other| other.overlaps(region.start, region.end))
+        {
+            return Err(other.clone());
+        }
+        let index = self
+            .regions
+            .iter()
+            .position(|other| other.start > region.start)
+            .unwrap_or_else(|| self.regions.len());
+        self.regions.insert(index, region);
+        Ok(())
+    }
+
+    /// Returns the lowest free range of `len` bytes in `start..end`, `len`
+    /// being a multiple of the page size.
+    pub fn find_free_range(&self, start: VirtAddr, end: VirtAddr, len: u64) -> Option<VirtAddr> {
+        let mut candidate = start;
+        for region in &self.regions {
+            if region.end <= candidate {
+                continue;
+            }
+            if region.start >= candidate + len {
+                break;
+            }
+            candidate = region.end;
+        }
+        match candidate.as_u64().checked_add(len) {
+            Some(candidate_end) if candidate_end <= end.as_u64() => Some(candidate),
+            _ => None,
+        }
+    }
+
+    /// Map the whole shared `memory` at `start`, and reserve its region.
+    ///
+    /// The range must not overlap any reserved region.
+    pub fn map_shared(
+        &mut self,
+        start: VirtAddr,
+        memory: &Arc<SharedMemory>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let region = Region {
+            start,
+            end: start + memory.size(),
+            flags,
+            backing: Backing::Shared {
+                memory: memory.clone(),
+                offset: 0,
+            },
+        };
+        self.add_region(region)
+            .expect("shared memory mapped over a reserved region");
+
+        let first = Page::containing_address(start);
+        let mapped = super::with_frame_allocator(|frame_allocator| {
+            for (index, &frame) in memory.frames().iter().enumerate() {
+                self.map(first + index as u64, frame, flags, frame_allocator)?;
+            }
+            Ok(())
+        });
+        if mapped.is_err() {
+            self.remove_range(start, start + memory.size());
+        }
+        mapped
+    }
+
+    /// Unmap the pages of `start..end`, and remove the range from the reserved
+    /// regions, splitting them if needed.
+    ///
+    /// The frames owned by the address space are freed, the shared ones are
+    /// freed with the last reference to their object.
+    pub fn remove_range(&mut self, start: VirtAddr, end: VirtAddr) {
+        let mut regions = Vec::with_capacity(self.regions.len() + 1);
+        let mut removed = Vec::new();
+        for region in self.regions.drain(..) {
+            if !region.overlaps(start, end) {
+                regions.push(region);
+                continue;
+            }
+            if region.start < start {
+                regions.push(Region {
+                    end: start,
+                    ..region.clone()
+                });
+            }
+            if region.end > end {
+                let backing = match &region.backing {
+                    Backing::Anonymous => Backing::Anonymous,
+                    Backing::Shared { memory, offset } => Backing::Shared {
+                        memory: memory.clone(),
+                        offset: offset + (end - region.start),
+                    },
+                };
+                regions.push(Region {
+                    start: end,
+                    backing,
+                    ..region.clone()
+                });
+            }
+            removed.push(region);
+        }
+        self.regions = regions;
+
+        let first = Page::containing_address(start);
+        let last = Page::containing_address(end);
+        super::with_frame_allocator(|frame_allocator| {
+            for page in Page::range(first, last) {
+                let is_shared = removed.iter().any(|region| {
+                    region.contains(page.start_address())
+                        && matches!(region.backing, Backing::Shared { .. })
+                });
+                if let Some(frame) = self.unmap(page) {
+                    if !is_shared {
+                        frame_allocator.deallocate_frame(frame);
+                    }
+                }
+            }
+        });
+        // may free the frames of shared objects, once the allocator is unlocked
+        drop(removed);
+    }
+
+    /// Returns the level 1 entry of `page`, if its page tables exist.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the returned reference must not outlive
+    /// the borrow of the address space, nor be aliased.
+    unsafe fn leaf_entry<'a>(&self, page: Page) -> Option<&'a mut PageTableEntry> {
+        let mut table = table_mut(self.level_4_frame);
+        for &index in &[page.p4_index(), page.p3_index(), page.p2_index()] {
+            let next = table[index].frame().ok()?;
+            table = table_mut(next);
+        }
+        Some(&mut table[page.p1_index()])
+    }
+
+    /// Flush the given page from the TLB, if this address space is loaded.
+    fn flush(&self, page: Page) {
+        if self.is_active() {
+            tlb::flush(page.start_address());
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            unsafe { Cr3::write(kernel_level_4_frame(), Cr3Flags::empty()) };
+        }
+
+        // the shared frames are only freed with their object
+        let regions = mem::take(&mut self.regions);
+        for region in &regions {
+            if let Backing::Shared { .. } = region.backing {
+                let first = Page::containing_address(region.start);
+                let last = Page::containing_address(region.end);
+                for page in Page::range(first, last) {
+                    self.unmap(page);
+                }
+            }
+        }
+
+        super::with_frame_allocator(|frame_allocator| {
+            let table = unsafe { table_mut(self.level_4_frame) };
+            for index in layout::USER_LEVEL_4_ENTRIES {
+                if let Ok(frame) = table[index].frame() {
+                    unsafe { free_table(frame, 3, frame_allocator) };
+                }
+                table[index].set_unused();
+            }
+            frame_allocator.deallocate_frame(self.level_4_frame);
+        });
+    }
+}
+
+/// Returns a mutable reference to the page table stored in `frame`.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `frame`
+/// contains a page table, and that it is not aliased.
+unsafe fn table_mut<'a>(frame: PhysFrame) -> &'a mut PageTable {
+    &mut *phys_to_virt(frame.start_address()).as_mut_ptr()
+}
+
+/// Free the page table of the given `level` stored in `frame`, the tables it
+/// points to and every frame they map.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the table is
+/// not in use anymore.
+unsafe fn free_table(
+    frame: PhysFrame,
+    level: u8,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    for entry in table_mut(frame).iter_mut() {
+        if let Ok(next) = entry.frame() {
+            if level > 1 {
+                free_table(next, level - 1, frame_allocator);
+            } else {
+                frame_allocator.deallocate_frame(next);
+            }
+        }
+        entry.set_unused();
+    }
+    frame_allocator.deallocate_frame(frame);
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_handle_fault() {
+    serial_print!("test_handle_fault... ");
+    let mut address_space = AddressSpace::new().unwrap();
+    let memory = Arc::new(SharedMemory::new(2 * Page::<Size4KiB>::SIZE).unwrap());
+    let start = VirtAddr::new(layout::USER_SPACE_START);
+    let flags =
+        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    let mut reserve = |offset: u64, len: u64, flags: PageTableFlags, backing: Backing| {
+        let region = Region {
+            start: start + offset,
+            end: start + offset + len,
+            flags,
+            backing,
+        };
+        address_space.add_region(region).unwrap();
+    };
+    let writable = flags | PageTableFlags::WRITABLE;
+    reserve(0x0000, 0x2000, writable, Backing::Anonymous);
+    let shared = Backing::Shared {
+        memory: memory.clone(),
+        offset: 0x1000,
+    };
+    reserve(0x2000, 0x1000, flags, shared);
+    reserve(0x3000, 0x1000, PageTableFlags::empty(), Backing::Anonymous);
+
+    let read = PageFaultErrorCode::USER_MODE;
+    let write = PageFaultErrorCode::USER_MODE | PageFaultErrorCode::CAUSED_BY_WRITE;
+    let fetch = PageFaultErrorCode::USER_MODE | PageFaultErrorCode::INSTRUCTION_FETCH;
+    let faults: [(u64, PageFaultErrorCode); 7] = [
+        (0x1008, write),
+        (0x1010, read),
+        (0x2000, read),
+        (0x2008, write),
+        (0x3000, read),
+        (0x0000, fetch),
+        (0x4000, read),
+    ];
+    let results: Vec<_> = super::with_frame_allocator(|frame_allocator| {
+        faults
+            .iter()
+            .map(|&(offset, error_code)| {
+                address_space.handle_fault(start + offset, error_code, frame_allocator)
+            })
+            .collect()
+    });
+    assert_eq!(
+        results,
+        [
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Err(FaultError::Denied),
+            Err(FaultError::Denied),
+            Err(FaultError::Denied),
+            Err(FaultError::NotReserved),
+        ]
+    );
+
+    // only the anonymous page and the shared one are mapped
+    assert_eq!(address_space.resident_pages(), 2);
+    let (frame, page_flags) = address_space
+        .translate(Page::containing_address(start + 0x1000u64))
+        .unwrap();
+    assert!(page_flags.contains(PageTableFlags::WRITABLE));
+    let byte: *const u8 = phys_to_virt(frame.start_address() + 8u64).as_ptr();
+    assert_eq!(unsafe { *byte }, 0);
+    let (frame, _) = address_space
+        .translate(Page::containing_address(start + 0x2000u64))
+        .unwrap();
+    assert_eq!(frame, memory.frames()[1]);
+    assert!(address_space
+        .translate(Page::containing_address(start))
+        .is_none());
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_map_zeroed_failure() {
+    serial_print!("test_map_zeroed_failure... ");
+    let mut address_space = AddressSpace::new().unwrap();
+    let page = Page::containing_address(VirtAddr::new(layout::USER_SPACE_START));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let (first, second, allocated) = super::with_frame_allocator(|frame_allocator| {
+        let first = address_space.map_zeroed(page, flags, frame_allocator);
+        let allocated = frame_allocator.stats().allocated;
+        let second = address_space.map_zeroed(page, flags, frame_allocator);
+        (first, second, allocated - frame_allocator.stats().allocated)
+    });
+    assert!(first.is_ok());
+    // the frame allocated for the already mapped page is given back
+    assert!(second.is_err());
+    assert_eq!(allocated, 0);
+    serial_println!("[ok]");
+}
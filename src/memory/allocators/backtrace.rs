@@ -0,0 +1,66 @@
+//! This module finds the callers of a function, to report where heap memory
+//! was allocated or freed.
+//!
+//! The callers are found by walking the chain of frame pointers, so the kernel
+//! must be compiled with them : `RUSTFLAGS="-C force-frame-pointers=yes"`.
+//! Without them, the returned addresses are meaningless.
+//!
+//! The addresses can be resolved with `addr2line -e <kernel binary>`.
+//!
+
+/// Number of return addresses of a trace.
+pub const TRACE_DEPTH: usize = 6;
+
+/// Maximum distance from the first frame that the walk may read : deeper
+/// frames are probably not on the stack anymore.
+const MAX_WALK: usize = 16 * 1024;
+
+global_asm!(
+    r#"
+.intel_syntax noprefix
+
+// fn nit_frame_pointer() -> usize
+// returns the frame pointer of the caller
+.global nit_frame_pointer
+nit_frame_pointer:
+    mov rax, rbp
+    ret
+
+.att_syntax prefix
+"#
+);
+
+extern "C" {
+    fn nit_frame_pointer() -> usize;
+}
+
+/// Returns the return addresses of the callers of the caller, from the
+/// innermost, skipping the first `skip` of them. Past the end of the walk,
+/// the addresses are 0.
+#[inline(never)]
+pub fn trace(skip: usize) -> [usize; TRACE_DEPTH] {
+    let mut trace = [0; TRACE_DEPTH];
+    let first = unsafe { nit_frame_pointer() };
+    let mut frame = first;
+    let mut skip = skip;
+    let mut index = 0;
+    while index < TRACE_DEPTH {
+        if frame == 0 || frame % 8 != 0 || frame < first || frame - first > MAX_WALK {
+            break;
+        }
+        // the frame holds the previous frame pointer, then the return address
+        let (previous, return_address) =
+            unsafe { (*(frame as *const usize), *((frame + 8) as *const usize)) };
+        if skip == 0 {
+            trace[index] = return_address;
+            index += 1;
+        } else {
+            skip -= 1;
+        }
+        if previous <= frame {
+            break;
+        }
+        frame = previous;
+    }
+    trace
+}
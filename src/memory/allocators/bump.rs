@@ -5,7 +5,7 @@
 //!
 
 // internal crate
-use super::{align_up, Locked};
+use super::{align_up, Counters, HeapAllocator, HeapStats, Locked};
 
 // external crates
 use alloc::alloc::{GlobalAlloc, Layout};
@@ -22,6 +22,7 @@ pub struct BumpAllocator {
     heap_end: usize,
     next: usize,
     allocations: usize,
+    counters: Counters,
 }
 
 impl BumpAllocator {
@@ -32,20 +33,31 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            counters: Counters::new(),
         }
     }
+}
 
+impl HeapAllocator for BumpAllocator {
     /// Initialize the bump allocator with the given heap bounds.
     ///
     /// ## Safety
     ///
     /// This method is unsafe because the caller must ensure that the given
     /// memory range is unused. Also, this method must be called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
     }
+
+    unsafe fn extend(&mut self, by: usize) {
+        self.heap_end += by;
+    }
+
+    fn stats(&self) -> HeapStats {
+        self.counters.stats(self.heap_end - self.heap_start)
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -53,24 +65,23 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
         let mut bump = self.lock();
 
         let alloc_start = align_up(bump.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
-        };
-
-        if alloc_end > bump.heap_end {
+        let ptr = match alloc_start.checked_add(layout.size()) {
+            Some(alloc_end) if alloc_end <= bump.heap_end => {
+                bump.next = alloc_end;
+                bump.allocations += 1;
+                alloc_start as *mut u8
+            }
             // out of memory
-            ptr::null_mut()
-        } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
-        }
+            _ => ptr::null_mut(),
+        };
+        bump.counters.alloc(ptr, layout.size(), layout.size());
+        ptr
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
 
+        bump.counters.dealloc(layout.size());
         bump.allocations -= 1;
         if bump.allocations == 0 {
             bump.next = bump.heap_start;
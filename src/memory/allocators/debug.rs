@@ -0,0 +1,377 @@
+//! This module contains the `DebugHeap`, which wraps an allocator to detect
+//! the misuses of the heap.
+//!
+//! It is only built with the `heap_debug` feature, which makes it the global
+//! allocator of the kernel. Each allocation is surrounded by redzones, bytes
+//! which must never be written, and preceded by a header recording where it
+//! was allocated :
+//!
+//! ```text
+//! | padding | Header | redzone | allocation | redzone |
+//! ```
+//!
+//! Freed allocations are filled with a poison pattern and kept in a
+//! quarantine before being really freed, so that :
+//! - a write out of the bounds of an allocation is detected when it is freed,
+//! - a double free is detected while the allocation is in quarantine,
+//! - a write after free is detected when it leaves the quarantine.
+//!
+//! Each of them panics with the callers of the allocation, found by
+//! `backtrace::trace`, which needs frame pointers.
+//!
+
+// internal crate
+use super::{
+    backtrace::{self, TRACE_DEPTH},
+    Locked,
+};
+
+// external crates
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{fmt, mem, ptr};
+
+/// Size of each redzone, in bytes.
+pub const REDZONE_SIZE: usize = 16;
+/// Number of freed allocations kept in quarantine.
+pub const QUARANTINE_SIZE: usize = 128;
+
+/// Byte filling the redzones.
+pub const REDZONE_BYTE: u8 = 0xfd;
+/// Byte filling new allocations, to spot the use of uninitialized memory.
+pub const ALLOC_BYTE: u8 = 0xcd;
+/// Byte filling freed allocations.
+pub const POISON_BYTE: u8 = 0x6b;
+
+/// Magic of the header of a live allocation.
+const LIVE: u64 = 0x_11fe_11fe_11fe_11fe;
+/// Magic of the header of an allocation in quarantine.
+const FREED: u64 = 0x_dead_dead_dead_dead;
+
+/// The header of an allocation, right before its first redzone.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Header {
+    magic: u64,
+    size: usize,
+    /// Callers of `alloc`.
+    trace: [usize; TRACE_DEPTH],
+}
+
+/// A misuse of the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// The allocation was freed twice, and was first freed from `freed_from`.
+    DoubleFree { freed_from: [usize; TRACE_DEPTH] },
+    /// The pointer freed was not allocated, or its header was overwritten.
+    InvalidFree,
+    /// The allocation was freed with a size other than its own.
+    SizeMismatch { size: usize },
+    /// A byte of a redzone was written, at `offset` from the allocation.
+    Overflow { offset: isize },
+    /// A byte was written at `offset` in the allocation after it was freed
+    /// from `freed_from`.
+    UseAfterFree {
+        offset: usize,
+        freed_from: [usize; TRACE_DEPTH],
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeapError::DoubleFree { freed_from } => {
+                write!(f, "double free, first freed from {:x?}", freed_from)
+            }
+            HeapError::InvalidFree => write!(f, "free of a pointer not allocated"),
+            HeapError::SizeMismatch { size } => {
+                write!(f, "freed with the wrong size, allocated with {}", size)
+            }
+            HeapError::Overflow { offset } => write!(f, "redzone written at offset {}", offset),
+            HeapError::UseAfterFree { offset, freed_from } => write!(
+                f,
+                "written at offset {} after being freed from {:x?}",
+                offset, freed_from
+            ),
+        }
+    }
+}
+
+/// A freed allocation in quarantine.
+#[derive(Debug, Clone, Copy)]
+struct Quarantined {
+    ptr: usize,
+    layout: Layout,
+    /// Callers of `dealloc`.
+    trace: [usize; TRACE_DEPTH],
+}
+
+/// The freed allocations in quarantine, the oldest one first.
+struct Quarantine {
+    entries: [Option<Quarantined>; QUARANTINE_SIZE],
+    /// Index of the oldest entry.
+    head: usize,
+    len: usize,
+}
+
+impl Quarantine {
+    /// Returns the entry of the allocation at `ptr`.
+    fn find(&self, ptr: usize) -> Option<&Quarantined> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|quarantined| quarantined.ptr == ptr)
+    }
+
+    /// Add an entry, returning the oldest one if the quarantine is full.
+    fn push(&mut self, quarantined: Quarantined) -> Option<Quarantined> {
+        let oldest = if self.len == QUARANTINE_SIZE {
+            self.len -= 1;
+            let oldest = self.entries[self.head].take();
+            self.head = (self.head + 1) % QUARANTINE_SIZE;
+            oldest
+        } else {
+            None
+        };
+        self.entries[(self.head + self.len) % QUARANTINE_SIZE] = Some(quarantined);
+        self.len += 1;
+        oldest
+    }
+
+    /// Remove the oldest entry.
+    fn pop(&mut self) -> Option<Quarantined> {
+        if self.len == 0 {
+            return None;
+        }
+        let oldest = self.entries[self.head].take();
+        self.head = (self.head + 1) % QUARANTINE_SIZE;
+        self.len -= 1;
+        oldest
+    }
+}
+
+/// An allocator wrapping `A`, with redzones, poisoning and a quarantine.
+///
+/// Each allocation takes `Header` and two redzones more from `A`, and its
+/// memory is given back to `A` only once it leaves the quarantine.
+pub struct DebugHeap<A> {
+    inner: A,
+    quarantine: Locked<Quarantine>,
+}
+
+impl<A: GlobalAlloc> DebugHeap<A> {
+    /// Create a `DebugHeap` taking its memory from `inner`.
+    pub const fn new(inner: A) -> Self {
+        DebugHeap {
+            inner,
+            quarantine: Locked::new(Quarantine {
+                entries: [None; QUARANTINE_SIZE],
+                head: 0,
+                len: 0,
+            }),
+        }
+    }
+
+    /// Returns the allocator wrapped.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Check the allocation at `ptr`, which is about to be freed with
+    /// `layout`.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr`
+    /// was allocated by this allocator, or is in its quarantine.
+    pub unsafe fn check(&self, ptr: *mut u8, layout: Layout) -> Result<(), HeapError> {
+        let header = &*header(ptr);
+        match header.magic {
+            LIVE => (),
+            FREED => {
+                let quarantine = self.quarantine.lock();
+                return Err(match quarantine.find(ptr as usize) {
+                    Some(quarantined) => HeapError::DoubleFree {
+                        freed_from: quarantined.trace,
+                    },
+                    None => HeapError::InvalidFree,
+                });
+            }
+            _ => return Err(HeapError::InvalidFree),
+        }
+        if header.size != layout.size() {
+            return Err(HeapError::SizeMismatch { size: header.size });
+        }
+        let front = ptr.sub(REDZONE_SIZE);
+        if let Some(index) = find_not(front, REDZONE_SIZE, REDZONE_BYTE) {
+            return Err(HeapError::Overflow {
+                offset: index as isize - REDZONE_SIZE as isize,
+            });
+        }
+        let back = ptr.add(layout.size());
+        if let Some(index) = find_not(back, REDZONE_SIZE, REDZONE_BYTE) {
+            return Err(HeapError::Overflow {
+                offset: (layout.size() + index) as isize,
+            });
+        }
+        Ok(())
+    }
+
+    /// Give back to `A` all the allocations in quarantine, checking them.
+    ///
+    /// Panics if one of them was written after being freed.
+    pub fn flush(&self) {
+        loop {
+            let oldest = self.quarantine.lock().pop();
+            match oldest {
+                Some(quarantined) => unsafe { self.release(quarantined) },
+                None => return,
+            }
+        }
+    }
+
+    /// Check that an allocation leaving the quarantine is still poisoned, and
+    /// give it back to `A`.
+    unsafe fn release(&self, quarantined: Quarantined) {
+        let ptr = quarantined.ptr as *mut u8;
+        let size = quarantined.layout.size();
+        if let Some(offset) = find_not(ptr, size, POISON_BYTE) {
+            report(
+                ptr,
+                HeapError::UseAfterFree {
+                    offset,
+                    freed_from: quarantined.trace,
+                },
+            );
+        }
+        let (layout, front) = outer_layout(&quarantined.layout);
+        self.inner.dealloc(ptr.sub(front), layout);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (outer, front) = outer_layout(&layout);
+        let base = self.inner.alloc(outer);
+        if base.is_null() {
+            return base;
+        }
+        let ptr = base.add(front);
+        header(ptr).write(Header {
+            magic: LIVE,
+            size: layout.size(),
+            // skip the return address in this function
+            trace: backtrace::trace(1),
+        });
+        ptr::write_bytes(ptr.sub(REDZONE_SIZE), REDZONE_BYTE, REDZONE_SIZE);
+        ptr::write_bytes(ptr, ALLOC_BYTE, layout.size());
+        ptr::write_bytes(ptr.add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Err(error) = self.check(ptr, layout) {
+            report(ptr, error);
+        }
+        (*header(ptr)).magic = FREED;
+        ptr::write_bytes(ptr, POISON_BYTE, layout.size());
+        let oldest = self.quarantine.lock().push(Quarantined {
+            ptr: ptr as usize,
+            layout,
+            trace: backtrace::trace(1),
+        });
+        if let Some(oldest) = oldest {
+            self.release(oldest);
+        }
+    }
+}
+
+/// Returns the layout taken from the wrapped allocator for an allocation with
+/// `layout`, and the offset of the allocation in it.
+fn outer_layout(layout: &Layout) -> (Layout, usize) {
+    let align = layout.align().max(mem::align_of::<Header>());
+    let front = mem::size_of::<Header>() + REDZONE_SIZE;
+    let front = (front + align - 1) & !(align - 1);
+    let size = front + layout.size() + REDZONE_SIZE;
+    let outer = Layout::from_size_align(size, align).expect("allocation too large");
+    (outer, front)
+}
+
+/// Returns the header of the allocation at `ptr`.
+fn header(ptr: *mut u8) -> *mut Header {
+    (ptr as usize - REDZONE_SIZE - mem::size_of::<Header>()) as *mut Header
+}
+
+/// Returns the index of the first of the `len` bytes at `ptr` which is not
+/// `byte`.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the bytes
+/// are readable.
+unsafe fn find_not(ptr: *const u8, len: usize, byte: u8) -> Option<usize> {
+    (0..len).find(|&index| ptr.add(index).read() != byte)
+}
+
+/// Panic on a misuse of the allocation at `ptr`, with its callers.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `ptr` has
+/// a header.
+unsafe fn report(ptr: *mut u8, error: HeapError) -> ! {
+    let header = &*header(ptr);
+    if error == HeapError::InvalidFree {
+        panic!("heap: {} at {:p}", error, ptr);
+    }
+    panic!(
+        "heap: allocation at {:p} of {} bytes {}, allocated from {:x?}",
+        ptr, header.size, error, header.trace
+    );
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_debug_heap() {
+    use super::{linked_list::LinkedListAllocator, HeapAllocator};
+    use alloc::vec::Vec;
+
+    serial_print!("test_debug_heap... ");
+    let mut buffer = Vec::<u64>::with_capacity(1024);
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(buffer.as_mut_ptr() as usize, 8192) };
+    let heap = DebugHeap::new(allocator);
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let ptr = unsafe { heap.alloc(layout) };
+    assert_eq!(unsafe { heap.check(ptr, layout) }, Ok(()));
+    assert_eq!(unsafe { ptr.read() }, ALLOC_BYTE);
+
+    // out of bounds
+    unsafe { ptr.add(24).write(0) };
+    let overflow = HeapError::Overflow { offset: 24 };
+    assert_eq!(unsafe { heap.check(ptr, layout) }, Err(overflow));
+    unsafe { ptr.add(24).write(REDZONE_BYTE) };
+    unsafe { ptr.sub(1).write(0) };
+    let underflow = HeapError::Overflow { offset: -1 };
+    assert_eq!(unsafe { heap.check(ptr, layout) }, Err(underflow));
+    unsafe { ptr.sub(1).write(REDZONE_BYTE) };
+    let wrong_layout = Layout::from_size_align(16, 8).unwrap();
+    let mismatch = HeapError::SizeMismatch { size: 24 };
+    assert_eq!(unsafe { heap.check(ptr, wrong_layout) }, Err(mismatch));
+
+    // freed : poisoned, and in quarantine
+    unsafe { heap.dealloc(ptr, layout) };
+    assert_eq!(unsafe { ptr.read() }, POISON_BYTE);
+    match unsafe { heap.check(ptr, layout) } {
+        Err(HeapError::DoubleFree { .. }) => (),
+        result => panic!("double free not detected : {:?}", result),
+    }
+    heap.flush();
+    assert_eq!(heap.inner().lock().stats().used, 0);
+    serial_println!("[ok]");
+}
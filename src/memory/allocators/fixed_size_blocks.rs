@@ -5,13 +5,12 @@
 //!
 
 // internal crate
-use super::Locked;
+use super::{linked_list::LinkedListAllocator, Counters, HeapAllocator, HeapStats, Locked};
 
 // external crates
 use alloc::alloc::GlobalAlloc;
 use alloc::alloc::Layout;
-use core::{mem, ptr};
-use linked_list_allocator::Heap;
+use core::mem;
 
 /// The block sizes to use.
 ///
@@ -40,9 +39,12 @@ pub struct FixedSizeBlockAllocator {
 
     /// Fallback allocator to use if size is too important to be contained in a block.
     ///
-    /// The one actually used is from the `linked_list_allocator` crate, it could be
-    /// switched to the `linked_list` module when it provides blocks merging.
-    fallback_allocator: Heap,
+    /// It merges the adjacent free regions, so that the large allocations do
+    /// not fragment the heap over time.
+    fallback_allocator: LinkedListAllocator,
+
+    /// The statistics of the allocations, counting the size of their blocks.
+    counters: Counters,
 }
 
 impl FixedSizeBlockAllocator {
@@ -50,29 +52,36 @@ impl FixedSizeBlockAllocator {
     pub const fn new() -> Self {
         FixedSizeBlockAllocator {
             list_heads: [None; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            fallback_allocator: LinkedListAllocator::new(),
+            counters: Counters::new(),
         }
     }
 
-    /// Initialize the allocator with the given heap bounds.
-    ///
-    /// ## Safety
-    ///
-    /// This function is unsafe because the caller must guarantee that the given
-    /// heap bounds are valid and that the heap is unused. This method must be
-    /// called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    /// Allocate using the fallback allocator.
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_allocator.allocate(layout)
+    }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
-    /// Allocate using the fallback allocator.
-    ///
-    /// This implementation is adapted to the `linked_list_allocator` crate.
-    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
-        }
+    unsafe fn extend(&mut self, by: usize) {
+        self.fallback_allocator.extend(by);
+    }
+
+    fn stats(&self) -> HeapStats {
+        self.counters.stats(self.fallback_allocator.stats().size)
+    }
+}
+
+/// Returns the number of bytes used by an allocation with the given layout.
+fn allocated_size(layout: &Layout) -> usize {
+    match list_index(layout) {
+        Some(index) => BLOCK_SIZES[index],
+        None => layout.size(),
     }
 }
 
@@ -87,7 +96,7 @@ fn list_index(layout: &Layout) -> Option<usize> {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -105,11 +114,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout),
-        }
+        };
+        allocator
+            .counters
+            .alloc(ptr, layout.size(), allocated_size(&layout));
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.counters.dealloc(allocated_size(&layout));
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
@@ -122,10 +136,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 new_node_ptr.write(new_node);
                 allocator.list_heads[index] = Some(&mut *new_node_ptr);
             }
-            None => {
-                let ptr = ptr::NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
-            }
+            None => allocator.fallback_allocator.deallocate(ptr, layout),
         }
     }
 }
@@ -3,13 +3,18 @@
 //! It may be a good allocator, but it needs to be used with caution : to much
 //! load makes it very slow.
 //!
+//! The free regions are kept sorted by address, and a freed region is merged
+//! with its free neighbours, so that the heap does not fragment more than its
+//! allocations do. The region of an allocation is chosen following the
+//! `FitStrategy` given at construction.
+//!
 
 // internal crate
-use super::{align_up, Locked};
+use super::{align_up, Counters, HeapAllocator, HeapStats, Locked};
 
 // external crates
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::{mem, ptr};
+use core::{iter, mem, ptr};
 
 /// A node representing a region of the memory.
 /// It can either be the last node or point to the next one.
@@ -32,51 +37,145 @@ impl ListNode {
     }
 }
 
+/// How the region of an allocation is chosen among the free ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy {
+    /// The first region large enough : fast, but the small regions gather at
+    /// the start of the heap.
+    FirstFit,
+    /// The smallest region large enough : keeps the large regions, but goes
+    /// through every region.
+    BestFit,
+    /// The first region large enough after the last allocation, wrapping
+    /// around : spreads the allocations over the heap.
+    NextFit,
+}
+
+/// The free regions of a `LinkedListAllocator`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FreeRegions {
+    /// Number of free regions.
+    pub count: usize,
+    /// Bytes of all free regions.
+    pub free: usize,
+    /// Bytes of the largest free region.
+    pub largest: usize,
+}
+
+impl FreeRegions {
+    /// Returns the fragmentation of the free memory, in percent : 0 if it is
+    /// all in one region, close to 100 if the largest region is a small part
+    /// of it.
+    pub fn fragmentation(&self) -> usize {
+        if self.free == 0 {
+            0
+        } else {
+            100 - self.largest * 100 / self.free
+        }
+    }
+}
+
 /// A linked list allocator.
 ///
 /// This allocator is reasonably simple, but not the simplest nor the fastest one.
 ///
 /// It can be used in many situations, especially when allocations are not too
 /// complex.
-///
-/// The current implementation does not merge blocks, resulting in memory
-/// fragmentation.
-// TODO support blocks merging
 pub struct LinkedListAllocator {
     head: ListNode,
+    strategy: FitStrategy,
+    /// End of the last allocation, where `FitStrategy::NextFit` starts from.
+    rover: usize,
+    heap_end: usize,
+    size: usize,
+    counters: Counters,
 }
 
 impl LinkedListAllocator {
-    /// Creates an empty `LinkedListAllocator`.
+    /// Creates an empty `LinkedListAllocator`, using the first fit strategy.
     pub const fn new() -> Self {
+        Self::with_strategy(FitStrategy::FirstFit)
+    }
+
+    /// Creates an empty `LinkedListAllocator`, using the given strategy.
+    pub const fn with_strategy(strategy: FitStrategy) -> Self {
         Self {
             head: ListNode::new(0),
+            strategy,
+            rover: 0,
+            heap_end: 0,
+            size: 0,
+            counters: Counters::new(),
         }
     }
 
-    /// Initialize the allocator with the given heap bounds.
-    ///
-    /// ## Safety
-    ///
-    /// This function is unsafe because the caller must guarantee that the given
-    /// heap bounds are valid and that the heap is unused. This method must be
-    /// called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.add_free_region(heap_start, heap_size);
+    /// Returns the strategy of the allocator.
+    pub fn strategy(&self) -> FitStrategy {
+        self.strategy
+    }
+
+    /// Returns the free regions of the heap.
+    pub fn free_regions(&self) -> FreeRegions {
+        let mut free_regions = FreeRegions::default();
+        for region in self.regions() {
+            free_regions.count += 1;
+            free_regions.free += region.size;
+            free_regions.largest = free_regions.largest.max(region.size);
+        }
+        free_regions
+    }
+
+    /// Returns the free regions, by address.
+    fn regions(&self) -> impl Iterator<Item = &ListNode> {
+        iter::successors(self.head.next.as_deref(), |&region| region.next.as_deref())
     }
 
-    /// Adds the given memory region to the front of the list.
+    /// Adds the given memory region to the list, at its place by address, and
+    /// merges it with the adjacent free regions.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // ensure that the freed region is capable of holding ListNode
         assert!(align_up(addr, mem::align_of::<ListNode>()) == addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        // create a new list node and append it at the start of the list
+        // find the last region before the new one, or the head
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .map_or(false, |next| next.start_addr() < addr)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+
         let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        let node_ptr = addr as *mut ListNode;
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr)
+        if let Some(next) = current.next.take() {
+            assert!(
+                addr + size <= next.start_addr(),
+                "freed region overlaps a free region"
+            );
+            if addr + size == next.start_addr() {
+                // merge with the next region
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        // the head has a size of 0, unlike any region
+        assert!(
+            current.size == 0 || current.end_addr() <= addr,
+            "freed region overlaps a free region"
+        );
+        if current.size > 0 && current.end_addr() == addr {
+            // merge with the previous region
+            current.size += node.size;
+            current.next = node.next;
+        } else {
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(node);
+            current.next = Some(&mut *node_ptr)
+        }
     }
 
     /// Looks for a free region with the given size and alignment and removes
@@ -84,24 +183,42 @@ impl LinkedListAllocator {
     ///
     /// Returns a tuple of the list node and the start address of the allocation.
     fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let chosen = self.choose_region(size, align)?;
+
         // reference to current list node, updated for each iteration
         let mut current = &mut self.head;
-        // look for a large enough memory region in linked list
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                // region suitable for allocation -> remove node from list
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            } else {
-                // region not suitable -> continue with next region
-                current = current.next.as_mut().unwrap();
-            }
+        while current
+            .next
+            .as_ref()
+            .map_or(false, |next| next.start_addr() != chosen)
+        {
+            current = current.next.as_mut().unwrap();
         }
 
-        // no suitable region found
-        None
+        // remove the chosen region from the list
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
+        let alloc_start = Self::alloc_from_region(region, size, align).unwrap();
+        Some((region, alloc_start))
+    }
+
+    /// Choose a free region for an allocation with the given size and
+    /// alignment, following the strategy.
+    ///
+    /// Returns the start address of the region.
+    fn choose_region(&self, size: usize, align: usize) -> Option<usize> {
+        let suitable = || {
+            self.regions()
+                .filter(move |region| Self::alloc_from_region(region, size, align).is_ok())
+        };
+        match self.strategy {
+            FitStrategy::FirstFit => suitable().next(),
+            FitStrategy::BestFit => suitable().min_by_key(|region| region.size),
+            FitStrategy::NextFit => suitable()
+                .find(|region| region.start_addr() >= self.rover)
+                .or_else(|| suitable().next()),
+        }
+        .map(ListNode::start_addr)
     }
 
     /// Try to use the given region for an allocation with given size and
@@ -109,7 +226,12 @@ impl LinkedListAllocator {
     ///
     /// Returns the allocation start address on success.
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        let gap_size = alloc_start - region.start_addr();
+        if gap_size > 0 && gap_size < mem::size_of::<ListNode>() {
+            // the gap before the allocation must hold a ListNode to stay free
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -128,6 +250,50 @@ impl LinkedListAllocator {
         Ok(alloc_start)
     }
 
+    /// Allocate memory with the given layout.
+    ///
+    /// Returns a null pointer if no free region is large enough.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        // perform layout adjustments
+        let (size, align) = Self::size_align(layout);
+
+        let ptr = if let Some((region, alloc_start)) = self.find_region(size, align) {
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
+            let alloc_end = alloc_start.checked_add(size).expect("overflow");
+            unsafe {
+                if alloc_start > region_start {
+                    self.add_free_region(region_start, alloc_start - region_start);
+                }
+                let excess_size = region_end - alloc_end;
+                if excess_size > 0 {
+                    self.add_free_region(alloc_end, excess_size);
+                }
+            }
+            self.rover = alloc_end;
+            alloc_start as *mut u8
+        } else {
+            ptr::null_mut()
+        };
+        self.counters.alloc(ptr, layout.size(), size);
+        ptr
+    }
+
+    /// Free the memory at `ptr`, allocated with the given layout, and merge it
+    /// with the adjacent free regions.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr`
+    /// was returned by `allocate` with the same layout, and is not used
+    /// anymore.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        // perform layout adjustments
+        let (size, _) = Self::size_align(layout);
+
+        self.counters.dealloc(size);
+        self.add_free_region(ptr as usize, size)
+    }
+
     /// Adjust the given layout so that the resulting allocated memory
     /// region is also capable of storing a `ListNode`.
     ///
@@ -142,28 +308,108 @@ impl LinkedListAllocator {
     }
 }
 
+impl HeapAllocator for LinkedListAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+        self.heap_end = heap_start + heap_size;
+        self.size = heap_size;
+    }
+
+    unsafe fn extend(&mut self, by: usize) {
+        self.add_free_region(self.heap_end, by);
+        self.heap_end += by;
+        self.size += by;
+    }
+
+    fn stats(&self) -> HeapStats {
+        self.counters.stats(self.size)
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // perform layout adjustments
-        let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
-
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
-            }
-            alloc_start as *mut u8
-        } else {
-            ptr::null_mut()
-        }
+        self.lock().allocate(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        // perform layout adjustments
-        let (size, _) = LinkedListAllocator::size_align(layout);
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
 
-        self.lock().add_free_region(ptr as usize, size)
+/// Allocate `sizes` in a new heap of 4 KiB using `strategy`, free the
+/// allocations at the indices `freed`, and then allocate 32 bytes.
+///
+/// Returns the addresses of the allocations, and of the last one.
+#[cfg(test)]
+fn fit_scenario(
+    strategy: FitStrategy,
+    sizes: &[usize],
+    freed: &[usize],
+) -> (alloc::vec::Vec<usize>, usize) {
+    use alloc::vec::Vec;
+
+    let mut buffer = Vec::<u64>::with_capacity(512);
+    let allocator = Locked::new(LinkedListAllocator::with_strategy(strategy));
+    unsafe { allocator.lock().init(buffer.as_mut_ptr() as usize, 4096) };
+
+    let layout = |size| Layout::from_size_align(size, 8).unwrap();
+    let addresses: Vec<_> = sizes
+        .iter()
+        .map(|&size| unsafe { allocator.alloc(layout(size)) } as usize)
+        .collect();
+    for &index in freed {
+        unsafe { allocator.dealloc(addresses[index] as *mut u8, layout(sizes[index])) };
     }
+    let last = unsafe { allocator.alloc(layout(32)) } as usize;
+    (addresses, last)
+}
+
+#[test_case]
+fn test_coalescing() {
+    use alloc::vec::Vec;
+
+    serial_print!("test_coalescing... ");
+    let mut buffer = Vec::<u64>::with_capacity(512);
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(buffer.as_mut_ptr() as usize, 4096) };
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let blocks: Vec<_> = (0..3).map(|_| unsafe { allocator.alloc(layout) }).collect();
+    unsafe { allocator.dealloc(blocks[1], layout) };
+    let free_regions = allocator.lock().free_regions();
+    assert_eq!(free_regions.count, 2);
+    assert!(free_regions.fragmentation() > 0);
+
+    // merged with the next region, and then with the previous one
+    unsafe { allocator.dealloc(blocks[0], layout) };
+    assert_eq!(allocator.lock().free_regions().count, 2);
+    unsafe { allocator.dealloc(blocks[2], layout) };
+    let free_regions = allocator.lock().free_regions();
+    assert_eq!(free_regions.count, 1);
+    assert_eq!(free_regions.largest, 4096);
+    assert_eq!(free_regions.fragmentation(), 0);
+    assert_eq!(allocator.lock().stats().used, 0);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_fit_strategies() {
+    serial_print!("test_fit_strategies... ");
+    // free regions of 128 and 32 bytes, separated, before the rest of the heap
+    let sizes = [64, 128, 64, 32, 64];
+    let freed = [1, 3];
+
+    let (addresses, last) = fit_scenario(FitStrategy::FirstFit, &sizes, &freed);
+    assert_eq!(last, addresses[1]);
+    let (addresses, last) = fit_scenario(FitStrategy::BestFit, &sizes, &freed);
+    assert_eq!(last, addresses[3]);
+    let (addresses, last) = fit_scenario(FitStrategy::NextFit, &sizes, &freed);
+    assert_eq!(last, addresses[4] + 64);
+    serial_println!("[ok]");
 }
@@ -2,13 +2,151 @@
 //!
 //! None of them is feature-complete, but they work well.
 //!
+//! The allocator of the kernel heap is selected by a cargo feature :
+//! `allocator_fixed_size_blocks` (the default), `allocator_linked_list` or
+//! `allocator_bump`. Each of them implements `HeapAllocator`.
+//!
+//! With the `heap_leak_tracking` feature, the kernel heap also records its
+//! live allocations in a `tracking::LeakTracker`. With the `heap_debug`
+//! feature, it is wrapped in a `debug::DebugHeap`, checking its allocations.
+//!
 
 // submodules export
+#[cfg(any(feature = "heap_leak_tracking", feature = "heap_debug"))]
+pub mod backtrace;
 pub mod bump;
+#[cfg(feature = "heap_debug")]
+pub mod debug;
 pub mod fixed_size_blocks;
 pub mod linked_list;
+pub mod slab;
+#[cfg(feature = "heap_leak_tracking")]
+pub mod tracking;
+
+// external crates
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+use x86_64::instructions::interrupts;
+
+/// Number of size classes of the allocation counts : the powers of 2 from 8
+/// bytes to 4 KiB, and the larger sizes.
+pub const SIZE_CLASS_COUNT: usize = 11;
+
+/// Usage statistics of a `HeapAllocator`, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes of the heap managed by the allocator.
+    pub size: usize,
+    /// Bytes currently allocated, rounded up by the allocator.
+    pub used: usize,
+    /// Highest number of bytes allocated at once.
+    pub peak: usize,
+    /// Number of allocations currently alive.
+    pub live: usize,
+    /// Number of allocations made, by size class, see `size_class`.
+    pub allocations: [u64; SIZE_CLASS_COUNT],
+    /// Number of allocations which failed.
+    pub failed: u64,
+}
+
+impl HeapStats {
+    /// Returns the number of bytes of the heap not allocated.
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+}
+
+/// Returns the size class of an allocation of `size` bytes : the index of the
+/// smallest power of 2 from 8 holding it, `SIZE_CLASS_COUNT - 1` for larger
+/// allocations.
+pub fn size_class(size: usize) -> usize {
+    let class = size.max(8).next_power_of_two().trailing_zeros() as usize - 3;
+    class.min(SIZE_CLASS_COUNT - 1)
+}
+
+/// The counters of the statistics of an allocator.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Counters {
+    used: usize,
+    peak: usize,
+    live: usize,
+    allocations: [u64; SIZE_CLASS_COUNT],
+    failed: u64,
+}
+
+impl Counters {
+    pub(crate) const fn new() -> Self {
+        Counters {
+            used: 0,
+            peak: 0,
+            live: 0,
+            allocations: [0; SIZE_CLASS_COUNT],
+            failed: 0,
+        }
+    }
+
+    /// Count an allocation of `size` bytes, `allocated` with the rounding of
+    /// the allocator, which failed if `ptr` is null.
+    pub(crate) fn alloc(&mut self, ptr: *mut u8, size: usize, allocated: usize) {
+        if ptr.is_null() {
+            self.failed += 1;
+            return;
+        }
+        self.used += allocated;
+        self.peak = self.peak.max(self.used);
+        self.live += 1;
+        self.allocations[size_class(size)] += 1;
+    }
+
+    /// Count the free of an allocation of `allocated` bytes.
+    pub(crate) fn dealloc(&mut self, allocated: usize) {
+        self.used -= allocated;
+        self.live -= 1;
+    }
+
+    /// Returns the statistics of an allocator managing `size` bytes.
+    pub(crate) fn stats(&self, size: usize) -> HeapStats {
+        HeapStats {
+            size,
+            used: self.used,
+            peak: self.peak,
+            live: self.live,
+            allocations: self.allocations,
+            failed: self.failed,
+        }
+    }
+}
+
+/// An allocator of the memory of a heap, usable as the allocator of the kernel
+/// heap.
+pub trait HeapAllocator {
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    /// Extend the heap by `by` bytes, after its current end.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// memory after the end of the heap is mapped and unused.
+    unsafe fn extend(&mut self, by: usize);
+
+    /// Returns the usage statistics of the allocator.
+    fn stats(&self) -> HeapStats;
+}
 
 /// A wrapper around spin::Mutex to permit trait implementations.
+///
+/// Interrupts are disabled while the lock is held, so that interrupt handlers
+/// can allocate without deadlocking.
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
 }
@@ -20,8 +158,43 @@ impl<A> Locked<A> {
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
-        self.inner.lock()
+    pub fn lock(&self) -> LockedGuard<A> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        LockedGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+        }
+    }
+}
+
+/// A guard of a `Locked`, restoring interrupts when dropped.
+pub struct LockedGuard<'a, A> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, A>>,
+    interrupts_enabled: bool,
+}
+
+impl<'a, A> Deref for LockedGuard<'a, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.guard
+    }
+}
+
+impl<'a, A> DerefMut for LockedGuard<'a, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        &mut self.guard
+    }
+}
+
+impl<'a, A> Drop for LockedGuard<'a, A> {
+    fn drop(&mut self) {
+        // release the lock before enabling interrupts again
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
     }
 }
 
@@ -0,0 +1,580 @@
+//! This module contains a slab allocator.
+//!
+//! Objects of a given size are kept in caches, each taking slabs of contiguous
+//! pages from a `PageSource` and cutting them in objects. The slabs of a cache
+//! are in three lists : the full ones, the partial ones which objects are taken
+//! from first, and the empty ones. Empty slabs are given back to the page
+//! source, excepted `EMPTY_SLABS_KEPT` of them so that a cache does not take
+//! and give back the same pages on every allocation.
+//!
+//! A slab starts with a header and the list of its free objects, as indices :
+//! the free objects themselves are never written, and keep the state given by
+//! the constructor of their cache or the one they were freed in.
+//!
+//! `SlabAllocator` has a cache per size class, for the heap, and `ObjectCache`
+//! is a cache of objects of a type.
+//!
+
+// internal crate
+use super::{align_up, Locked};
+
+// external crates
+use alloc::alloc::Layout;
+use core::{
+    mem,
+    ptr::{self, NonNull},
+};
+
+/// Size of a page.
+const PAGE_SIZE: usize = 4096;
+/// Order of the largest slabs, of `2^order` pages.
+const MAX_SLAB_ORDER: usize = 3;
+/// Number of objects a slab should at least hold, if not larger than
+/// `MAX_SLAB_ORDER`.
+const MIN_OBJECTS: usize = 8;
+/// Number of empty slabs a cache keeps instead of giving them back.
+pub const EMPTY_SLABS_KEPT: usize = 1;
+/// End of a list of slabs.
+const NO_SLAB: usize = 0;
+/// End of the list of the free objects of a slab.
+const NO_OBJECT: u16 = u16::max_value();
+
+/// The size classes of `SlabAllocator`.
+///
+/// The sizes must each be power of 2 because they are also used as
+/// the object alignment.
+pub const SIZE_CLASSES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// A source of pages for the slabs.
+pub trait PageSource {
+    /// Allocate `2^order` contiguous pages, aligned to their size.
+    fn alloc_pages(&mut self, order: usize) -> Option<NonNull<u8>>;
+
+    /// Give back the pages at `pages`, allocated with the same order.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// pages were allocated by `alloc_pages` and are unused.
+    unsafe fn free_pages(&mut self, pages: NonNull<u8>, order: usize);
+}
+
+/// Usage statistics of a cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub name: &'static str,
+    /// Size of the objects, as requested.
+    pub object_size: usize,
+    /// Objects in use.
+    pub objects: usize,
+    /// Slabs of the cache, empty ones included.
+    pub slabs: usize,
+    /// Slabs without any object in use.
+    pub empty_slabs: usize,
+    /// Bytes of the slabs which can hold no object : headers, padding and the
+    /// ends of the slabs.
+    pub waste: usize,
+}
+
+/// The header of a slab, at its start.
+#[repr(C)]
+struct Slab {
+    next: usize,
+    prev: usize,
+    /// Index of the first free object, `NO_OBJECT` if there is none.
+    free: u16,
+    in_use: u16,
+}
+
+/// The lists of slabs of a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlabList {
+    Empty,
+    Partial,
+    Full,
+}
+
+/// A cache of objects of the same size.
+pub struct SlabCache {
+    name: &'static str,
+    /// Size of the objects, as requested.
+    size: usize,
+    align: usize,
+    /// Distance between two objects.
+    stride: usize,
+    /// The slabs are `2^order` pages.
+    order: usize,
+    /// Number of objects of a slab, 0 before its first use.
+    capacity: usize,
+    /// Offset of the first object in a slab.
+    offset: usize,
+    partial: usize,
+    full: usize,
+    empty: usize,
+    slabs: usize,
+    empty_slabs: usize,
+    objects: usize,
+}
+
+impl SlabCache {
+    /// Create an empty cache of objects of `size` bytes aligned to `align`,
+    /// which must be a power of 2 up to the size of a page.
+    pub const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        SlabCache {
+            name,
+            size,
+            align,
+            stride: 0,
+            order: 0,
+            capacity: 0,
+            offset: 0,
+            partial: NO_SLAB,
+            full: NO_SLAB,
+            empty: NO_SLAB,
+            slabs: 0,
+            empty_slabs: 0,
+            objects: 0,
+        }
+    }
+
+    /// Returns the name of the cache.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the usage statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        let slab_size = PAGE_SIZE << self.order;
+        CacheStats {
+            name: self.name,
+            object_size: self.size,
+            objects: self.objects,
+            slabs: self.slabs,
+            empty_slabs: self.empty_slabs,
+            waste: self.slabs * (slab_size - self.capacity * self.size),
+        }
+    }
+
+    /// Take an object, from a new slab of `pages` if every slab is full.
+    /// `construct` is run on the objects of a new slab.
+    ///
+    /// Returns a null pointer if no slab can be allocated.
+    pub fn alloc(&mut self, pages: &mut impl PageSource, construct: impl Fn(*mut u8)) -> *mut u8 {
+        self.layout();
+        let slab = if self.partial != NO_SLAB {
+            self.partial
+        } else if self.empty != NO_SLAB {
+            self.empty
+        } else {
+            match self.grow(pages, construct) {
+                Some(slab) => slab,
+                None => return ptr::null_mut(),
+            }
+        };
+
+        unsafe {
+            let header = slab as *mut Slab;
+            let index = (*header).free as usize;
+            (*header).free = *self.next_free(slab, index);
+            let in_use = (*header).in_use as usize;
+            (*header).in_use += 1;
+            self.move_slab(slab, in_use, in_use + 1);
+            self.objects += 1;
+            self.object(slab, index)
+        }
+    }
+
+    /// Give back `object`, which keeps its state for its next use. `destroy`
+    /// is run on the objects of a slab given back to `pages`.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `object`
+    /// was taken from this cache with `alloc`, and is unused.
+    pub unsafe fn free(
+        &mut self,
+        pages: &mut impl PageSource,
+        object: *mut u8,
+        destroy: impl Fn(*mut u8),
+    ) {
+        let slab = object as usize & !((PAGE_SIZE << self.order) - 1);
+        let index = (object as usize - slab - self.offset) / self.stride;
+        let header = slab as *mut Slab;
+        *self.next_free(slab, index) = (*header).free;
+        (*header).free = index as u16;
+        let in_use = (*header).in_use as usize;
+        (*header).in_use -= 1;
+        self.move_slab(slab, in_use, in_use - 1);
+        self.objects -= 1;
+
+        if in_use == 1 && self.empty_slabs > EMPTY_SLABS_KEPT {
+            self.release(pages, slab, &destroy);
+        }
+    }
+
+    /// Give back every empty slab to `pages`. `destroy` is run on their
+    /// objects.
+    pub fn shrink(&mut self, pages: &mut impl PageSource, destroy: impl Fn(*mut u8)) {
+        while self.empty != NO_SLAB {
+            unsafe { self.release(pages, self.empty, &destroy) };
+        }
+    }
+
+    /// Compute the layout of the slabs, on first use.
+    fn layout(&mut self) {
+        if self.capacity > 0 {
+            return;
+        }
+        assert!(
+            self.align.is_power_of_two() && self.align <= PAGE_SIZE,
+            "invalid alignment of cache {}",
+            self.name
+        );
+        self.stride = align_up(self.size.max(1), self.align);
+        for order in 0..=MAX_SLAB_ORDER {
+            let (capacity, offset) = Self::fit(PAGE_SIZE << order, self.stride, self.align);
+            self.order = order;
+            self.capacity = capacity;
+            self.offset = offset;
+            if capacity >= MIN_OBJECTS {
+                break;
+            }
+        }
+        assert!(
+            self.capacity > 0,
+            "objects of cache {} too large",
+            self.name
+        );
+    }
+
+    /// Returns the number of objects a slab of `slab_size` bytes holds, and
+    /// the offset of the first one.
+    fn fit(slab_size: usize, stride: usize, align: usize) -> (usize, usize) {
+        let header = mem::size_of::<Slab>();
+        let free_index = mem::size_of::<u16>();
+        let mut capacity = ((slab_size - header) / (stride + free_index)).min(NO_OBJECT as usize);
+        loop {
+            let offset = align_up(header + capacity * free_index, align);
+            if capacity == 0 || offset + capacity * stride <= slab_size {
+                return (capacity, offset);
+            }
+            capacity -= 1;
+        }
+    }
+
+    /// Allocate a new slab, in the empty list.
+    fn grow(&mut self, pages: &mut impl PageSource, construct: impl Fn(*mut u8)) -> Option<usize> {
+        let slab = pages.alloc_pages(self.order)?.as_ptr() as usize;
+        assert_eq!(
+            slab & ((PAGE_SIZE << self.order) - 1),
+            0,
+            "slab not aligned to its size"
+        );
+        unsafe {
+            (slab as *mut Slab).write(Slab {
+                next: NO_SLAB,
+                prev: NO_SLAB,
+                free: 0,
+                in_use: 0,
+            });
+            for index in 0..self.capacity {
+                let next = if index + 1 < self.capacity {
+                    index as u16 + 1
+                } else {
+                    NO_OBJECT
+                };
+                self.next_free(slab, index).write(next);
+                construct(self.object(slab, index));
+            }
+            self.push(slab, SlabList::Empty);
+        }
+        self.slabs += 1;
+        Some(slab)
+    }
+
+    /// Give back the empty `slab` to `pages`.
+    unsafe fn release(
+        &mut self,
+        pages: &mut impl PageSource,
+        slab: usize,
+        destroy: &impl Fn(*mut u8),
+    ) {
+        self.remove(slab, SlabList::Empty);
+        for index in 0..self.capacity {
+            destroy(self.object(slab, index));
+        }
+        pages.free_pages(NonNull::new_unchecked(slab as *mut u8), self.order);
+        self.slabs -= 1;
+    }
+
+    /// Returns the list of the slabs with `in_use` objects in use.
+    fn list_of(&self, in_use: usize) -> SlabList {
+        if in_use == 0 {
+            SlabList::Empty
+        } else if in_use == self.capacity {
+            SlabList::Full
+        } else {
+            SlabList::Partial
+        }
+    }
+
+    /// Returns the head of `list`.
+    fn head(&mut self, list: SlabList) -> &mut usize {
+        match list {
+            SlabList::Empty => &mut self.empty,
+            SlabList::Partial => &mut self.partial,
+            SlabList::Full => &mut self.full,
+        }
+    }
+
+    /// Move `slab` to its list after its objects in use went from `from` to
+    /// `to`.
+    unsafe fn move_slab(&mut self, slab: usize, from: usize, to: usize) {
+        let (from, to) = (self.list_of(from), self.list_of(to));
+        if from != to {
+            self.remove(slab, from);
+            self.push(slab, to);
+        }
+    }
+
+    /// Insert `slab` at the head of `list`.
+    unsafe fn push(&mut self, slab: usize, list: SlabList) {
+        let head = *self.head(list);
+        let header = slab as *mut Slab;
+        (*header).next = head;
+        (*header).prev = NO_SLAB;
+        if head != NO_SLAB {
+            (*(head as *mut Slab)).prev = slab;
+        }
+        *self.head(list) = slab;
+        if list == SlabList::Empty {
+            self.empty_slabs += 1;
+        }
+    }
+
+    /// Remove `slab` from `list`.
+    unsafe fn remove(&mut self, slab: usize, list: SlabList) {
+        let header = slab as *mut Slab;
+        let (next, prev) = ((*header).next, (*header).prev);
+        if prev == NO_SLAB {
+            *self.head(list) = next;
+        } else {
+            (*(prev as *mut Slab)).next = next;
+        }
+        if next != NO_SLAB {
+            (*(next as *mut Slab)).prev = prev;
+        }
+        if list == SlabList::Empty {
+            self.empty_slabs -= 1;
+        }
+    }
+
+    /// Returns the entry of object `index` in the list of free objects of
+    /// `slab`.
+    fn next_free(&self, slab: usize, index: usize) -> *mut u16 {
+        (slab + mem::size_of::<Slab>() + index * mem::size_of::<u16>()) as *mut u16
+    }
+
+    /// Returns the object `index` of `slab`.
+    fn object(&self, slab: usize, index: usize) -> *mut u8 {
+        (slab + self.offset + index * self.stride) as *mut u8
+    }
+}
+
+// ! ------------- size classes -------------
+
+/// A slab allocator with a cache per size class, for the heap.
+///
+/// Empty slabs are given back to the page source, unlike the blocks of the
+/// `FixedSizeBlockAllocator`.
+pub struct SlabAllocator<P: PageSource> {
+    caches: [SlabCache; SIZE_CLASSES.len()],
+    pages: P,
+}
+
+impl<P: PageSource> SlabAllocator<P> {
+    /// Create an allocator taking its slabs from `pages`.
+    pub const fn new(pages: P) -> Self {
+        SlabAllocator {
+            caches: [
+                SlabCache::new("size-8", 8, 8),
+                SlabCache::new("size-16", 16, 16),
+                SlabCache::new("size-32", 32, 32),
+                SlabCache::new("size-64", 64, 64),
+                SlabCache::new("size-128", 128, 128),
+                SlabCache::new("size-256", 256, 256),
+                SlabCache::new("size-512", 512, 512),
+                SlabCache::new("size-1024", 1024, 1024),
+                SlabCache::new("size-2048", 2048, 2048),
+            ],
+            pages,
+        }
+    }
+
+    /// Returns whether allocations with `layout` fit in a size class.
+    pub fn fits(layout: &Layout) -> bool {
+        class_index(layout).is_some()
+    }
+
+    /// Allocate an object for `layout`.
+    ///
+    /// Returns a null pointer if it fits in no size class or no slab can be
+    /// allocated.
+    pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        match class_index(&layout) {
+            Some(index) => self.caches[index].alloc(&mut self.pages, |_| ()),
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Free the object at `ptr`, allocated with the same layout.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr`
+    /// was allocated by `alloc` with `layout`, and is unused.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let index = class_index(&layout).expect("layout fits in no size class");
+        self.caches[index].free(&mut self.pages, ptr, |_| ());
+    }
+
+    /// Give back every empty slab.
+    pub fn shrink(&mut self) {
+        for cache in self.caches.iter_mut() {
+            cache.shrink(&mut self.pages, |_| ());
+        }
+    }
+
+    /// Returns the usage statistics of each size class.
+    pub fn stats(&self) -> [CacheStats; SIZE_CLASSES.len()] {
+        let mut stats = [CacheStats::default(); SIZE_CLASSES.len()];
+        for (stats, cache) in stats.iter_mut().zip(self.caches.iter()) {
+            *stats = cache.stats();
+        }
+        stats
+    }
+}
+
+/// Choose the size class for the given layout.
+///
+/// Returns an index into the `SIZE_CLASSES` array.
+fn class_index(layout: &Layout) -> Option<usize> {
+    let required_size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&s| s >= required_size)
+}
+
+// ! ------------- object caches -------------
+
+/// A cache of objects of type `T`, taking its slabs from `P`.
+///
+/// The objects are constructed once, when their slab is allocated, and
+/// dropped when it is given back : in between, each object keeps the state it
+/// was freed in.
+pub struct ObjectCache<T: Send + 'static, P: PageSource + Send> {
+    inner: Locked<Cache<P>>,
+    constructor: fn() -> T,
+}
+
+/// The locked part of an `ObjectCache`.
+struct Cache<P> {
+    slabs: SlabCache,
+    pages: P,
+}
+
+impl<T: Send + 'static, P: PageSource + Send> ObjectCache<T, P> {
+    /// Create an empty cache named `name`, whose objects are created with
+    /// `constructor`, which must not use the cache.
+    pub const fn new(name: &'static str, constructor: fn() -> T, pages: P) -> Self {
+        ObjectCache {
+            inner: Locked::new(Cache {
+                slabs: SlabCache::new(name, mem::size_of::<T>(), mem::align_of::<T>()),
+                pages,
+            }),
+            constructor,
+        }
+    }
+
+    /// Take an object, in the state it was freed in or just constructed.
+    ///
+    /// Returns `None` if no slab can be allocated.
+    pub fn alloc(&self) -> Option<NonNull<T>> {
+        let constructor = self.constructor;
+        let mut inner = self.inner.lock();
+        let Cache { slabs, pages } = &mut *inner;
+        let object = slabs.alloc(pages, |object| unsafe {
+            (object as *mut T).write(constructor());
+        });
+        NonNull::new(object as *mut T)
+    }
+
+    /// Give back `object`.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `object`
+    /// was taken from this cache with `alloc`, and is unused.
+    pub unsafe fn free(&self, object: NonNull<T>) {
+        let mut inner = self.inner.lock();
+        let Cache { slabs, pages } = &mut *inner;
+        slabs.free(pages, object.as_ptr() as *mut u8, |object| {
+            ptr::drop_in_place(object as *mut T)
+        });
+    }
+
+    /// Give back every empty slab.
+    pub fn shrink(&self) {
+        let mut inner = self.inner.lock();
+        let Cache { slabs, pages } = &mut *inner;
+        slabs.shrink(pages, |object| unsafe {
+            ptr::drop_in_place(object as *mut T)
+        });
+    }
+
+    /// Returns the usage statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.inner.lock().slabs.stats()
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_object_cache() {
+    use crate::memory::heap::HeapPages;
+    use alloc::vec::Vec;
+
+    static CACHE: ObjectCache<[u64; 8], HeapPages> = ObjectCache::new("test", || [7; 8], HeapPages);
+
+    serial_print!("test_object_cache... ");
+    // enough objects for two slabs
+    let objects: Vec<_> = (0..100).map(|_| CACHE.alloc().unwrap()).collect();
+    assert!(objects
+        .iter()
+        .all(|object| unsafe { *object.as_ptr() } == [7; 8]));
+    let stats = CACHE.stats();
+    assert_eq!(stats.objects, 100);
+    assert_eq!(stats.slabs, 2);
+
+    // a freed object keeps its state
+    unsafe {
+        *objects[0].as_ptr() = [1; 8];
+        CACHE.free(objects[0]);
+    }
+    let object = CACHE.alloc().unwrap();
+    assert_eq!(unsafe { *object.as_ptr() }, [1; 8]);
+
+    // empty slabs are given back
+    for &object in objects.iter() {
+        unsafe { CACHE.free(object) };
+    }
+    let stats = CACHE.stats();
+    assert_eq!(stats.objects, 0);
+    assert_eq!(stats.slabs, EMPTY_SLABS_KEPT);
+    CACHE.shrink();
+    assert_eq!(CACHE.stats().slabs, 0);
+    serial_println!("[ok]");
+}
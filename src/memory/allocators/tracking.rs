@@ -0,0 +1,113 @@
+//! This module contains the `LeakTracker`, which records the live allocations
+//! of the kernel heap, to find the ones which are never freed.
+//!
+//! It is only built with the `heap_leak_tracking` feature. The callers of an
+//! allocation are found by `backtrace::trace`, which needs frame pointers.
+//!
+
+// internal crate
+use super::backtrace::{self, TRACE_DEPTH};
+use crate::serial_println;
+
+/// Maximum number of live allocations recorded, the others are only counted.
+pub const MAX_TRACKED: usize = 1024;
+
+/// A live allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    /// Address of the allocation.
+    pub ptr: usize,
+    /// Size of the allocation, in bytes.
+    pub size: usize,
+    /// Return addresses of the callers, from the innermost, 0 past the end of
+    /// the walk.
+    pub trace: [usize; TRACE_DEPTH],
+}
+
+impl Allocation {
+    const EMPTY: Allocation = Allocation {
+        ptr: 0,
+        size: 0,
+        trace: [0; TRACE_DEPTH],
+    };
+}
+
+/// Records the live allocations in a fixed table, to need no allocation.
+pub struct LeakTracker {
+    allocations: [Allocation; MAX_TRACKED],
+    len: usize,
+    /// Live allocations not recorded because the table was full.
+    untracked: usize,
+}
+
+impl LeakTracker {
+    /// Create an empty `LeakTracker`.
+    pub const fn new() -> Self {
+        LeakTracker {
+            allocations: [Allocation::EMPTY; MAX_TRACKED],
+            len: 0,
+            untracked: 0,
+        }
+    }
+
+    /// Returns the recorded live allocations.
+    pub fn allocations(&self) -> &[Allocation] {
+        &self.allocations[..self.len]
+    }
+
+    /// Returns the number of live allocations not recorded.
+    pub fn untracked(&self) -> usize {
+        self.untracked
+    }
+
+    /// Record the allocation of `size` bytes at `ptr`, with its callers.
+    ///
+    /// Must be called directly by the allocator, whose frame is the first one
+    /// of the trace.
+    #[inline(never)]
+    pub fn record(&mut self, ptr: *mut u8, size: usize) {
+        if self.len == MAX_TRACKED {
+            self.untracked += 1;
+            return;
+        }
+        self.allocations[self.len] = Allocation {
+            ptr: ptr as usize,
+            size,
+            // skip the return address in this function
+            trace: backtrace::trace(1),
+        };
+        self.len += 1;
+    }
+
+    /// Forget the allocation at `ptr`, which has been freed.
+    pub fn forget(&mut self, ptr: *mut u8) {
+        // recent allocations are usually freed first
+        let found = self.allocations[..self.len]
+            .iter()
+            .rposition(|allocation| allocation.ptr == ptr as usize);
+        match found {
+            Some(index) => {
+                self.len -= 1;
+                self.allocations[index] = self.allocations[self.len];
+            }
+            None => self.untracked = self.untracked.saturating_sub(1),
+        }
+    }
+
+    /// Print the live allocations over serial.
+    pub fn dump(&self) {
+        serial_println!(
+            "[heap] {} live allocations ({} untracked)",
+            self.len + self.untracked,
+            self.untracked
+        );
+        for allocation in self.allocations() {
+            serial_println!(
+                "[heap] {:#x} ({} bytes) allocated from {:x?}",
+                allocation.ptr,
+                allocation.size,
+                allocation.trace
+            );
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! This module contains a buddy allocator of physical memory.
+//!
+//! Memory is handed out in blocks of `2^order` contiguous frames, `order` being
+//! at most `MAX_ORDER`, aligned to their size. A free block is split in two
+//! halves, buddies of each other, to satisfy a smaller request, and a freed
+//! block is merged back with its buddy when both are free.
+//!
+//! The free blocks of each order are kept in a doubly-linked list, stored in
+//! the blocks themselves through the mapping of the physical memory. A bitmap
+//! per order tells whether a block is free, so that a block can be merged with
+//! its buddy in constant time : it is stored in frames taken from the usable
+//! regions at initialization.
+//!
+
+// external crates
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::{ops::Range, ptr};
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// Order of the largest blocks : 4 MiB.
+pub const MAX_ORDER: usize = 10;
+
+/// End of a list of free blocks.
+const NO_BLOCK: u64 = u64::max_value();
+/// Size of a frame.
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+/// Number of bits in a bitmap word.
+const WORD_BITS: u64 = 64;
+
+/// The links of a free block in the list of its order, stored at its start.
+#[repr(C)]
+struct Link {
+    next: u64,
+    prev: u64,
+}
+
+/// Usage statistics of a buddy allocator, in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuddyStats {
+    /// Frames managed by the allocator.
+    pub total: u64,
+    /// Frames currently allocated.
+    pub allocated: u64,
+    /// Number of free blocks of each order.
+    pub free_blocks: [u64; MAX_ORDER + 1],
+}
+
+impl BuddyStats {
+    /// Returns the number of frames which can still be allocated.
+    pub fn free(&self) -> u64 {
+        self.total - self.allocated
+    }
+
+    /// Returns the order of the largest free block, `None` if there is none.
+    pub fn largest_free_order(&self) -> Option<usize> {
+        (0..=MAX_ORDER)
+            .rev()
+            .find(|&order| self.free_blocks[order] > 0)
+    }
+}
+
+/// Returns the smallest order of the blocks holding `count` frames, `None` if
+/// it is greater than `MAX_ORDER`.
+pub fn order_for(count: u64) -> Option<usize> {
+    let order = count.max(1).next_power_of_two().trailing_zeros() as usize;
+    if order <= MAX_ORDER {
+        Some(order)
+    } else {
+        None
+    }
+}
+
+/// A buddy allocator of the usable frames of the bootloader's memory map, or
+/// of a range of them.
+///
+/// It is also a `FrameAllocator` of single frames, blocks of order 0.
+pub struct BuddyAllocator {
+    physical_memory_offset: VirtAddr,
+    /// First frame number of the managed range, aligned to the largest blocks.
+    base: u64,
+    /// Number of frames of the managed range.
+    frames: u64,
+    /// Address of the first free block of each order, `NO_BLOCK` if none.
+    heads: [u64; MAX_ORDER + 1],
+    /// Virtual address of the bitmap of the free blocks of each order.
+    bitmaps: [u64; MAX_ORDER + 1],
+    stats: BuddyStats,
+}
+
+impl BuddyAllocator {
+    /// Create a buddy allocator of the usable frames of the passed memory map.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// passed memory map is valid, that all frames marked as `USABLE` in it are
+    /// really unused, and that the complete physical memory is mapped at
+    /// `physical_memory_offset`.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        Self::init_within(memory_map, physical_memory_offset, 0..u64::max_value())
+            .expect("no usable physical memory")
+    }
+
+    /// Create a buddy allocator of the usable frames of the passed memory map
+    /// lying in the physical address range `range`.
+    ///
+    /// Returns `None` if the range has no usable frames, or not enough
+    /// contiguous ones for the bitmaps.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe for the same reasons as `init`, and because the
+    /// caller must guarantee that no other allocator manages the same frames.
+    pub unsafe fn init_within(
+        memory_map: &'static MemoryMap,
+        physical_memory_offset: VirtAddr,
+        range: Range<u64>,
+    ) -> Option<Self> {
+        let (range_start, range_end) = (
+            (range.start + FRAME_SIZE - 1) / FRAME_SIZE,
+            range.end / FRAME_SIZE,
+        );
+        let usable = || {
+            memory_map
+                .iter()
+                .filter(|region| region.region_type == MemoryRegionType::Usable)
+                .map(move |region| {
+                    region.range.start_frame_number.max(range_start)
+                        ..region.range.end_frame_number.min(range_end)
+                })
+                .filter(|range| range.start < range.end)
+        };
+        let first = usable().map(|range| range.start).min()?;
+        let end = usable().map(|range| range.end).max()?;
+        let base = first & !((1 << MAX_ORDER) - 1);
+        let frames = end - base;
+
+        // the bitmaps, one bit per block of each order
+        let mut words = [0; MAX_ORDER + 1];
+        for (order, count) in words.iter_mut().enumerate() {
+            *count = ((frames >> order) + WORD_BITS - 1) / WORD_BITS;
+        }
+        let bitmap_size: u64 = words.iter().sum::<u64>() * 8;
+        let bitmap_frames = (bitmap_size + FRAME_SIZE - 1) / FRAME_SIZE;
+        let bitmap_start = usable()
+            .find(|range| range.end - range.start >= bitmap_frames)?
+            .start;
+
+        let mut allocator = BuddyAllocator {
+            physical_memory_offset,
+            base,
+            frames,
+            heads: [NO_BLOCK; MAX_ORDER + 1],
+            bitmaps: [0; MAX_ORDER + 1],
+            stats: BuddyStats {
+                total: 0,
+                allocated: 0,
+                free_blocks: [0; MAX_ORDER + 1],
+            },
+        };
+        let mut bitmap = (physical_memory_offset + bitmap_start * FRAME_SIZE).as_u64();
+        ptr::write_bytes(bitmap as *mut u8, 0, bitmap_size as usize);
+        for order in 0..=MAX_ORDER {
+            allocator.bitmaps[order] = bitmap;
+            bitmap += words[order] * 8;
+        }
+
+        for range in usable() {
+            let start = if range.start == bitmap_start {
+                range.start + bitmap_frames
+            } else {
+                range.start
+            };
+            allocator.add_range(start, range.end);
+        }
+        Some(allocator)
+    }
+
+    /// Returns the usage statistics of the allocator.
+    pub fn stats(&self) -> BuddyStats {
+        self.stats
+    }
+
+    /// Allocate a block of `2^order` contiguous frames, aligned to its size.
+    ///
+    /// Returns its first frame.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let current = (order..=MAX_ORDER).find(|&order| self.heads[order] != NO_BLOCK)?;
+        let block = self.heads[current];
+        Some(self.take(block, current, order))
+    }
+
+    /// Allocate a block of `2^order` contiguous frames ending below the
+    /// physical address `limit`.
+    ///
+    /// Unlike `allocate`, the free blocks are searched : it takes linear time.
+    pub fn allocate_below(&mut self, order: usize, limit: u64) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        // the lowest part of a larger block is taken
+        let size = FRAME_SIZE << order;
+        for current in order..=MAX_ORDER {
+            let mut block = self.heads[current];
+            while block != NO_BLOCK {
+                if block + size <= limit {
+                    return Some(self.take(block, current, order));
+                }
+                block = unsafe { (*self.link(block)).next };
+            }
+        }
+        None
+    }
+
+    /// Returns `true` if `frame` lies in the range managed by the allocator.
+    pub fn manages(&self, frame: PhysFrame) -> bool {
+        self.contains(frame.start_address().as_u64(), 0)
+    }
+
+    /// Free the block of `2^order` frames starting at `frame`, merging it with
+    /// its free buddies.
+    ///
+    /// The block must have been allocated with the same order.
+    pub fn deallocate(&mut self, frame: PhysFrame, order: usize) {
+        let mut block = frame.start_address().as_u64();
+        assert!(
+            order <= MAX_ORDER && self.contains(block, order) && !self.is_free(block, order),
+            "invalid deallocation of a physical memory block"
+        );
+        self.stats.allocated -= 1 << order;
+
+        let mut order = order;
+        while order < MAX_ORDER {
+            let buddy = self.buddy(block, order);
+            if !self.contains(buddy, order) || !self.is_free(buddy, order) {
+                break;
+            }
+            self.remove(buddy, order);
+            block = block.min(buddy);
+            order += 1;
+        }
+        self.insert(block, order);
+    }
+
+    /// Take the free block at `block` of order `current`, giving its upper
+    /// halves back down to `order`.
+    ///
+    /// Returns its first frame.
+    fn take(&mut self, block: u64, current: usize, order: usize) -> PhysFrame {
+        self.remove(block, current);
+        let mut current = current;
+        while current > order {
+            current -= 1;
+            self.insert(block + (FRAME_SIZE << current), current);
+        }
+        self.stats.allocated += 1 << order;
+        PhysFrame::containing_address(PhysAddr::new(block))
+    }
+
+    /// Add the frames `start..end`, given as frame numbers, to the free blocks.
+    fn add_range(&mut self, start: u64, end: u64) {
+        let mut frame = start;
+        while frame < end {
+            // the largest aligned block starting at `frame` and fitting
+            let offset = frame - self.base;
+            let mut order = MAX_ORDER;
+            while offset & ((1 << order) - 1) != 0 || frame + (1 << order) > end {
+                order -= 1;
+            }
+            self.insert(frame * FRAME_SIZE, order);
+            self.stats.total += 1 << order;
+            frame += 1 << order;
+        }
+    }
+
+    /// Returns the address of the buddy of the block at `block`.
+    fn buddy(&self, block: u64, order: usize) -> u64 {
+        let base = self.base * FRAME_SIZE;
+        base + ((block - base) ^ (FRAME_SIZE << order))
+    }
+
+    /// Returns `true` if the block at `block` is in the managed range.
+    fn contains(&self, block: u64, order: usize) -> bool {
+        let frame = block / FRAME_SIZE;
+        frame >= self.base && frame + (1 << order) <= self.base + self.frames
+    }
+
+    /// Returns the bitmap word holding the bit of the block at `block`, and the
+    /// mask of this bit.
+    fn bit(&self, block: u64, order: usize) -> (*mut u64, u64) {
+        let index = (block / FRAME_SIZE - self.base) >> order;
+        let word = self.bitmaps[order] + (index / WORD_BITS) * 8;
+        (word as *mut u64, 1 << (index % WORD_BITS))
+    }
+
+    /// Returns `true` if the block at `block` is a free block of this order.
+    fn is_free(&self, block: u64, order: usize) -> bool {
+        let (word, mask) = self.bit(block, order);
+        unsafe { word.read() & mask != 0 }
+    }
+
+    /// Returns the links stored in the free block at `block`.
+    fn link(&self, block: u64) -> *mut Link {
+        (self.physical_memory_offset + block).as_mut_ptr()
+    }
+
+    /// Add the block at `block` to the free blocks of this order.
+    fn insert(&mut self, block: u64, order: usize) {
+        let head = self.heads[order];
+        unsafe {
+            self.link(block).write(Link {
+                next: head,
+                prev: NO_BLOCK,
+            });
+            if head != NO_BLOCK {
+                (*self.link(head)).prev = block;
+            }
+            let (word, mask) = self.bit(block, order);
+            word.write(word.read() | mask);
+        }
+        self.heads[order] = block;
+        self.stats.free_blocks[order] += 1;
+    }
+
+    /// Remove the free block at `block` from the free blocks of this order.
+    fn remove(&mut self, block: u64, order: usize) {
+        unsafe {
+            let Link { next, prev } = self.link(block).read();
+            if prev != NO_BLOCK {
+                (*self.link(prev)).next = next;
+            } else {
+                self.heads[order] = next;
+            }
+            if next != NO_BLOCK {
+                (*self.link(next)).prev = prev;
+            }
+            let (word, mask) = self.bit(block, order);
+            word.write(word.read() & !mask);
+        }
+        self.stats.free_blocks[order] -= 1;
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyAllocator {
+    /// Give back a frame allocated with `allocate_frame`.
+    fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate(frame, 0);
+    }
+}
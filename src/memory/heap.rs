@@ -1,23 +1,442 @@
+//! This module contains the kernel heap.
+//!
+//! The heap starts with `HEAP_SIZE` bytes mapped at `HEAP_START` and grows on
+//! demand : when its allocator runs out of memory, more pages are mapped after
+//! the end of the heap, up to a maximum size. Its virtual memory is reserved
+//! in `KernelRegion::Heap` by `init`. Its pages are mapped eagerly as it grows,
+//! never on a page fault. The allocator is selected by a cargo feature, see
+//! `memory::allocators`.
+//!
+//! Large allocations, of at least `LARGE_SIZE` bytes, are not taken from the
+//! heap but are blocks of the frame allocator, accessed through the mapping of
+//! the physical memory : freeing them gives their frames back at once, so the
+//! heap shrinks when large regions are freed.
+//!
+//! Small allocations are taken from a `SlabAllocator` first, whose slabs are
+//! such blocks too, given back once empty. The kernel can also keep objects of
+//! a type in their own `KernelCache`.
+//!
+//! Both need the kernel page table and the frame allocator stored by
+//! `memory::init`, and are not available to code which holds them locked :
+//! the allocations are then limited to the memory the heap already has.
+//!
+//! Every allocation is counted in the statistics returned by `stats`. With
+//! the `heap_leak_tracking` feature, the live allocations are also recorded
+//! with their callers, and printed by `dump_leaks`.
+//!
+//! With the `heap_debug` feature, the global allocator is a `DebugHeap`
+//! wrapping the kernel heap, which checks the bounds of the allocations and
+//! detects double frees and writes after free.
+//!
+
 // internal function used
-use super::allocators::{fixed_size_blocks::FixedSizeBlockAllocator, Locked};
+#[cfg(feature = "heap_debug")]
+use super::allocators::debug::DebugHeap;
+#[cfg(feature = "heap_leak_tracking")]
+use super::allocators::tracking::LeakTracker;
+use super::{
+    allocators::{
+        slab::{CacheStats, ObjectCache, PageSource, SlabAllocator, SIZE_CLASSES},
+        Counters, HeapAllocator, HeapStats, Locked,
+    },
+    buddy,
+    layout::KernelRegion,
+    vma, ZonedAllocator,
+};
 
 // external crates used
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
     },
-    VirtAddr,
+    PhysAddr, VirtAddr,
 };
 
 // ! ------------- heap allocator -------------
 
-/// Starting point of the heap.
-pub const HEAP_START: usize = 0x_4444_4444_0000;
-/// Size of memory used for the heap.
+/// Starting point of the heap : the start of its kernel region.
+pub const HEAP_START: usize = KernelRegion::Heap.start() as usize;
+/// Size of the virtual memory reserved for the heap, which it cannot grow
+/// beyond.
+pub const HEAP_RESERVED: usize = 1 << 30; // 1 GiB
+/// Size of memory mapped for the heap by `init`.
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Default maximum size of the heap, large allocations included.
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+/// Size from which allocations are taken from the frame allocator.
+pub const LARGE_SIZE: usize = 4 * PAGE_SIZE; // 16 KiB
+
+/// Size of a page.
+const PAGE_SIZE: usize = 4096;
+/// Minimum number of bytes mapped each time the heap grows.
+const GROWTH_SIZE: usize = 16 * PAGE_SIZE; // 64 KiB
+
+/// The allocator of the heap, selected by the `allocator_*` features.
+#[cfg(feature = "allocator_fixed_size_blocks")]
+type Heap = super::allocators::fixed_size_blocks::FixedSizeBlockAllocator;
+#[cfg(feature = "allocator_linked_list")]
+type Heap = super::allocators::linked_list::LinkedListAllocator;
+#[cfg(feature = "allocator_bump")]
+type Heap = super::allocators::bump::BumpAllocator;
+
+#[cfg(not(any(
+    feature = "allocator_fixed_size_blocks",
+    feature = "allocator_linked_list",
+    feature = "allocator_bump"
+)))]
+compile_error!("no heap allocator selected, enable one of the `allocator_*` features");
+
+#[cfg(any(
+    all(
+        feature = "allocator_fixed_size_blocks",
+        feature = "allocator_linked_list"
+    ),
+    all(feature = "allocator_fixed_size_blocks", feature = "allocator_bump"),
+    all(feature = "allocator_linked_list", feature = "allocator_bump")
+))]
+compile_error!("several heap allocators selected, enable only one of the `allocator_*` features");
 
+#[cfg_attr(not(feature = "heap_debug"), global_allocator)]
+static ALLOCATOR: KernelHeap = KernelHeap::new();
+
+/// The global allocator in debug mode, taking its memory from `ALLOCATOR`.
+#[cfg(feature = "heap_debug")]
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static DEBUG_ALLOCATOR: DebugHeap<GlobalHeap> = DebugHeap::new(GlobalHeap);
+
+/// A cache of objects of type `T`, taking its slabs from the kernel heap.
+pub type KernelCache<T> = ObjectCache<T, HeapPages>;
+
+/// The global allocator of the kernel.
+///
+/// Small allocations are taken from slabs, large ones are blocks of frames,
+/// and the others, or all of them if the frame allocator is not available,
+/// from the selected heap allocator, whose heap grows when it runs out of
+/// memory.
+pub struct KernelHeap {
+    slabs: Locked<SlabAllocator<HeapPages>>,
+    heap: Locked<Heap>,
+    /// Locked after `slabs` and before `heap` when both are.
+    state: Locked<HeapState>,
+}
+
+/// The kernel heap, as the allocator wrapped by the `DebugHeap`.
+#[cfg(feature = "heap_debug")]
+pub struct GlobalHeap;
+
+/// The pages of the slabs of the kernel : blocks of frames, like large
+/// allocations.
+pub struct HeapPages;
+
+/// The memory used by the heap.
+struct HeapState {
+    /// End of the pages mapped for the heap.
+    end: usize,
+    /// Bytes of the large allocations.
+    large: usize,
+    /// Maximum number of bytes of the heap, large allocations included.
+    max_size: usize,
+    /// Large allocations freed while the frame allocator was locked, to give
+    /// back on the next large allocation or free.
+    pending: Option<&'static mut PendingFree>,
+    /// The statistics of all the allocations, counting their requested size.
+    counters: Counters,
+    /// The live allocations and their callers.
+    #[cfg(feature = "heap_leak_tracking")]
+    leaks: LeakTracker,
+}
+
+/// A freed large allocation waiting to be given back, stored in itself.
+struct PendingFree {
+    order: usize,
+    next: Option<&'static mut PendingFree>,
+}
+
+impl HeapState {
+    /// Returns the number of bytes used by the heap.
+    fn size(&self) -> usize {
+        self.end - HEAP_START + self.large
+    }
+
+    /// Returns whether `addr` is in the pages mapped for the heap.
+    fn contains(&self, addr: usize) -> bool {
+        (HEAP_START..self.end).contains(&addr)
+    }
+
+    /// Give back the large allocations freed while the frame allocator was
+    /// locked.
+    fn free_pending(&mut self, frame_allocator: &mut ZonedAllocator) {
+        while let Some(pending) = self.pending.take() {
+            self.pending = pending.next.take();
+            let order = pending.order;
+            frame_allocator.deallocate(large_frame(pending as *mut PendingFree as *mut u8), order);
+        }
+    }
+}
+
+impl KernelHeap {
+    /// Create an empty heap, usable once `init` has been called.
+    pub const fn new() -> Self {
+        KernelHeap {
+            slabs: Locked::new(SlabAllocator::new(HeapPages)),
+            heap: Locked::new(Heap::new()),
+            state: Locked::new(HeapState {
+                end: HEAP_START,
+                large: 0,
+                max_size: HEAP_MAX_SIZE,
+                pending: None,
+                counters: Counters::new(),
+                #[cfg(feature = "heap_leak_tracking")]
+                leaks: LeakTracker::new(),
+            }),
+        }
+    }
+
+    /// Map pages after the end of the heap for at least `min` more bytes, and
+    /// give them to the allocator.
+    ///
+    /// Returns whether the heap grew.
+    fn grow(&self, state: &mut HeapState, min: usize) -> bool {
+        let available = state.max_size.saturating_sub(state.size()) & !(PAGE_SIZE - 1);
+        let available = available.min(HEAP_START + HEAP_RESERVED - state.end);
+        let size = (min.max(GROWTH_SIZE) + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let size = size.min(available);
+        if size < min {
+            return false;
+        }
+
+        let start = state.end;
+        let mapped = super::try_with_kernel_memory(|mapper, frame_allocator| {
+            let pages = Page::range(page(start), page(start + size));
+            pages
+                .take_while(|&page| map_page(mapper, frame_allocator, page).is_ok())
+                .count()
+        });
+        let mapped = mapped.unwrap_or(0) * PAGE_SIZE;
+        if mapped == 0 {
+            return false;
+        }
+        unsafe { self.heap.lock().extend(mapped) };
+        state.end += mapped;
+        true
+    }
+
+    /// Allocate a block of `2^order` frames for a large allocation.
+    ///
+    /// Returns a null pointer if there is no memory left or the frame
+    /// allocator is locked.
+    fn alloc_large(&self, state: &mut HeapState, order: usize) -> *mut u8 {
+        let size = PAGE_SIZE << order;
+        if state.size() + size > state.max_size {
+            return ptr::null_mut();
+        }
+        let frame = super::try_with_frame_allocator(|frame_allocator| {
+            state.free_pending(frame_allocator);
+            frame_allocator.allocate(order)
+        });
+        match frame {
+            Some(Some(frame)) => {
+                state.large += size;
+                super::phys_to_virt(frame.start_address()).as_mut_ptr()
+            }
+            _ => ptr::null_mut(),
+        }
+    }
+
+    /// Free the large allocation at `ptr`, of `2^order` frames.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr`
+    /// was allocated by `alloc_large` with the same order, and is unused.
+    unsafe fn dealloc_large(&self, state: &mut HeapState, ptr: *mut u8, order: usize) {
+        state.large -= PAGE_SIZE << order;
+        let freed = super::try_with_frame_allocator(|frame_allocator| {
+            state.free_pending(frame_allocator);
+            frame_allocator.deallocate(large_frame(ptr), order);
+        });
+        if freed.is_none() {
+            // the caller holds the frame allocator : give the frames back later
+            let pending = ptr as *mut PendingFree;
+            pending.write(PendingFree {
+                order,
+                next: state.pending.take(),
+            });
+            state.pending = Some(&mut *pending);
+        }
+    }
+}
+
+impl PageSource for HeapPages {
+    fn alloc_pages(&mut self, order: usize) -> Option<NonNull<u8>> {
+        NonNull::new(ALLOCATOR.alloc_large(&mut ALLOCATOR.state.lock(), order))
+    }
+
+    unsafe fn free_pages(&mut self, pages: NonNull<u8>, order: usize) {
+        ALLOCATOR.dealloc_large(&mut ALLOCATOR.state.lock(), pages.as_ptr(), order);
+    }
+}
+
+impl KernelHeap {
+    /// Allocate memory for `layout`, from the slabs, the frame allocator or
+    /// the heap.
+    unsafe fn allocate(&self, layout: Layout) -> *mut u8 {
+        if let Some(order) = large_order(&layout) {
+            let ptr = self.alloc_large(&mut self.state.lock(), order);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        } else if SlabAllocator::<HeapPages>::fits(&layout) {
+            let ptr = self.slabs.lock().alloc(layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+        loop {
+            let ptr = self.heap.alloc(layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+            // out of memory : grow the heap and retry
+            if !self.grow(&mut self.state.lock(), layout.size() + layout.align()) {
+                return ptr::null_mut();
+            }
+        }
+    }
+}
+
+/// Returns the order of the block of frames of a large allocation with the
+/// given layout, `None` if it must be taken from the heap.
+fn large_order(layout: &Layout) -> Option<usize> {
+    if layout.size() < LARGE_SIZE || layout.align() > PAGE_SIZE {
+        return None;
+    }
+    buddy::order_for(((layout.size() + PAGE_SIZE - 1) / PAGE_SIZE) as u64)
+}
+
+/// Returns the first frame of the large allocation at `ptr`.
+fn large_frame(ptr: *mut u8) -> PhysFrame {
+    let addr = ptr as u64 - super::physical_memory_offset().as_u64();
+    PhysFrame::containing_address(PhysAddr::new(addr))
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.allocate(layout);
+        let mut state = self.state.lock();
+        state.counters.alloc(ptr, layout.size(), layout.size());
+        #[cfg(feature = "heap_leak_tracking")]
+        {
+            if !ptr.is_null() {
+                state.leaks.record(ptr, layout.size());
+            }
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let in_heap = {
+            let mut state = self.state.lock();
+            state.counters.dealloc(layout.size());
+            #[cfg(feature = "heap_leak_tracking")]
+            state.leaks.forget(ptr);
+            state.contains(ptr as usize)
+        };
+        // it may have been taken from the heap if no frame was available
+        if in_heap {
+            return self.heap.dealloc(ptr, layout);
+        }
+        match large_order(&layout) {
+            Some(order) => self.dealloc_large(&mut self.state.lock(), ptr, order),
+            None => self.slabs.lock().dealloc(ptr, layout),
+        }
+    }
+}
+
+#[cfg(feature = "heap_debug")]
+unsafe impl GlobalAlloc for GlobalHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATOR.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATOR.dealloc(ptr, layout)
+    }
+}
+
+/// Returns the number of bytes used by the heap : its mapped pages, and the
+/// blocks of frames of its large allocations and slabs.
+pub fn size() -> usize {
+    ALLOCATOR.state.lock().size()
+}
+
+/// Returns the usage statistics of the whole heap : `size` is its number of
+/// bytes, see `size`, and the allocations are counted with their requested
+/// size.
+pub fn stats() -> HeapStats {
+    let state = ALLOCATOR.state.lock();
+    state.counters.stats(state.size())
+}
+
+/// Returns the usage statistics of the heap allocator, without the slabs and
+/// the large allocations.
+pub fn allocator_stats() -> HeapStats {
+    ALLOCATOR.heap.lock().stats()
+}
+
+/// Print the live allocations of the heap and their callers over serial.
+#[cfg(feature = "heap_leak_tracking")]
+pub fn dump_leaks() {
+    ALLOCATOR.state.lock().leaks.dump();
+}
+
+/// Give back the allocations freed in debug mode, checking that they were not
+/// written after being freed.
+#[cfg(feature = "heap_debug")]
+pub fn flush_quarantine() {
+    DEBUG_ALLOCATOR.flush();
+}
+
+/// Returns the usage statistics of the slabs of each size class.
+pub fn slab_stats() -> [CacheStats; SIZE_CLASSES.len()] {
+    ALLOCATOR.slabs.lock().stats()
+}
+
+/// Give back the empty slabs of the size classes.
+pub fn shrink() {
+    ALLOCATOR.slabs.lock().shrink();
+}
+
+/// Set the maximum number of bytes of the heap, large allocations included,
+/// `HEAP_MAX_SIZE` by default.
+///
+/// The memory already used is kept if it is above.
+pub fn set_max_size(max_size: usize) {
+    ALLOCATOR.state.lock().max_size = max_size;
+}
+
+/// Returns the page containing the address `addr`.
+fn page(addr: usize) -> Page {
+    Page::containing_address(VirtAddr::new(addr as u64))
+}
+
+/// Map `page` to a new frame, writable.
+fn map_page(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page: Page,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe {
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    }
+    Ok(())
+}
 
 // ! ------------- heap init -------------
 
@@ -37,18 +456,18 @@ pub fn init(
     };
 
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
-        }
+        map_page(mapper, frame_allocator, page)?;
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.heap.lock().init(HEAP_START, HEAP_SIZE);
     }
+    ALLOCATOR.state.lock().end = HEAP_START + HEAP_SIZE;
+
+    // the tree of the reserved ranges needs the heap
+    let start = VirtAddr::new(HEAP_START as u64);
+    vma::reserve_at(KernelRegion::Heap, start, HEAP_RESERVED as u64, "heap")
+        .expect("heap range already reserved");
 
     Ok(())
 }
@@ -0,0 +1,90 @@
+//! This module describes the layout of the virtual address space.
+//!
+//! The kernel lives in the lower part of the address space : the bootloader maps
+//! the kernel and the physical memory in the first level 4 entries, and the
+//! kernel space above the user space is split in regions, one for each kind of
+//! mapping, see `KernelRegion`. Ranges of these regions are reserved with
+//! `memory::vma`.
+//!
+//! The user space is a dedicated range of level 4 entries : they are the only
+//! ones owned by each `AddressSpace`, every other entry is shared with the
+//! kernel page table.
+//!
+
+// external crates
+use core::ops::Range;
+
+/// Size of the memory covered by a level 4 entry.
+const LEVEL_4_ENTRY_SIZE: u64 = 1 << 39;
+
+/// Start of the user space.
+pub const USER_SPACE_START: u64 = 0x_0000_1000_0000_0000;
+/// End of the user space, excluded.
+pub const USER_SPACE_END: u64 = 0x_0000_4000_0000_0000;
+
+/// Start of the range searched for anonymous mappings without address hint.
+pub const USER_MMAP_START: u64 = 0x_0000_2000_0000_0000;
+
+/// Top of the stack of the first thread of a user program.
+pub const USER_STACK_TOP: u64 = USER_SPACE_END;
+
+/// Start of the kernel space above the user space.
+pub const KERNEL_SPACE_START: u64 = USER_SPACE_END;
+/// Size of each kernel region : 16 level 4 entries, 8 TiB.
+pub const KERNEL_REGION_SIZE: u64 = 16 * LEVEL_4_ENTRY_SIZE;
+
+/// Level 4 entries covering the user space.
+pub const USER_LEVEL_4_ENTRIES: Range<usize> = Range {
+    start: (USER_SPACE_START / LEVEL_4_ENTRY_SIZE) as usize,
+    end: (USER_SPACE_END / LEVEL_4_ENTRY_SIZE) as usize,
+};
+
+/// Returns `true` if the range `start..start + len` lies entirely in the user
+/// space.
+pub fn is_user_range(start: u64, len: u64) -> bool {
+    match start.checked_add(len) {
+        Some(end) => start >= USER_SPACE_START && end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// A region of the kernel space, dedicated to a kind of mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KernelRegion {
+    /// The kernel heap.
+    Heap,
+    /// The kernel stacks of the threads.
+    Stacks,
+    /// The registers of the devices.
+    Mmio,
+    /// The code and data of the kernel modules.
+    Modules,
+}
+
+impl KernelRegion {
+    /// Every region, from the lowest one.
+    pub const ALL: [KernelRegion; 4] = [
+        KernelRegion::Heap,
+        KernelRegion::Stacks,
+        KernelRegion::Mmio,
+        KernelRegion::Modules,
+    ];
+
+    /// Returns the start of the region.
+    pub const fn start(self) -> u64 {
+        KERNEL_SPACE_START + self as u64 * KERNEL_REGION_SIZE
+    }
+
+    /// Returns the end of the region, excluded.
+    pub const fn end(self) -> u64 {
+        self.start() + KERNEL_REGION_SIZE
+    }
+
+    /// Returns the region containing `addr`, if any.
+    pub fn containing(addr: u64) -> Option<KernelRegion> {
+        KernelRegion::ALL
+            .iter()
+            .copied()
+            .find(|region| (region.start()..region.end()).contains(&addr))
+    }
+}
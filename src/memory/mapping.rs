@@ -6,8 +6,8 @@ use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 use x86_64::{
     registers::control::Cr3,
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame,
-        Size4KiB,
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page,
+        PageSize, PageTable, PhysFrame, Size4KiB,
     },
     PhysAddr, VirtAddr,
 };
@@ -45,14 +45,20 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
 
 // ! ------------- stack bounds -------------
 
-use core::sync::atomic::{AtomicU64, Ordering};
-use x86_64::structures::paging::PageTableFlags as Flags;
+use super::{layout::KernelRegion, vma};
+use x86_64::structures::paging::{mapper::UnmapError, PageTableFlags as Flags};
+
+/// Number of unmapped pages below each stack, so that an overflow faults
+/// instead of overwriting other memory.
+pub const STACK_GUARD_PAGES: u64 = 1;
 
 /// Represents bounds of a process's stack.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct StackBounds {
     start: VirtAddr,
     end: VirtAddr,
+    /// Number of guard pages below `start`, reserved but not mapped.
+    guard_pages: u64,
 }
 
 impl StackBounds {
@@ -62,46 +68,155 @@ impl StackBounds {
     pub fn end(&self) -> VirtAddr {
         self.end
     }
+
+    /// Returns the number of guard pages below the stack.
+    pub fn guard_pages(&self) -> u64 {
+        self.guard_pages
+    }
+
+    /// Returns the start of the guard pages, which is the start of the
+    /// reserved range.
+    pub fn guard_start(&self) -> VirtAddr {
+        self.start - self.guard_pages * Page::<Size4KiB>::SIZE
+    }
+
+    /// Returns the number of mapped pages of the stack.
+    pub fn size_in_pages(&self) -> u64 {
+        (self.end - self.start) / Page::<Size4KiB>::SIZE
+    }
+
+    /// Returns `true` if `addr` is in the guard pages : an access there is a
+    /// stack overflow.
+    pub fn is_guard_page(&self, addr: VirtAddr) -> bool {
+        self.guard_start() <= addr && addr < self.start
+    }
+
+    /// Returns the mapped pages of the stack.
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end),
+        )
+    }
 }
 
 /// Returns a stack for a new thread.
-pub fn alloc_stack(
+///
+/// The stack and its guard pages are reserved in `KernelRegion::Stacks`.
+/// Running out of virtual memory is reported as a frame allocation failure.
+/// On failure, nothing is left mapped or reserved.
+pub fn alloc_stack<A>(
     size_in_pages: u64,
     mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<StackBounds, MapToError<Size4KiB>> {
-    static STACK_ALLOC_NEXT: AtomicU64 = AtomicU64::new(0x_5555_5555_0000);
-
-    let guard_page_start = STACK_ALLOC_NEXT.fetch_add(
-        (size_in_pages + 1) * Page::<Size4KiB>::SIZE,
-        Ordering::SeqCst,
-    );
-    let guard_page = Page::from_start_address(VirtAddr::new(guard_page_start))
-        .expect("`STACK_ALLOC_NEXT` not page aligned");
-
-    let stack_start = guard_page + 1;
+    frame_allocator: &mut A,
+) -> Result<StackBounds, MapToError<Size4KiB>>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    let area = vma::reserve(
+        KernelRegion::Stacks,
+        (size_in_pages + STACK_GUARD_PAGES) * Page::<Size4KiB>::SIZE,
+        "stack",
+    )
+    .map_err(|_| MapToError::FrameAllocationFailed)?;
+    let guard_page = Page::from_start_address(area.start).expect("area not page aligned");
+
+    let stack_start = guard_page + STACK_GUARD_PAGES;
     let stack_end = stack_start + size_in_pages;
     let flags = Flags::PRESENT | Flags::WRITABLE;
     for page in Page::range(stack_start, stack_end) {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+        let mapped = match frame_allocator.allocate_frame() {
+            Some(frame) => unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
+                .map(|flush| flush.flush())
+                .map_err(|error| {
+                    frame_allocator.deallocate_frame(frame);
+                    error
+                }),
+            None => Err(MapToError::FrameAllocationFailed),
+        };
+        if let Err(error) = mapped {
+            // give back the pages mapped so far
+            let stack = StackBounds {
+                start: stack_start.start_address(),
+                end: page.start_address(),
+                guard_pages: STACK_GUARD_PAGES,
+            };
+            unsafe { free_stack(stack, mapper, frame_allocator) }.expect("stack pages not mapped");
+            return Err(error);
         }
     }
     Ok(StackBounds {
         start: stack_start.start_address(),
         end: stack_end.start_address(),
+        guard_pages: STACK_GUARD_PAGES,
     })
 }
 
+/// Free a stack returned by `alloc_stack` : unmap its pages, give back their
+/// frames, and free its range, guard pages included.
+///
+/// The page tables emptied are kept, to be used by the next stacks.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the stack
+/// is not used anymore, and was allocated in the page table of `mapper`.
+pub unsafe fn free_stack(
+    stack: StackBounds,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> Result<(), UnmapError> {
+    for page in stack.pages() {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        frame_deallocator.deallocate_frame(frame);
+    }
+    vma::free(stack.guard_start()).expect("stack range not reserved");
+    Ok(())
+}
+
 // ! ------------- boot info frame allocator -------------
 
+/// End of the list of deallocated frames.
+const NO_FRAME: u64 = u64::max_value();
+
+/// Usage statistics of a frame allocator, in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Usable frames of the memory map.
+    pub total: u64,
+    /// Frames currently allocated.
+    pub allocated: u64,
+    /// Frames deallocated and not reused yet, part of the free ones.
+    pub recycled: u64,
+}
+
+impl FrameStats {
+    /// Returns the number of frames which can still be allocated.
+    pub fn free(&self) -> u64 {
+        self.total - self.allocated
+    }
+}
+
 /// A `FrameAllocator` that returns usable frames from the bootloader's memory map.
+///
+/// Frames are handed out in order, region after region, and deallocated frames
+/// are kept in a list and reused first. The list is stored in the frames
+/// themselves, through the mapping of the physical memory : both operations
+/// take constant time and no heap.
+///
+/// The kernel uses a `ZonedAllocator` of buddy allocators, which can also
+/// allocate contiguous frames : this one is a simpler allocator, usable on its
+/// own.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
-    next: usize,
+    /// Index of the region frames are currently taken from.
+    region: usize,
+    /// Address of the next frame never allocated, in `region` or after it.
+    next: u64,
+    /// Address of the last deallocated frame, `NO_FRAME` if none.
+    recycled: u64,
+    stats: FrameStats,
 }
 
 impl BootInfoFrameAllocator {
@@ -113,33 +228,77 @@ impl BootInfoFrameAllocator {
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let total = memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .map(|region| region.range.end_frame_number - region.range.start_frame_number)
+            .sum();
         BootInfoFrameAllocator {
             memory_map,
+            region: 0,
             next: 0,
+            recycled: NO_FRAME,
+            stats: FrameStats {
+                total,
+                allocated: 0,
+                recycled: 0,
+            },
         }
     }
 
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        let frames = frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)));
-        // we know that the frames are really unused
-        // FIXME verify that `PhysFrame::from(f)` is equivalent to pre 9.6's `UnusedPhysFrame::new(f)`
-        frames.map(|f| PhysFrame::from(f))
+    /// Returns the usage statistics of the allocator.
+    pub fn stats(&self) -> FrameStats {
+        self.stats
     }
+
+    /// Returns the next frame never allocated, moving to the next usable
+    /// region when the current one is exhausted.
+    fn next_unused_frame(&mut self) -> Option<PhysFrame> {
+        loop {
+            let region = self.memory_map.get(self.region)?;
+            if region.region_type == MemoryRegionType::Usable {
+                let start = self.next.max(region.range.start_addr());
+                if start < region.range.end_addr() {
+                    self.next = start + Size4KiB::SIZE;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(start)));
+                }
+            }
+            self.region += 1;
+        }
+    }
+}
+
+/// Returns a pointer to the link of the list of deallocated frames stored in
+/// `frame`.
+fn recycled_link(frame: u64) -> *mut u64 {
+    (super::physical_memory_offset() + frame).as_mut_ptr()
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        let frame = if self.recycled != NO_FRAME {
+            let frame = self.recycled;
+            self.recycled = unsafe { recycled_link(frame).read() };
+            self.stats.recycled -= 1;
+            PhysFrame::containing_address(PhysAddr::new(frame))
+        } else {
+            self.next_unused_frame()?
+        };
+        self.stats.allocated += 1;
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    /// Give back a frame to the allocator.
+    ///
+    /// The frame must have been allocated by this allocator, and must not be
+    /// used anymore : it is overwritten.
+    fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let frame = frame.start_address().as_u64();
+        unsafe { recycled_link(frame).write(self.recycled) };
+        self.recycled = frame;
+        self.stats.recycled += 1;
+        self.stats.allocated -= 1;
     }
 }
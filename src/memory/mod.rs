@@ -2,41 +2,171 @@
 //!
 
 // extern crates
-use x86_64::{structures::paging::OffsetPageTable, VirtAddr};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    registers::control::Cr3,
+    structures::paging::{OffsetPageTable, PhysFrame},
+    PhysAddr, VirtAddr,
+};
 
 // public submodules
+pub mod address_space;
 pub mod allocators;
+pub mod buddy;
 pub mod heap;
+pub mod layout;
 pub mod mapping;
+pub mod shared;
+pub mod user;
+pub mod vma;
+pub mod zone;
+
+// submodules exports
+pub use buddy::BuddyAllocator;
+pub use mapping::{BootInfoFrameAllocator, FrameStats};
+pub use zone::{Zone, ZonedAllocator};
 
 /// The divergent function that the kernel throws when it encounter an allocation
 /// error.
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     panic!(
-        "allocation error: {:?}\nMake sure heap was initialized before using it.",
-        layout
+        "allocation error: {:?}\nMake sure heap was initialized before using it, \
+         and is not full with {} bytes.",
+        layout,
+        heap::size()
     )
 }
 
+// ! ------------- global state -------------
+
+/// Offset of the mapping of the complete physical memory, given by the bootloader.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Address of the level 4 page table of the kernel.
+static KERNEL_LEVEL_4_TABLE: AtomicU64 = AtomicU64::new(0);
+
+/// The page table of the kernel, available once `init` has been called.
+///
+/// It must only be locked with interrupts disabled, see `with_kernel_memory`.
+pub static KERNEL_PAGE_TABLE: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+/// The frame allocator of the kernel, available once `init` has been called.
+///
+/// It is a buddy allocator per zone of physical memory, also able to allocate
+/// contiguous frames. It must only be locked with interrupts disabled, see
+/// `with_frame_allocator`.
+pub static FRAME_ALLOCATOR: Mutex<Option<ZonedAllocator>> = Mutex::new(None);
+
+/// Returns the virtual address at which the physical memory is mapped.
+pub fn physical_memory_offset() -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst);
+    assert_ne!(offset, 0, "memory is not initialized");
+    VirtAddr::new(offset)
+}
+
+/// Returns the virtual address through which the given physical address can
+/// be accessed.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    physical_memory_offset() + addr.as_u64()
+}
+
+/// Returns the frame containing the level 4 page table of the kernel.
+pub fn kernel_level_4_frame() -> PhysFrame {
+    let addr = KERNEL_LEVEL_4_TABLE.load(Ordering::SeqCst);
+    assert_ne!(addr, 0, "memory is not initialized");
+    PhysFrame::containing_address(PhysAddr::new(addr))
+}
+
+/// Run `f` with the kernel page table and the frame allocator.
+///
+/// Interrupts are disabled meanwhile.
+pub fn with_kernel_memory<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut OffsetPageTable<'static>, &mut ZonedAllocator) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut page_table = KERNEL_PAGE_TABLE.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        f(
+            page_table.as_mut().expect("memory is not initialized"),
+            frame_allocator.as_mut().expect("memory is not initialized"),
+        )
+    })
+}
+
+/// Run `f` with the frame allocator.
+///
+/// Interrupts are disabled meanwhile.
+pub fn with_frame_allocator<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ZonedAllocator) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        f(frame_allocator.as_mut().expect("memory is not initialized"))
+    })
+}
+
+/// Run `f` with the kernel page table and the frame allocator, like
+/// `with_kernel_memory`, if they are available.
+///
+/// Returns `None` instead of waiting if either is already locked, which on a
+/// single CPU means locked by the caller, or before `init`.
+pub fn try_with_kernel_memory<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut OffsetPageTable<'static>, &mut ZonedAllocator) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut page_table = KERNEL_PAGE_TABLE.try_lock()?;
+        let mut frame_allocator = FRAME_ALLOCATOR.try_lock()?;
+        Some(f(page_table.as_mut()?, frame_allocator.as_mut()?))
+    })
+}
+
+/// Run `f` with the frame allocator, like `with_frame_allocator`, if it is
+/// available.
+///
+/// Returns `None` instead of waiting if it is already locked, or before
+/// `init`.
+pub fn try_with_frame_allocator<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut ZonedAllocator) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut frame_allocator = FRAME_ALLOCATOR.try_lock()?;
+        Some(f(frame_allocator.as_mut()?))
+    })
+}
+
+// ! ------------- init -------------
+
 /// Initialize memory of the kernel, must be used before any use of `alloc`.
 ///
 /// The default steps are :
 /// - get physical memory offset
 /// - init mapping table
-/// - init allocator
+/// - init the buddy frame allocators of the zones
 /// - init heap
 ///
-/// Returns the mapper and the frame allocator.
-pub fn init(
-    boot_info: &'static bootloader::BootInfo,
-) -> (OffsetPageTable<'static>, mapping::BootInfoFrameAllocator) {
+/// The mapper and the frame allocator are then stored in `KERNEL_PAGE_TABLE`
+/// and `FRAME_ALLOCATOR`.
+pub fn init(boot_info: &'static bootloader::BootInfo) {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    PHYSICAL_MEMORY_OFFSET.store(phys_mem_offset.as_u64(), Ordering::SeqCst);
+    KERNEL_LEVEL_4_TABLE.store(
+        Cr3::read().0.start_address().as_u64(),
+        Ordering::SeqCst,
+    );
+
     let mut mapper = unsafe { mapping::init(phys_mem_offset) };
     let mut frame_allocator =
-        unsafe { mapping::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+        unsafe { ZonedAllocator::init(&boot_info.memory_map, phys_mem_offset) };
 
     heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
 
-    (mapper, frame_allocator)
+    interrupts::without_interrupts(|| {
+        *KERNEL_PAGE_TABLE.lock() = Some(mapper);
+        *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    });
 }
@@ -0,0 +1,137 @@
+//! This module contains `SharedMemory` : frames which can be mapped in
+//! several address spaces at once.
+//!
+//! A shared memory object is reference-counted : each mapping and each handle
+//! holds a reference, and the frames are freed with the last one.
+//!
+
+// internal crate
+use super::{phys_to_virt, with_frame_allocator};
+
+// external crates
+use alloc::vec::Vec;
+use core::ptr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
+
+/// Maximum size of a shared memory object.
+pub const MAX_SHARED_MEMORY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// An error occuring while creating a shared memory object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharedMemoryError {
+    /// The size is null or greater than `MAX_SHARED_MEMORY_SIZE`.
+    InvalidSize,
+    /// Not enough frames are available.
+    OutOfMemory,
+}
+
+/// Zeroed frames, mapped in several address spaces.
+#[derive(Debug)]
+pub struct SharedMemory {
+    frames: Vec<PhysFrame>,
+}
+
+impl SharedMemory {
+    /// Allocate a shared memory object of `size` bytes, rounded up to a
+    /// multiple of the page size.
+    pub fn new(size: u64) -> Result<Self, SharedMemoryError> {
+        if size == 0 || size > MAX_SHARED_MEMORY_SIZE {
+            return Err(SharedMemoryError::InvalidSize);
+        }
+        let count = ((size + Size4KiB::SIZE - 1) / Size4KiB::SIZE) as usize;
+
+        let mut frames = Vec::with_capacity(count);
+        let allocated = with_frame_allocator(|frame_allocator| {
+            for _ in 0..count {
+                match frame_allocator.allocate_frame() {
+                    Some(frame) => frames.push(frame),
+                    None => {
+                        for frame in frames.drain(..) {
+                            frame_allocator.deallocate_frame(frame);
+                        }
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+        if !allocated {
+            return Err(SharedMemoryError::OutOfMemory);
+        }
+
+        for frame in &frames {
+            unsafe {
+                let frame_ptr: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+                ptr::write_bytes(frame_ptr, 0, Size4KiB::SIZE as usize);
+            }
+        }
+        Ok(SharedMemory { frames })
+    }
+
+    /// Returns the size of the object, in bytes.
+    pub fn size(&self) -> u64 {
+        self.frames.len() as u64 * Size4KiB::SIZE
+    }
+
+    /// Returns the frames of the object.
+    pub fn frames(&self) -> &[PhysFrame] {
+        &self.frames
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        let frames = &mut self.frames;
+        with_frame_allocator(|frame_allocator| {
+            for frame in frames.drain(..) {
+                frame_allocator.deallocate_frame(frame);
+            }
+        });
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_shared_mappings() {
+    use super::{
+        address_space::{AddressSpace, Backing},
+        layout::USER_SPACE_START,
+    };
+    use alloc::sync::Arc;
+    use x86_64::{
+        structures::paging::{Page, PageTableFlags},
+        VirtAddr,
+    };
+
+    serial_print!("test_shared_mappings... ");
+    let memory = Arc::new(SharedMemory::new(2 * Size4KiB::SIZE).unwrap());
+    let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let mut first = AddressSpace::new().unwrap();
+    let mut second = AddressSpace::new().unwrap();
+    let start = VirtAddr::new(USER_SPACE_START);
+    first.map_shared(start, &memory, flags).unwrap();
+    second
+        .map_shared(start + 0x10000u64, &memory, flags)
+        .unwrap();
+    assert_eq!(Arc::strong_count(&memory), 3);
+
+    let page = Page::containing_address(start + Size4KiB::SIZE);
+    assert_eq!(first.translate(page).unwrap().0, memory.frames()[1]);
+    match &first.region(start).unwrap().backing {
+        Backing::Shared { offset, .. } => assert_eq!(*offset, 0),
+        Backing::Anonymous => panic!("region is not shared"),
+    }
+
+    // unmapping or freeing an address space keeps the frames
+    first.remove_range(start, start + Size4KiB::SIZE);
+    drop(second);
+    assert_eq!(Arc::strong_count(&memory), 2);
+    drop(first);
+    assert_eq!(Arc::strong_count(&memory), 1);
+    serial_println!("[ok]");
+}
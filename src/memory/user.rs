@@ -0,0 +1,293 @@
+//! This module permits to safely access the memory of user programs.
+//!
+//! Every access first checks that the range lies in the user space and is
+//! mapped with the right permissions in the active page table. The pages not
+//! mapped yet are first touched, so that the page fault handler maps them if
+//! their region allows it : the checks must not be made while the process
+//! table is locked, or the lazily mapped pages are refused. As the mapping
+//! may still change before the copy, for example if another thread of the
+//! process unmaps it, the copy itself is done by a routine whose page faults
+//! are recovered through an exception fixup table : the copy stops and an
+//! error is returned instead of a kernel panic.
+//!
+
+// internal crate
+use super::{layout, phys_to_virt};
+
+// external crates
+use alloc::vec::Vec;
+use core::mem;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// An error returned when a user range is invalid, or when a page fault
+/// occurred while accessing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadUserAddress;
+
+/// The kind of access made to user memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// ! ------------- fixup table -------------
+
+global_asm!(
+    r#"
+.intel_syntax noprefix
+
+// fn nit_user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize
+// returns the number of bytes not copied
+.global nit_user_copy
+nit_user_copy:
+    mov rcx, rdx
+.global nit_user_copy_start
+nit_user_copy_start:
+    rep movsb
+.global nit_user_copy_end
+nit_user_copy_end:
+    xor eax, eax
+    ret
+.global nit_user_copy_fixup
+nit_user_copy_fixup:
+    mov rax, rcx
+    ret
+
+.att_syntax prefix
+"#
+);
+
+extern "C" {
+    fn nit_user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn nit_user_copy_start();
+    fn nit_user_copy_end();
+    fn nit_user_copy_fixup();
+}
+
+/// An entry of the exception fixup table.
+struct Fixup {
+    /// Start of the instructions allowed to fault.
+    start: u64,
+    /// End of the instructions allowed to fault, excluded.
+    end: u64,
+    /// Where to resume after a fault.
+    fixup: u64,
+}
+
+/// Returns the exception fixup table.
+fn fixup_table() -> [Fixup; 1] {
+    [Fixup {
+        start: nit_user_copy_start as usize as u64,
+        end: nit_user_copy_end as usize as u64,
+        fixup: nit_user_copy_fixup as usize as u64,
+    }]
+}
+
+/// Returns where to resume after a fault at `instruction_pointer` in kernel
+/// mode, if the faulting instruction accesses user memory.
+pub fn search_fixup(instruction_pointer: VirtAddr) -> Option<VirtAddr> {
+    let instruction_pointer = instruction_pointer.as_u64();
+    fixup_table()
+        .iter()
+        .find(|entry| entry.start <= instruction_pointer && instruction_pointer < entry.end)
+        .map(|entry| VirtAddr::new(entry.fixup))
+}
+
+// ! ------------- checks -------------
+
+/// Check that `addr..addr + len` lies in the user space, and is mapped with
+/// user access in the active page table, writable for `Access::Write`.
+pub fn check_range(addr: u64, len: u64, access: Access) -> Result<(), BadUserAddress> {
+    if len == 0 {
+        return Ok(());
+    }
+    if !layout::is_user_range(addr, len) {
+        return Err(BadUserAddress);
+    }
+
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if access == Access::Write {
+        required |= PageTableFlags::WRITABLE;
+    }
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(addr + len - 1));
+    for page in Page::range_inclusive(first, last) {
+        if !is_accessible(page, required) {
+            return Err(BadUserAddress);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the physical address `addr` is mapped to in the active page table,
+/// checking it like `check_range`.
+pub fn translate(addr: u64, access: Access) -> Result<PhysAddr, BadUserAddress> {
+    if !layout::is_user_range(addr, 1) {
+        return Err(BadUserAddress);
+    }
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if access == Access::Write {
+        required |= PageTableFlags::WRITABLE;
+    }
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    if !is_accessible(page, required) {
+        return Err(BadUserAddress);
+    }
+    let frame = mapped_frame(page, required).ok_or(BadUserAddress)?;
+    Ok(frame.start_address() + (addr - page.start_address().as_u64()))
+}
+
+/// Returns `true` if `page` is mapped like `is_mapped` checks, after touching
+/// it if it is not mapped yet.
+///
+/// Reading the page is enough : the page fault handler maps it with the flags
+/// of its region, whatever the access.
+fn is_accessible(page: Page, required: PageTableFlags) -> bool {
+    if is_mapped(page, required) {
+        return true;
+    }
+    let mut byte = 0u8;
+    // an unresolved fault is recovered, and leaves the page unmapped
+    unsafe { nit_user_copy(&mut byte, page.start_address().as_ptr(), 1) };
+    is_mapped(page, required)
+}
+
+/// Returns `true` if `page` is mapped in the active page table, with every
+/// level having the `required` flags.
+fn is_mapped(page: Page, required: PageTableFlags) -> bool {
+    mapped_frame(page, required).is_some()
+}
+
+/// Returns the frame `page` is mapped to in the active page table, if every
+/// level has the `required` flags.
+fn mapped_frame(page: Page, required: PageTableFlags) -> Option<PhysFrame> {
+    let mut frame = Cr3::read().0;
+    let indexes = [
+        page.p4_index(),
+        page.p3_index(),
+        page.p2_index(),
+        page.p1_index(),
+    ];
+    for &index in &indexes {
+        let table: &PageTable = unsafe { &*phys_to_virt(frame.start_address()).as_ptr() };
+        let entry = &table[index];
+        if !entry.flags().contains(required) {
+            return None;
+        }
+        // fails on huge pages, never used in the user space
+        frame = entry.frame().ok()?;
+    }
+    Some(frame)
+}
+
+// ! ------------- copies -------------
+
+/// Copy the user memory at `src` to `dst`.
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), BadUserAddress> {
+    check_range(src, dst.len() as u64, Access::Read)?;
+    let not_copied = unsafe { nit_user_copy(dst.as_mut_ptr(), src as *const u8, dst.len()) };
+    match not_copied {
+        0 => Ok(()),
+        _ => Err(BadUserAddress),
+    }
+}
+
+/// Copy `src` to the user memory at `dst`.
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), BadUserAddress> {
+    check_range(dst, src.len() as u64, Access::Write)?;
+    let not_copied = unsafe { nit_user_copy(dst as *mut u8, src.as_ptr(), src.len()) };
+    match not_copied {
+        0 => Ok(()),
+        _ => Err(BadUserAddress),
+    }
+}
+
+/// Read a `u64` from the user memory at `src`.
+pub fn read_u64(src: u64) -> Result<u64, BadUserAddress> {
+    let mut bytes = [0; mem::size_of::<u64>()];
+    copy_from_user(&mut bytes, src)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read a `u32` from the user memory at `src`.
+pub fn read_u32(src: u64) -> Result<u32, BadUserAddress> {
+    let mut bytes = [0; mem::size_of::<u32>()];
+    copy_from_user(&mut bytes, src)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read the null-terminated string at `src`, without its terminator.
+///
+/// Returns `None` if it is longer than `max_len` bytes.
+pub fn read_string(src: u64, max_len: usize) -> Result<Option<Vec<u8>>, BadUserAddress> {
+    let mut string = Vec::new();
+    let mut addr = src;
+    loop {
+        // the string may end before an unmapped page : copy page by page
+        let page_left = Size4KiB::SIZE - addr % Size4KiB::SIZE;
+        let mut chunk = [0; 256];
+        let len = page_left.min(chunk.len() as u64) as usize;
+        copy_from_user(&mut chunk[..len], addr)?;
+        if let Some(end) = chunk[..len].iter().position(|&byte| byte == 0) {
+            string.extend_from_slice(&chunk[..end]);
+            return Ok(if string.len() <= max_len {
+                Some(string)
+            } else {
+                None
+            });
+        }
+        string.extend_from_slice(&chunk[..len]);
+        if string.len() > max_len {
+            return Ok(None);
+        }
+        addr += len as u64;
+    }
+}
+
+/// Write a `u64` to the user memory at `dst`.
+pub fn write_u64(dst: u64, value: u64) -> Result<(), BadUserAddress> {
+    copy_to_user(dst, &value.to_le_bytes())
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_kernel_address_rejected() {
+    serial_print!("test_kernel_address_rejected... ");
+    let kernel_data = [1u8; 8];
+    let mut buffer = [0u8; 8];
+    assert_eq!(
+        copy_from_user(&mut buffer, kernel_data.as_ptr() as u64),
+        Err(BadUserAddress)
+    );
+    assert_eq!(
+        copy_to_user(layout::USER_SPACE_END - 4, &buffer),
+        Err(BadUserAddress)
+    );
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_fault_recovered() {
+    serial_print!("test_fault_recovered... ");
+    let mut buffer = [0u8; 8];
+    // unmapped user page : the copy faults, and the fault is recovered
+    let not_copied = unsafe {
+        nit_user_copy(
+            buffer.as_mut_ptr(),
+            layout::USER_SPACE_START as *const u8,
+            buffer.len(),
+        )
+    };
+    assert_eq!(not_copied, buffer.len());
+    serial_println!("[ok]");
+}
@@ -0,0 +1,521 @@
+//! This module contains the allocator of the kernel virtual address space.
+//!
+//! Each `KernelRegion` has its own `VmaAllocator`, which reserves and frees
+//! ranges of pages in it. The reserved ranges are kept in an `IntervalTree`,
+//! so that a range overlapping another one is refused.
+//!
+//! The heap is the first reservation of its region, made by `heap::init` once
+//! the heap can allocate the nodes of the tree. The kernel stacks are
+//! reserved by `mapping::alloc_stack`.
+//!
+
+// internal crate
+use super::layout::KernelRegion;
+
+// external crates
+use alloc::boxed::Box;
+use core::cmp;
+use spin::Mutex;
+use x86_64::{instructions::interrupts, VirtAddr};
+
+/// Size of a page.
+const PAGE_SIZE: u64 = 4096;
+
+/// A reserved range of virtual memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    /// Start of the area, page aligned.
+    pub start: VirtAddr,
+    /// End of the area, page aligned and excluded.
+    pub end: VirtAddr,
+    /// What the area is reserved for.
+    pub name: &'static str,
+}
+
+impl Area {
+    /// Returns the number of bytes of the area.
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Returns `true` if the area contains the given address.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns `true` if the area overlaps the range `start..end`.
+    pub fn overlaps(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+/// An error returned when reserving or freeing virtual memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// The range is not page aligned, or not in the region.
+    InvalidRange,
+    /// The range overlaps an area already reserved.
+    Overlap(Area),
+    /// No range of the requested size is free in the region.
+    Exhausted,
+    /// No area starts at the given address.
+    NotReserved,
+}
+
+// ! ------------- interval tree -------------
+
+/// A node of an `IntervalTree`.
+struct Node {
+    area: Area,
+    /// Highest end of the areas of the subtree.
+    max_end: VirtAddr,
+    height: u8,
+    left: Option<Box<Node>>,
+    right: Option<Box<Node>>,
+}
+
+/// An AVL tree of areas sorted by start, whose nodes also hold the highest
+/// end of their subtree : the areas overlapping a range are found in
+/// logarithmic time.
+pub struct IntervalTree {
+    root: Option<Box<Node>>,
+    len: usize,
+}
+
+impl IntervalTree {
+    /// Create an empty `IntervalTree`.
+    pub const fn new() -> Self {
+        IntervalTree { root: None, len: 0 }
+    }
+
+    /// Returns the number of areas.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree has no area.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the height of the tree.
+    pub fn height(&self) -> u8 {
+        height(&self.root)
+    }
+
+    /// Add `area`, which may overlap others.
+    pub fn insert(&mut self, area: Area) {
+        self.root = Some(insert(self.root.take(), area));
+        self.len += 1;
+    }
+
+    /// Remove the area starting at `start`, and return it.
+    pub fn remove(&mut self, start: VirtAddr) -> Option<Area> {
+        let (root, area) = remove(self.root.take(), start);
+        self.root = root;
+        if area.is_some() {
+            self.len -= 1;
+        }
+        area
+    }
+
+    /// Returns the lowest area overlapping the range `start..end`.
+    pub fn find_overlap(&self, start: VirtAddr, end: VirtAddr) -> Option<&Area> {
+        find_overlap(&self.root, start, end)
+    }
+
+    /// Call `f` on the areas in order, until it returns `false`.
+    pub fn visit(&self, mut f: impl FnMut(&Area) -> bool) {
+        visit(&self.root, &mut f);
+    }
+}
+
+fn height(node: &Option<Box<Node>>) -> u8 {
+    node.as_ref().map_or(0, |node| node.height)
+}
+
+fn max_end(node: &Option<Box<Node>>) -> Option<VirtAddr> {
+    node.as_ref().map(|node| node.max_end)
+}
+
+/// Update the height and the highest end of `node` from its children.
+fn update(node: &mut Node) {
+    node.height = 1 + cmp::max(height(&node.left), height(&node.right));
+    node.max_end = [max_end(&node.left), max_end(&node.right)]
+        .iter()
+        .flatten()
+        .fold(node.area.end, |max, &end| cmp::max(max, end));
+}
+
+fn rotate_right(mut node: Box<Node>) -> Box<Node> {
+    let mut left = node.left.take().expect("rotation without left child");
+    node.left = left.right.take();
+    update(&mut node);
+    left.right = Some(node);
+    update(&mut left);
+    left
+}
+
+fn rotate_left(mut node: Box<Node>) -> Box<Node> {
+    let mut right = node.right.take().expect("rotation without right child");
+    node.right = right.left.take();
+    update(&mut node);
+    right.left = Some(node);
+    update(&mut right);
+    right
+}
+
+/// Restore the balance of `node`, whose subtrees are balanced and differ in
+/// height by 2 at most.
+fn balance(mut node: Box<Node>) -> Box<Node> {
+    update(&mut node);
+    let (left, right) = (height(&node.left), height(&node.right));
+    if left > right + 1 {
+        let child = node.left.take().expect("left subtree is higher");
+        node.left = Some(if height(&child.left) < height(&child.right) {
+            rotate_left(child)
+        } else {
+            child
+        });
+        rotate_right(node)
+    } else if right > left + 1 {
+        let child = node.right.take().expect("right subtree is higher");
+        node.right = Some(if height(&child.right) < height(&child.left) {
+            rotate_right(child)
+        } else {
+            child
+        });
+        rotate_left(node)
+    } else {
+        node
+    }
+}
+
+fn insert(node: Option<Box<Node>>, area: Area) -> Box<Node> {
+    match node {
+        None => Box::new(Node {
+            area,
+            max_end: area.end,
+            height: 1,
+            left: None,
+            right: None,
+        }),
+        Some(mut node) => {
+            if area.start < node.area.start {
+                node.left = Some(insert(node.left.take(), area));
+            } else {
+                node.right = Some(insert(node.right.take(), area));
+            }
+            balance(node)
+        }
+    }
+}
+
+fn remove(node: Option<Box<Node>>, start: VirtAddr) -> (Option<Box<Node>>, Option<Area>) {
+    let mut node = match node {
+        Some(node) => node,
+        None => return (None, None),
+    };
+    if start < node.area.start {
+        let (left, area) = remove(node.left.take(), start);
+        node.left = left;
+        (Some(balance(node)), area)
+    } else if start > node.area.start {
+        let (right, area) = remove(node.right.take(), start);
+        node.right = right;
+        (Some(balance(node)), area)
+    } else {
+        let area = node.area;
+        let node = match (node.left.take(), node.right.take()) {
+            (None, child) | (child, None) => child,
+            (left, Some(right)) => {
+                // replaced by the lowest node of the right subtree
+                let (right, mut lowest) = remove_lowest(right);
+                lowest.left = left;
+                lowest.right = right;
+                Some(balance(lowest))
+            }
+        };
+        (node, Some(area))
+    }
+}
+
+/// Remove the lowest node of the subtree of `node`, and return it.
+fn remove_lowest(mut node: Box<Node>) -> (Option<Box<Node>>, Box<Node>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (left, lowest) = remove_lowest(left);
+            node.left = left;
+            (Some(balance(node)), lowest)
+        }
+    }
+}
+
+fn find_overlap(node: &Option<Box<Node>>, start: VirtAddr, end: VirtAddr) -> Option<&Area> {
+    let node = node.as_ref()?;
+    if node.max_end <= start {
+        return None;
+    }
+    if let Some(area) = find_overlap(&node.left, start, end) {
+        return Some(area);
+    }
+    if node.area.overlaps(start, end) {
+        return Some(&node.area);
+    }
+    if node.area.start >= end {
+        // the areas on the right start even later
+        return None;
+    }
+    find_overlap(&node.right, start, end)
+}
+
+/// Returns `false` if `f` stopped the visit.
+fn visit(node: &Option<Box<Node>>, f: &mut impl FnMut(&Area) -> bool) -> bool {
+    match node {
+        None => true,
+        Some(node) => visit(&node.left, f) && f(&node.area) && visit(&node.right, f),
+    }
+}
+
+// ! ------------- vma allocator -------------
+
+/// Reserves ranges of pages in a range of virtual memory.
+pub struct VmaAllocator {
+    start: u64,
+    end: u64,
+    areas: IntervalTree,
+    /// Bytes of the reserved areas.
+    reserved: u64,
+}
+
+impl VmaAllocator {
+    /// Create an allocator of the range `start..end`, page aligned.
+    pub const fn new(start: u64, end: u64) -> Self {
+        VmaAllocator {
+            start,
+            end,
+            areas: IntervalTree::new(),
+            reserved: 0,
+        }
+    }
+
+    /// Returns the number of reserved areas.
+    pub fn len(&self) -> usize {
+        self.areas.len()
+    }
+
+    /// Returns the number of reserved bytes.
+    pub fn reserved(&self) -> u64 {
+        self.reserved
+    }
+
+    /// Reserve `len` bytes, rounded up to pages, at the lowest free address.
+    ///
+    /// The search takes linear time in the number of areas.
+    pub fn reserve(&mut self, len: u64, name: &'static str) -> Result<Area, VmaError> {
+        let len = align_up(len).ok_or(VmaError::Exhausted)?;
+        let mut start = VirtAddr::new(self.start);
+        let mut found = false;
+        self.areas.visit(|area| {
+            if area.start >= start && area.start - start >= len {
+                found = true;
+                return false;
+            }
+            start = cmp::max(start, area.end);
+            true
+        });
+        if !found && self.end.saturating_sub(start.as_u64()) < len {
+            return Err(VmaError::Exhausted);
+        }
+        self.reserve_at(start, len, name)
+    }
+
+    /// Reserve `len` bytes, rounded up to pages, at `start`.
+    pub fn reserve_at(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        name: &'static str,
+    ) -> Result<Area, VmaError> {
+        let len = align_up(len).ok_or(VmaError::InvalidRange)?;
+        if !start.is_aligned(PAGE_SIZE) || len == 0 || start.as_u64() < self.start {
+            return Err(VmaError::InvalidRange);
+        }
+        let end = match start.as_u64().checked_add(len) {
+            Some(end) if end <= self.end => VirtAddr::new(end),
+            _ => return Err(VmaError::InvalidRange),
+        };
+        if let Some(&area) = self.areas.find_overlap(start, end) {
+            return Err(VmaError::Overlap(area));
+        }
+        let area = Area { start, end, name };
+        self.areas.insert(area);
+        self.reserved += len;
+        Ok(area)
+    }
+
+    /// Free the area starting at `start`.
+    pub fn free(&mut self, start: VirtAddr) -> Result<Area, VmaError> {
+        let area = self.areas.remove(start).ok_or(VmaError::NotReserved)?;
+        self.reserved -= area.size();
+        Ok(area)
+    }
+
+    /// Returns the area containing `addr`.
+    pub fn find(&self, addr: VirtAddr) -> Option<Area> {
+        self.areas.find_overlap(addr, addr + 1u64).copied()
+    }
+}
+
+/// Round `len` up to a multiple of the page size.
+fn align_up(len: u64) -> Option<u64> {
+    Some(len.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1))
+}
+
+// ! ------------- kernel space -------------
+
+const fn region_allocator(region: KernelRegion) -> VmaAllocator {
+    VmaAllocator::new(region.start(), region.end())
+}
+
+/// The allocators of the kernel regions, in the order of `KernelRegion::ALL`.
+///
+/// It must only be locked with interrupts disabled. It may be locked while
+/// the kernel page table and the frame allocator are.
+static KERNEL_VMA: Mutex<[VmaAllocator; 4]> = Mutex::new([
+    region_allocator(KernelRegion::Heap),
+    region_allocator(KernelRegion::Stacks),
+    region_allocator(KernelRegion::Mmio),
+    region_allocator(KernelRegion::Modules),
+]);
+
+/// Run `f` with the allocator of `region`.
+fn with_region<F, R>(region: KernelRegion, f: F) -> R
+where
+    F: FnOnce(&mut VmaAllocator) -> R,
+{
+    interrupts::without_interrupts(|| f(&mut KERNEL_VMA.lock()[region as usize]))
+}
+
+/// Reserve `len` bytes, rounded up to pages, in `region`.
+pub fn reserve(region: KernelRegion, len: u64, name: &'static str) -> Result<Area, VmaError> {
+    with_region(region, |allocator| allocator.reserve(len, name))
+}
+
+/// Reserve `len` bytes, rounded up to pages, at `start` in `region`.
+pub fn reserve_at(
+    region: KernelRegion,
+    start: VirtAddr,
+    len: u64,
+    name: &'static str,
+) -> Result<Area, VmaError> {
+    with_region(region, |allocator| allocator.reserve_at(start, len, name))
+}
+
+/// Free the area of the kernel space starting at `start`.
+///
+/// Its pages must have been unmapped before.
+pub fn free(start: VirtAddr) -> Result<Area, VmaError> {
+    let region = KernelRegion::containing(start.as_u64()).ok_or(VmaError::NotReserved)?;
+    with_region(region, |allocator| allocator.free(start))
+}
+
+/// Returns the area of the kernel space containing `addr`.
+pub fn find(addr: VirtAddr) -> Option<Area> {
+    let region = KernelRegion::containing(addr.as_u64())?;
+    with_region(region, |allocator| allocator.find(addr))
+}
+
+/// Returns the number of areas and of bytes reserved in `region`.
+pub fn usage(region: KernelRegion) -> (usize, u64) {
+    with_region(region, |allocator| (allocator.len(), allocator.reserved()))
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_interval_tree() {
+    serial_print!("test_interval_tree... ");
+    let addr = |page: u64| VirtAddr::new(page * PAGE_SIZE);
+    let area = |start: u64, end: u64| Area {
+        start: addr(start),
+        end: addr(end),
+        name: "test",
+    };
+
+    let mut tree = IntervalTree::new();
+    for page in 0..64 {
+        tree.insert(area(page * 4, page * 4 + 2));
+    }
+    assert_eq!(tree.len(), 64);
+    // balanced : an AVL tree of 64 nodes has 8 levels at most
+    assert!(tree.height() <= 8);
+    assert_eq!(tree.find_overlap(addr(2), addr(4)), None);
+    assert_eq!(tree.find_overlap(addr(3), addr(9)), Some(&area(4, 6)));
+    assert_eq!(tree.find_overlap(addr(9), addr(10)), Some(&area(8, 10)));
+
+    for page in (0..64).step_by(2) {
+        assert_eq!(
+            tree.remove(addr(page * 4)),
+            Some(area(page * 4, page * 4 + 2))
+        );
+    }
+    assert_eq!(tree.remove(addr(0)), None);
+    assert_eq!(tree.len(), 32);
+    assert!(tree.height() <= 6);
+    assert_eq!(tree.find_overlap(addr(0), addr(4)), None);
+    let mut previous = VirtAddr::new(0);
+    tree.visit(|area| {
+        assert!(area.start >= previous);
+        previous = area.start;
+        true
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_vma_allocator() {
+    serial_print!("test_vma_allocator... ");
+    let addr = |page: u64| VirtAddr::new(0x_1000_0000 + page * PAGE_SIZE);
+    let mut allocator = VmaAllocator::new(addr(0).as_u64(), addr(16).as_u64());
+
+    let first = allocator.reserve(2 * PAGE_SIZE, "first").unwrap();
+    let second = allocator.reserve(1, "second").unwrap();
+    assert_eq!((first.start, first.end), (addr(0), addr(2)));
+    assert_eq!((second.start, second.end), (addr(2), addr(3)));
+    assert_eq!(allocator.reserved(), 3 * PAGE_SIZE);
+
+    // overlaps are refused
+    let fixed = allocator.reserve_at(addr(8), PAGE_SIZE, "fixed").unwrap();
+    assert_eq!(
+        allocator.reserve_at(addr(7), 2 * PAGE_SIZE, "overlap"),
+        Err(VmaError::Overlap(fixed))
+    );
+    assert_eq!(
+        allocator.reserve_at(addr(15), 2 * PAGE_SIZE, "outside"),
+        Err(VmaError::InvalidRange)
+    );
+    assert_eq!(allocator.find(addr(8) + 10u64), Some(fixed));
+
+    // the freed range is reused, larger ones go after the fixed area
+    assert_eq!(allocator.free(first.start), Ok(first));
+    assert_eq!(allocator.free(first.start), Err(VmaError::NotReserved));
+    assert_eq!(
+        allocator.reserve(PAGE_SIZE, "reused").unwrap().start,
+        addr(0)
+    );
+    assert_eq!(
+        allocator.reserve(6 * PAGE_SIZE, "large").unwrap().start,
+        addr(9)
+    );
+    assert_eq!(
+        allocator.reserve(6 * PAGE_SIZE, "too large"),
+        Err(VmaError::Exhausted)
+    );
+    serial_println!("[ok]");
+}
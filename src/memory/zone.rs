@@ -0,0 +1,189 @@
+//! This module contains the zones of physical memory.
+//!
+//! Some devices can only address low memory : legacy ISA DMA is limited to
+//! the first 16 MiB, and 32-bit PCI devices to the first 4 GiB. The physical
+//! memory is thus split in zones, each managed by its own `BuddyAllocator`
+//! with its own statistics :
+//! - `Zone::Dma` below 16 MiB,
+//! - `Zone::Dma32` from 16 MiB to 4 GiB,
+//! - `Zone::Normal` above 4 GiB.
+//!
+//! Unconstrained allocations take the highest zone with free memory, keeping
+//! low memory for the devices which need it.
+//!
+
+// internal crate
+use super::buddy::{BuddyAllocator, BuddyStats, MAX_ORDER};
+
+// external crates
+use bootloader::bootinfo::MemoryMap;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    VirtAddr,
+};
+
+/// Physical addresses reachable by legacy ISA DMA : 16 MiB.
+pub const DMA_LIMIT: u64 = 16 << 20;
+/// Physical addresses reachable by 32-bit devices : 4 GiB.
+pub const DMA32_LIMIT: u64 = 4 << 30;
+/// Physical addresses reachable in real mode, by the trampoline starting the
+/// application processors : 1 MiB.
+pub const REAL_MODE_LIMIT: u64 = 1 << 20;
+
+/// A zone of physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    /// Below `DMA_LIMIT`.
+    Dma,
+    /// From `DMA_LIMIT` to `DMA32_LIMIT`.
+    Dma32,
+    /// Above `DMA32_LIMIT`.
+    Normal,
+}
+
+impl Zone {
+    /// Every zone, from the lowest one.
+    pub const ALL: [Zone; 3] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    /// Returns the range of physical addresses of the zone.
+    pub fn range(self) -> (u64, u64) {
+        match self {
+            Zone::Dma => (0, DMA_LIMIT),
+            Zone::Dma32 => (DMA_LIMIT, DMA32_LIMIT),
+            Zone::Normal => (DMA32_LIMIT, u64::max_value()),
+        }
+    }
+
+    /// Returns the zone containing the physical address `addr`.
+    pub fn containing(addr: u64) -> Zone {
+        if addr < DMA_LIMIT {
+            Zone::Dma
+        } else if addr < DMA32_LIMIT {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// The frame allocator of the kernel : a buddy allocator per zone.
+pub struct ZonedAllocator {
+    /// The allocator of each zone, `None` if the zone has no usable memory.
+    zones: [Option<BuddyAllocator>; 3],
+}
+
+impl ZonedAllocator {
+    /// Create the allocators of the zones of the usable frames of the passed
+    /// memory map.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe for the same reasons as `BuddyAllocator::init`.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let zone = |zone: Zone| {
+            let (start, end) = zone.range();
+            BuddyAllocator::init_within(memory_map, physical_memory_offset, start..end)
+        };
+        let allocator = ZonedAllocator {
+            zones: [zone(Zone::Dma), zone(Zone::Dma32), zone(Zone::Normal)],
+        };
+        assert!(
+            allocator.zones.iter().any(Option::is_some),
+            "no usable physical memory"
+        );
+        allocator
+    }
+
+    /// Returns the usage statistics of all zones.
+    pub fn stats(&self) -> BuddyStats {
+        let mut stats = BuddyStats {
+            total: 0,
+            allocated: 0,
+            free_blocks: [0; MAX_ORDER + 1],
+        };
+        for zone in self.zones.iter().flatten() {
+            let zone = zone.stats();
+            stats.total += zone.total;
+            stats.allocated += zone.allocated;
+            for (count, zone_count) in stats.free_blocks.iter_mut().zip(&zone.free_blocks) {
+                *count += zone_count;
+            }
+        }
+        stats
+    }
+
+    /// Returns the usage statistics of `zone`, `None` if it has no usable
+    /// memory.
+    pub fn zone_stats(&self, zone: Zone) -> Option<BuddyStats> {
+        self.zones[zone.index()].as_ref().map(BuddyAllocator::stats)
+    }
+
+    /// Allocate a block of `2^order` contiguous frames anywhere, from the
+    /// highest zone able to.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysFrame> {
+        self.zones
+            .iter_mut()
+            .rev()
+            .flatten()
+            .find_map(|zone| zone.allocate(order))
+    }
+
+    /// Allocate a block of `2^order` contiguous frames in `zone` or a lower
+    /// one, from the highest of them able to.
+    pub fn allocate_in(&mut self, order: usize, zone: Zone) -> Option<PhysFrame> {
+        self.zones[..=zone.index()]
+            .iter_mut()
+            .rev()
+            .flatten()
+            .find_map(|zone| zone.allocate(order))
+    }
+
+    /// Allocate a block of `2^order` contiguous frames ending below the
+    /// physical address `limit`, such as `REAL_MODE_LIMIT`.
+    ///
+    /// The zone containing `limit` is searched, which takes linear time.
+    pub fn allocate_below(&mut self, order: usize, limit: u64) -> Option<PhysFrame> {
+        let zone = Zone::containing(limit.saturating_sub(1));
+        if let Some(frame) = self.allocate_in_below(order, zone, limit) {
+            return Some(frame);
+        }
+        match zone {
+            Zone::Dma => None,
+            Zone::Dma32 => self.allocate_in(order, Zone::Dma),
+            Zone::Normal => self.allocate_in(order, Zone::Dma32),
+        }
+    }
+
+    /// Free the block of `2^order` frames starting at `frame`, allocated by
+    /// this allocator with the same order.
+    pub fn deallocate(&mut self, frame: PhysFrame, order: usize) {
+        let zone = Zone::containing(frame.start_address().as_u64());
+        match self.zones[zone.index()].as_mut() {
+            Some(zone) if zone.manages(frame) => zone.deallocate(frame, order),
+            _ => panic!("deallocated frame {:?} was not allocated", frame),
+        }
+    }
+
+    /// Allocate a block in `zone` only, ending below `limit`.
+    fn allocate_in_below(&mut self, order: usize, zone: Zone, limit: u64) -> Option<PhysFrame> {
+        let zone = self.zones[zone.index()].as_mut()?;
+        zone.allocate_below(order, limit)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for ZonedAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for ZonedAllocator {
+    /// Give back a frame allocated with `allocate_frame`.
+    fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate(frame, 0);
+    }
+}
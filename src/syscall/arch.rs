@@ -0,0 +1,44 @@
+//! The system calls specific to x86_64.
+//!
+
+// internal crate
+use super::{errno, SyscallResult};
+use crate::{
+    memory::{layout, user},
+    task::scheduler,
+};
+
+// external crates
+use x86_64::VirtAddr;
+
+/// Set the base of the `GS` segment, not supported.
+const ARCH_SET_GS: u64 = 0x1001;
+/// Set the base of the `FS` segment.
+const ARCH_SET_FS: u64 = 0x1002;
+/// Write the base of the `FS` segment to an address.
+const ARCH_GET_FS: u64 = 0x1003;
+/// Write the base of the `GS` segment to an address, not supported.
+const ARCH_GET_GS: u64 = 0x1004;
+
+/// Set or get the state of the current thread given by `code`.
+///
+/// Only the `FS` base, the thread pointer of the thread-local storage, can be
+/// set : the `GS` segment is reserved.
+pub fn arch_prctl(code: u64, addr: u64) -> SyscallResult {
+    match code {
+        ARCH_SET_FS => {
+            // 0 removes the thread-local storage
+            if addr != 0 && !layout::is_user_range(addr, 0) {
+                return Err(errno::EPERM);
+            }
+            scheduler::set_current_fs_base(VirtAddr::new(addr));
+            Ok(0)
+        }
+        ARCH_GET_FS => {
+            user::write_u64(addr, scheduler::current_fs_base().as_u64())?;
+            Ok(0)
+        }
+        ARCH_SET_GS | ARCH_GET_GS => Err(errno::EINVAL),
+        _ => Err(errno::EINVAL),
+    }
+}
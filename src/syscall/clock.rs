@@ -0,0 +1,35 @@
+//! The system calls reading the clocks.
+//!
+
+// internal crate
+use super::{errno, SyscallResult};
+use crate::{memory::user, time};
+
+/// Wall-clock time, not supported as the `CMOS` is too slow to read.
+const CLOCK_REALTIME: u64 = 0;
+/// Time since boot.
+const CLOCK_MONOTONIC: u64 = 1;
+/// Time since boot, not adjusted.
+const CLOCK_MONOTONIC_RAW: u64 = 4;
+/// Time since boot, at the resolution of a tick.
+const CLOCK_MONOTONIC_COARSE: u64 = 6;
+/// Time since boot, including suspended time.
+const CLOCK_BOOTTIME: u64 = 7;
+
+/// Write the time of the given clock to the `timespec` at `time_pointer`.
+pub fn clock_gettime(clock: u64, time_pointer: u64) -> SyscallResult {
+    let now = match clock {
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+            time::uptime()
+        }
+        CLOCK_REALTIME => return Err(errno::EINVAL),
+        _ => return Err(errno::EINVAL),
+    };
+
+    // struct timespec { time_t tv_sec; long tv_nsec; }
+    let mut timespec = [0; 16];
+    timespec[..8].copy_from_slice(&now.as_secs().to_le_bytes());
+    timespec[8..].copy_from_slice(&u64::from(now.subsec_nanos()).to_le_bytes());
+    user::copy_to_user(time_pointer, &timespec)?;
+    Ok(0)
+}
@@ -0,0 +1,156 @@
+//! The entry point of the `syscall` instruction.
+//!
+//! The `syscall` instruction does not switch stacks : the entry saves the user
+//! stack pointer, loads the kernel stack of the current thread and builds a
+//! `TrapFrame` on it. The thread returns to user mode with `iretq`.
+//!
+
+// internal crate
+use super::{dispatch, numbers, process};
+use crate::{
+    interrupts::{gdt, trap::TrapFrame},
+    task::signal,
+};
+
+// external crates
+use x86_64::{registers::model_specific::Msr, VirtAddr};
+
+/// Selector of the user code segment, pushed by the entry.
+const USER_CODE_SELECTOR: u16 = 0x23;
+/// Selector of the user data segment, pushed by the entry.
+const USER_DATA_SELECTOR: u16 = 0x1b;
+
+/// Extended feature enable register.
+const EFER: u32 = 0xc000_0080;
+/// Segments loaded by `syscall` and `sysret`.
+const STAR: u32 = 0xc000_0081;
+/// Entry point of `syscall`.
+const LSTAR: u32 = 0xc000_0082;
+/// Flags cleared by `syscall`.
+const SFMASK: u32 = 0xc000_0084;
+
+/// `syscall` enable bit of `EFER`.
+const EFER_SCE: u64 = 1;
+/// Flags cleared on entry : trap, interrupts and direction.
+const SYSCALL_FLAGS_MASK: u64 = 0x700;
+
+global_asm!(
+    r#"
+.intel_syntax noprefix
+
+.global nit_syscall_entry
+nit_syscall_entry:
+    mov [rip + NIT_SYSCALL_USER_RSP], rsp
+    mov rsp, [rip + NIT_KERNEL_STACK_TOP]
+
+    // iretq frame
+    push 0x1b
+    push qword ptr [rip + NIT_SYSCALL_USER_RSP]
+    push r11
+    push 0x23
+    push rcx
+    // error code and vector
+    push 0
+    push 0x80
+
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    sti
+    call nit_syscall_handler
+    cli
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    add rsp, 16
+    iretq
+
+.att_syntax prefix
+"#
+);
+
+extern "C" {
+    fn nit_syscall_entry();
+}
+
+/// The user stack pointer, saved by the entry until it is pushed.
+#[no_mangle]
+static mut NIT_SYSCALL_USER_RSP: u64 = 0;
+
+/// The top of the kernel stack of the current thread.
+#[no_mangle]
+static mut NIT_KERNEL_STACK_TOP: u64 = 0;
+
+/// Set the stack used when the current thread enters a system call.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that interrupts
+/// are disabled, and that the stack is valid until the next call.
+pub unsafe fn set_kernel_stack(stack_end: VirtAddr) {
+    NIT_KERNEL_STACK_TOP = stack_end.as_u64();
+}
+
+/// Enable the `syscall` instruction.
+pub fn init() {
+    // the entry pushes them directly
+    assert_eq!(gdt::user_code_selector().0 | 3, USER_CODE_SELECTOR);
+    assert_eq!(gdt::user_data_selector().0 | 3, USER_DATA_SELECTOR);
+
+    // `syscall` loads the kernel code segment and the next one, `sysret` the
+    // user data segment and the next one, both with the requested privilege
+    let kernel_base = u64::from(gdt::kernel_code_selector().0);
+    let user_base = u64::from(USER_DATA_SELECTOR - 8);
+    unsafe {
+        let mut efer = Msr::new(EFER);
+        efer.write(efer.read() | EFER_SCE);
+        Msr::new(STAR).write(user_base << 48 | kernel_base << 32);
+        Msr::new(LSTAR).write(nit_syscall_entry as usize as u64);
+        Msr::new(SFMASK).write(SYSCALL_FLAGS_MASK);
+    }
+}
+
+/// Called by `nit_syscall_entry` with interrupts enabled.
+///
+/// The pending signals are delivered before returning to user mode.
+#[no_mangle]
+extern "C" fn nit_syscall_handler(frame: &mut TrapFrame) {
+    match frame.rax {
+        // restores every register, including `rax`
+        numbers::RT_SIGRETURN => signal::sigreturn(frame),
+        // replaces every register on success
+        numbers::EXECVE => process::execve(frame),
+        number => {
+            let arguments = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9];
+            frame.rax = dispatch(number, arguments);
+        }
+    }
+    signal::deliver(frame);
+}
@@ -0,0 +1,104 @@
+//! The error numbers returned by system calls, the ones of Linux.
+//!
+
+// internal crate
+use crate::{
+    memory::user::BadUserAddress,
+    task::{exec::ExecError, file::FileError, handle::HandleError, process::WaitError},
+};
+
+/// An error returned by a system call, as its negated value in `rax`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(i32);
+
+impl Errno {
+    /// Returns the error number.
+    pub fn as_i32(self) -> i32 {
+        self.0
+    }
+}
+
+impl From<BadUserAddress> for Errno {
+    fn from(_: BadUserAddress) -> Self {
+        EFAULT
+    }
+}
+
+impl From<HandleError> for Errno {
+    fn from(error: HandleError) -> Self {
+        match error {
+            HandleError::BadHandle | HandleError::WrongType => EBADF,
+            HandleError::TableFull => EMFILE,
+        }
+    }
+}
+
+impl From<FileError> for Errno {
+    fn from(error: FileError) -> Self {
+        match error {
+            FileError::BadDescriptor | FileError::NotReadable | FileError::NotWritable => EBADF,
+            FileError::TableFull => EMFILE,
+            FileError::BrokenPipe => EPIPE,
+            FileError::Interrupted => EINTR,
+        }
+    }
+}
+
+impl From<ExecError> for Errno {
+    fn from(error: ExecError) -> Self {
+        match error {
+            ExecError::InvalidElf(_) => ENOEXEC,
+            ExecError::OutOfMemory => ENOMEM,
+            ExecError::ArgumentsTooLong => E2BIG,
+            ExecError::NotFound => ENOENT,
+        }
+    }
+}
+
+impl From<WaitError> for Errno {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::NoChildren => ECHILD,
+            WaitError::Interrupted => EINTR,
+        }
+    }
+}
+
+/// Operation not permitted.
+pub const EPERM: Errno = Errno(1);
+/// No such file or directory.
+pub const ENOENT: Errno = Errno(2);
+/// No such process.
+pub const ESRCH: Errno = Errno(3);
+/// Interrupted system call.
+pub const EINTR: Errno = Errno(4);
+/// Argument list too long.
+pub const E2BIG: Errno = Errno(7);
+/// Exec format error.
+pub const ENOEXEC: Errno = Errno(8);
+/// Bad file descriptor.
+pub const EBADF: Errno = Errno(9);
+/// No child processes.
+pub const ECHILD: Errno = Errno(10);
+/// Resource temporarily unavailable.
+pub const EAGAIN: Errno = Errno(11);
+/// Out of memory.
+pub const ENOMEM: Errno = Errno(12);
+/// Bad address.
+pub const EFAULT: Errno = Errno(14);
+/// No such device.
+pub const ENODEV: Errno = Errno(19);
+/// Invalid argument.
+pub const EINVAL: Errno = Errno(22);
+/// Too many open files.
+pub const EMFILE: Errno = Errno(24);
+/// Broken pipe.
+pub const EPIPE: Errno = Errno(32);
+/// File name too long.
+pub const ENAMETOOLONG: Errno = Errno(36);
+/// Function not implemented.
+pub const ENOSYS: Errno = Errno(38);
+/// Message too long.
+pub const EMSGSIZE: Errno = Errno(90);
+/// Connection timed out.
+pub const ETIMEDOUT: Errno = Errno(110);
@@ -0,0 +1,77 @@
+//! The `futex` system call.
+//!
+
+// internal crate
+use super::{errno, Errno, SyscallResult};
+use crate::{
+    ipc::futex::{self, FutexError},
+    memory::user,
+    time,
+};
+
+// external crates
+use core::time::Duration;
+
+/// Wait on the futex, if it holds the given value.
+const FUTEX_WAIT: u64 = 0;
+/// Wake waiters of the futex.
+const FUTEX_WAKE: u64 = 1;
+/// The futex is private to the process : accepted, but futexes are always
+/// keyed by physical address.
+const FUTEX_PRIVATE_FLAG: u64 = 128;
+/// The timeout is measured against the realtime clock, not supported.
+const FUTEX_CLOCK_REALTIME: u64 = 256;
+
+/// Number of nanoseconds in a second.
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Convert a futex error to the error returned to user programs.
+fn futex_errno(error: FutexError) -> Errno {
+    match error {
+        FutexError::BadAddress => errno::EFAULT,
+        FutexError::WouldBlock => errno::EAGAIN,
+        FutexError::TimedOut => errno::ETIMEDOUT,
+        FutexError::Interrupted => errno::EINTR,
+    }
+}
+
+/// Run the futex operation `op` on the `u32` at `addr`.
+///
+/// `FUTEX_WAIT` blocks while the value is `value`, for at most the relative
+/// `timespec` at `timeout` if not null. `FUTEX_WAKE` wakes at most `value`
+/// waiters, and returns their number.
+pub fn futex(addr: u64, op: u64, value: u64, timeout: u64) -> SyscallResult {
+    if op & FUTEX_CLOCK_REALTIME != 0 {
+        return Err(errno::EINVAL);
+    }
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            let deadline = match timeout {
+                0 => None,
+                pointer => {
+                    let timeout = time::duration_to_ticks(read_timespec(pointer)?);
+                    Some(time::ticks().saturating_add(timeout))
+                }
+            };
+            futex::wait(addr, value as u32, deadline).map_err(futex_errno)?;
+            Ok(0)
+        }
+        FUTEX_WAKE => {
+            let count = value.min(i32::max_value() as u64) as usize;
+            let woken = futex::wake(addr, count).map_err(futex_errno)?;
+            Ok(woken as u64)
+        }
+        _ => Err(errno::ENOSYS),
+    }
+}
+
+/// Read the `timespec` at `pointer`.
+fn read_timespec(pointer: u64) -> Result<Duration, Errno> {
+    // struct timespec { time_t tv_sec; long tv_nsec; }
+    let seconds = user::read_u64(pointer)?;
+    let nanos = user::read_u64(pointer.checked_add(8).ok_or(errno::EFAULT)?)?;
+    if (seconds as i64) < 0 || nanos >= NANOS_PER_SEC {
+        return Err(errno::EINVAL);
+    }
+    Ok(Duration::new(seconds, nanos as u32))
+}
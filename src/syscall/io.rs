@@ -0,0 +1,143 @@
+//! The input and output system calls, on the files of the current process.
+//!
+//! The file is taken from the descriptor table before blocking : closing the
+//! descriptor meanwhile does not close the file until the call returns.
+//!
+
+// internal crate
+use super::{errno, with_current_process, Errno, SyscallResult};
+use crate::{
+    ipc::pipe,
+    memory::user::{self, Access},
+    task::{
+        file::{Fd, File},
+        resource::HeapCharge,
+    },
+};
+
+// external crates
+use alloc::{sync::Arc, vec};
+use core::mem;
+
+/// Maximum number of bytes transferred by a single call.
+const MAX_TRANSFER: u64 = 1 << 16;
+
+/// Size of a file descriptor in user memory.
+const FD_SIZE: usize = mem::size_of::<u32>();
+
+/// Read up to `count` bytes from `fd` into `buffer`.
+///
+/// Blocks until some input is available, or fails with `EINTR` if a signal
+/// arrives meanwhile. Reads less than `count` bytes if the kernel heap limit
+/// of the process does not allow a buffer of this size.
+pub fn read(fd: u64, buffer: u64, count: u64) -> SyscallResult {
+    let file = file(fd)?;
+    let count = count.min(MAX_TRANSFER);
+    // fail before blocking
+    user::check_range(buffer, count, Access::Write)?;
+
+    let charge = HeapCharge::up_to(count as usize).map_err(|_| errno::ENOMEM)?;
+    let mut data = vec![0; charge.bytes()];
+    let read = file.read(&mut data)?;
+    user::copy_to_user(buffer, &data[..read])?;
+    Ok(read as u64)
+}
+
+/// Write up to `count` bytes from `buffer` to `fd`.
+///
+/// Like `read`, writes less than `count` bytes if the kernel heap limit of the
+/// process does not allow a buffer of this size.
+pub fn write(fd: u64, buffer: u64, count: u64) -> SyscallResult {
+    let file = file(fd)?;
+    let count = count.min(MAX_TRANSFER) as usize;
+    let charge = HeapCharge::up_to(count).map_err(|_| errno::ENOMEM)?;
+    let mut data = vec![0; charge.bytes()];
+    user::copy_from_user(&mut data, buffer)?;
+    let written = file.write(&data)?;
+    Ok(written as u64)
+}
+
+/// Close the descriptor `fd`.
+pub fn close(fd: u64) -> SyscallResult {
+    let fd = to_fd(fd)?;
+    let file = with_current_process(|process| Ok(process.files().remove(fd)?))?;
+    // closing an end of a pipe may wake other threads
+    drop(file);
+    Ok(0)
+}
+
+/// Create a pipe, and write the descriptors of its reading and writing ends to
+/// the two `u32` at `fds_pointer`.
+pub fn pipe(fds_pointer: u64) -> SyscallResult {
+    user::check_range(fds_pointer, 2 * FD_SIZE as u64, Access::Write)?;
+
+    let (reader, writer) = pipe::create();
+    let (reader, writer): (Arc<dyn File>, Arc<dyn File>) = (reader, writer);
+    let fds = with_current_process(|process| {
+        let table = process.files();
+        let reader = table.insert(reader)?;
+        match table.insert(writer) {
+            Ok(writer) => Ok([reader, writer]),
+            Err(error) => {
+                table.remove(reader)?;
+                Err(error.into())
+            }
+        }
+    })?;
+
+    let mut bytes = [0; 2 * FD_SIZE];
+    bytes[..FD_SIZE].copy_from_slice(&fds[0].as_u32().to_le_bytes());
+    bytes[FD_SIZE..].copy_from_slice(&fds[1].as_u32().to_le_bytes());
+    if let Err(error) = user::copy_to_user(fds_pointer, &bytes) {
+        let files = with_current_process(|process| {
+            let table = process.files();
+            Ok([table.remove(fds[0]).ok(), table.remove(fds[1]).ok()])
+        });
+        drop(files);
+        return Err(error.into());
+    }
+    Ok(0)
+}
+
+/// Duplicate `fd` to the lowest free descriptor, which is returned.
+pub fn dup(fd: u64) -> SyscallResult {
+    let fd = to_fd(fd)?;
+    let new_fd = with_current_process(|process| {
+        let table = process.files();
+        let file = table.get(fd)?;
+        Ok(table.insert(file)?)
+    })?;
+    Ok(u64::from(new_fd.as_u32()))
+}
+
+/// Make `new_fd` name the file of `old_fd`, closing its previous file.
+///
+/// Returns `new_fd`.
+pub fn dup2(old_fd: u64, new_fd: u64) -> SyscallResult {
+    let (old_fd, new_fd) = (to_fd(old_fd)?, to_fd(new_fd)?);
+    let replaced = with_current_process(|process| {
+        let table = process.files();
+        let file = table.get(old_fd)?;
+        if old_fd == new_fd {
+            return Ok(None);
+        }
+        Ok(table.insert_at(new_fd, file)?)
+    })?;
+    // the replaced file may wake other threads when closed
+    drop(replaced);
+    Ok(u64::from(new_fd.as_u32()))
+}
+
+/// Returns the file named by the descriptor `fd` in the current process.
+fn file(fd: u64) -> Result<Arc<dyn File>, Errno> {
+    let fd = to_fd(fd)?;
+    with_current_process(|process| Ok(process.files().get(fd)?))
+}
+
+/// Convert a system call argument to a file descriptor.
+fn to_fd(value: u64) -> Result<Fd, Errno> {
+    if value > u64::from(u32::max_value()) {
+        return Err(errno::EBADF);
+    }
+    Ok(Fd::new(value as u32))
+}
@@ -0,0 +1,244 @@
+//! The system calls managing channels and handles.
+//!
+
+// internal crate
+use super::{errno, with_current_process, Errno, SyscallResult};
+use crate::{
+    ipc::channel::{self, ChannelError, Message, MAX_MESSAGE_HANDLES, MAX_MESSAGE_SIZE},
+    memory::user::{self, Access},
+    task::{
+        handle::{Handle, KernelObject},
+        resource::HeapCharge,
+    },
+};
+
+// external crates
+use alloc::{vec, vec::Vec};
+use core::mem;
+
+/// The call fails with `EAGAIN` instead of blocking.
+const CHANNEL_NONBLOCK: u64 = 0x1;
+
+/// Size of a handle in user memory.
+const HANDLE_SIZE: usize = mem::size_of::<u32>();
+
+/// Convert a channel error to the error returned to user programs.
+fn channel_errno(error: ChannelError) -> Errno {
+    match error {
+        ChannelError::PeerClosed => errno::EPIPE,
+        ChannelError::WouldBlock => errno::EAGAIN,
+        ChannelError::MessageTooLarge | ChannelError::BufferTooSmall => errno::EMSGSIZE,
+        ChannelError::Interrupted => errno::EINTR,
+    }
+}
+
+/// Create a channel, and write the handles of its two endpoints to the two
+/// `u32` at `handles_pointer`.
+pub fn channel_create(handles_pointer: u64) -> SyscallResult {
+    user::check_range(handles_pointer, 2 * HANDLE_SIZE as u64, Access::Write)?;
+
+    let (first, second) = channel::create();
+    let handles = with_current_process(|process| {
+        let table = process.handles();
+        let first = table.insert(KernelObject::Channel(first))?;
+        match table.insert(KernelObject::Channel(second)) {
+            Ok(second) => Ok([first, second]),
+            Err(error) => {
+                table.remove(first)?;
+                Err(error.into())
+            }
+        }
+    })?;
+
+    let mut bytes = [0; 2 * HANDLE_SIZE];
+    bytes[..HANDLE_SIZE].copy_from_slice(&handles[0].as_u32().to_le_bytes());
+    bytes[HANDLE_SIZE..].copy_from_slice(&handles[1].as_u32().to_le_bytes());
+    if let Err(error) = user::copy_to_user(handles_pointer, &bytes) {
+        close_handles(&handles);
+        return Err(error.into());
+    }
+    Ok(0)
+}
+
+/// Send the `len` bytes at `data` and the `handles_count` handles at
+/// `handles_pointer` through the channel endpoint `handle`.
+///
+/// The sent handles are removed from the process once the message is queued,
+/// even if the receiving endpoint is closed before it is read. Until then,
+/// they name no object but are not reused.
+pub fn channel_write(
+    handle: u64,
+    data: u64,
+    len: u64,
+    handles_pointer: u64,
+    handles_count: u64,
+    flags: u64,
+) -> SyscallResult {
+    if len > MAX_MESSAGE_SIZE as u64 || handles_count > MAX_MESSAGE_HANDLES as u64 {
+        return Err(errno::EMSGSIZE);
+    }
+    // the queued message is bounded by the capacity of the channel
+    let _charge = HeapCharge::new(len as usize).map_err(|_| errno::ENOMEM)?;
+    let mut bytes = vec![0; len as usize];
+    user::copy_from_user(&mut bytes, data)?;
+    let handles = read_handles(handles_pointer, handles_count as usize)?;
+    let handle = to_handle(handle)?;
+
+    // the transferred handles stay reserved until the message is queued
+    let (endpoint, objects) = with_current_process(|process| {
+        let table = process.handles();
+        let endpoint = table.channel(handle)?;
+        for (index, &transferred) in handles.iter().enumerate() {
+            if transferred == handle || handles[..index].contains(&transferred) {
+                return Err(errno::EINVAL);
+            }
+            // a channel sent through itself could never be closed
+            if let KernelObject::Channel(other) = table.get(transferred)? {
+                if other.same_channel(&endpoint) {
+                    return Err(errno::EINVAL);
+                }
+            }
+        }
+        let objects: Vec<KernelObject> = handles
+            .iter()
+            .map(|&transferred| table.reserve(transferred).unwrap())
+            .collect();
+        Ok((endpoint, objects))
+    })?;
+
+    let message = Message {
+        data: bytes,
+        handles: objects,
+    };
+    let sent = endpoint.send(message, flags & CHANNEL_NONBLOCK == 0);
+    let released = with_current_process(|process| {
+        let table = process.handles();
+        let mut released = Vec::new();
+        for &transferred in &handles {
+            if sent.is_ok() {
+                released.extend(table.release(transferred));
+            } else {
+                // give the handle back, the message only held copies
+                table.restore(transferred);
+            }
+        }
+        Ok(released)
+    })?;
+    // the objects may wake other threads when freed
+    drop(released);
+    match sent {
+        Ok(()) => Ok(0),
+        Err((error, _)) => Err(channel_errno(error)),
+    }
+}
+
+/// Receive a message from the channel endpoint `handle`, copying its bytes to
+/// `data` and its handles to `handles_pointer`.
+///
+/// Returns the number of bytes of the message, plus the number of handles
+/// shifted by 32 bits. If the message does not fit, it stays queued and
+/// `EMSGSIZE` is returned. It is also put back in the queue if its handles do
+/// not fit in the handle table, with `EMFILE`, or if it cannot be copied.
+pub fn channel_read(
+    handle: u64,
+    data: u64,
+    capacity: u64,
+    handles_pointer: u64,
+    handles_capacity: u64,
+    flags: u64,
+) -> SyscallResult {
+    let capacity = capacity.min(MAX_MESSAGE_SIZE as u64);
+    let handles_capacity = handles_capacity.min(MAX_MESSAGE_HANDLES as u64);
+    // fail before the message is taken
+    user::check_range(data, capacity, Access::Write)?;
+    user::check_range(
+        handles_pointer,
+        handles_capacity * HANDLE_SIZE as u64,
+        Access::Write,
+    )?;
+
+    let handle = to_handle(handle)?;
+    let endpoint = with_current_process(|process| Ok(process.handles().channel(handle)?))?;
+    let message = endpoint
+        .receive(
+            flags & CHANNEL_NONBLOCK == 0,
+            capacity as usize,
+            handles_capacity as usize,
+        )
+        .map_err(channel_errno)?;
+
+    // install copies of the received objects, only if they all fit : the
+    // message keeps them until it is consumed
+    let received = with_current_process(|process| {
+        let table = process.handles();
+        if message.handles.len() > table.available() {
+            return Ok(None);
+        }
+        let received: Vec<Handle> = message
+            .handles
+            .iter()
+            .map(|object| table.insert(object.clone()).expect("handle table full"))
+            .collect();
+        Ok(Some(received))
+    })?;
+    let received = match received {
+        Some(received) => received,
+        None => {
+            endpoint.requeue(message);
+            return Err(errno::EMFILE);
+        }
+    };
+
+    let mut handle_bytes = Vec::with_capacity(received.len() * HANDLE_SIZE);
+    for handle in &received {
+        handle_bytes.extend_from_slice(&handle.as_u32().to_le_bytes());
+    }
+    let copied = user::copy_to_user(data, &message.data)
+        .and_then(|_| user::copy_to_user(handles_pointer, &handle_bytes));
+    if let Err(error) = copied {
+        close_handles(&received);
+        endpoint.requeue(message);
+        return Err(error.into());
+    }
+    Ok(message.data.len() as u64 | (received.len() as u64) << 32)
+}
+
+/// Close `handle`.
+pub fn handle_close(handle: u64) -> SyscallResult {
+    let handle = to_handle(handle)?;
+    let object = with_current_process(|process| Ok(process.handles().remove(handle)?))?;
+    // the object may wake other threads when freed
+    drop(object);
+    Ok(0)
+}
+
+/// Convert a system call argument to a handle.
+fn to_handle(value: u64) -> Result<Handle, Errno> {
+    if value > u64::from(u32::max_value()) {
+        return Err(errno::EBADF);
+    }
+    Ok(Handle::new(value as u32))
+}
+
+/// Read `count` handles from the user memory at `pointer`.
+fn read_handles(pointer: u64, count: usize) -> Result<Vec<Handle>, Errno> {
+    let mut bytes = vec![0; count * HANDLE_SIZE];
+    user::copy_from_user(&mut bytes, pointer)?;
+    let handles = bytes
+        .chunks_exact(HANDLE_SIZE)
+        .map(|chunk| Handle::new(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])))
+        .collect();
+    Ok(handles)
+}
+
+/// Close the given handles of the current process, ignoring invalid ones.
+fn close_handles(handles: &[Handle]) {
+    let objects = with_current_process(|process| {
+        let objects: Vec<_> = handles
+            .iter()
+            .filter_map(|&handle| process.handles().remove(handle).ok())
+            .collect();
+        Ok(objects)
+    });
+    drop(objects);
+}
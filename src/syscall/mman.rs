@@ -0,0 +1,272 @@
+//! The system calls managing the memory of the current process.
+//!
+//! Only anonymous mappings are supported. Their pages are backed by zeroed
+//! frames on their first access, or as soon as they are created with
+//! `MAP_POPULATE`.
+//!
+//! Shared memory objects are created as handles, which can be sent to other
+//! processes through channels and mapped by each of them.
+//!
+//! The mappings of a process reserve at most `Limits::max_memory` bytes.
+//!
+
+// internal crate
+use super::{errno, with_address_space, with_current_process, Errno, SyscallResult};
+use crate::{
+    memory::{
+        address_space::{AddressSpace, Backing, Region},
+        layout,
+        shared::{SharedMemory, SharedMemoryError},
+        with_frame_allocator,
+    },
+    task::handle::{Handle, KernelObject},
+};
+
+// external crates
+use alloc::sync::Arc;
+use x86_64::{
+    structures::paging::{Page, PageSize, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+/// Pages can be read.
+const PROT_READ: u64 = 0x1;
+/// Pages can be written.
+const PROT_WRITE: u64 = 0x2;
+/// Pages can be executed.
+const PROT_EXEC: u64 = 0x4;
+
+/// Changes are shared with other mappings of the same memory.
+const MAP_SHARED: u64 = 0x01;
+/// Changes are private to the mapping.
+const MAP_PRIVATE: u64 = 0x02;
+/// The mapping is placed exactly at the given address.
+const MAP_FIXED: u64 = 0x10;
+/// The mapping is not backed by a file.
+const MAP_ANONYMOUS: u64 = 0x20;
+/// The pages are backed by frames when the mapping is created.
+const MAP_POPULATE: u64 = 0x8000;
+
+/// Map `len` bytes of zeroed memory, at `addr` if possible.
+///
+/// Returns the address of the mapping.
+pub fn mmap(
+    addr: u64,
+    len: u64,
+    prot: u64,
+    flags: u64,
+    _fd: u64,
+    _offset: u64,
+) -> SyscallResult {
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err(errno::ENODEV);
+    }
+    let sharing = flags & (MAP_SHARED | MAP_PRIVATE);
+    if sharing != MAP_SHARED && sharing != MAP_PRIVATE {
+        return Err(errno::EINVAL);
+    }
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(errno::EINVAL);
+    }
+    let len = page_align(len)?;
+    let page_flags = page_flags(prot);
+
+    with_current_process(|process| {
+        let max_memory = process.limits().max_memory;
+        let address_space = process.address_space().ok_or(errno::EFAULT)?;
+        let start = if flags & MAP_FIXED != 0 {
+            if !is_page_aligned(addr) || !layout::is_user_range(addr, len) {
+                return Err(errno::EINVAL);
+            }
+            VirtAddr::new(addr)
+        } else {
+            find_range(address_space, addr, len).ok_or(errno::ENOMEM)?
+        };
+        check_memory_limit(address_space, max_memory, start, len)?;
+        address_space.remove_range(start, start + len);
+
+        let region = Region {
+            start,
+            end: start + len,
+            flags: page_flags,
+            backing: Backing::Anonymous,
+        };
+        address_space
+            .add_region(region.clone())
+            .expect("mapping overlaps a region");
+        if flags & MAP_POPULATE != 0 && !page_flags.is_empty() {
+            populate(address_space, &region)?;
+        }
+        Ok(start.as_u64())
+    })
+}
+
+/// Unmap the pages of `addr..addr + len`, and free their memory.
+pub fn munmap(addr: u64, len: u64) -> SyscallResult {
+    let len = page_align(len)?;
+    if !is_page_aligned(addr) || !layout::is_user_range(addr, len) {
+        return Err(errno::EINVAL);
+    }
+    with_address_space(|address_space| {
+        let start = VirtAddr::new(addr);
+        address_space.remove_range(start, start + len);
+        Ok(0)
+    })
+}
+
+/// Create a shared memory object of `size` bytes, rounded up to a multiple of
+/// the page size.
+///
+/// Fails with `ENOMEM` if the object could never be mapped within the memory
+/// limit of the process. Returns its handle.
+pub fn shm_create(size: u64) -> SyscallResult {
+    let max_memory = with_current_process(|process| Ok(process.limits().max_memory))?;
+    if size > max_memory {
+        return Err(errno::ENOMEM);
+    }
+    let memory = SharedMemory::new(size).map_err(|error| match error {
+        SharedMemoryError::InvalidSize => errno::EINVAL,
+        SharedMemoryError::OutOfMemory => errno::ENOMEM,
+    })?;
+    let object = KernelObject::SharedMemory(Arc::new(memory));
+    let handle = with_current_process(|process| Ok(process.handles().insert(object)?))?;
+    Ok(u64::from(handle.as_u32()))
+}
+
+/// Map the whole shared memory object `handle` with the protection `prot`, at
+/// `addr` if possible or exactly there with `MAP_FIXED`.
+///
+/// Returns the address of the mapping. It stays valid after the handle is
+/// closed.
+pub fn shm_map(handle: u64, addr: u64, prot: u64, flags: u64) -> SyscallResult {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 || flags & !MAP_FIXED != 0 {
+        return Err(errno::EINVAL);
+    }
+    if handle > u64::from(u32::max_value()) {
+        return Err(errno::EBADF);
+    }
+    let handle = Handle::new(handle as u32);
+    let page_flags = page_flags(prot);
+
+    with_current_process(|process| {
+        let memory = process.handles().shared_memory(handle)?;
+        let max_memory = process.limits().max_memory;
+        let address_space = process.address_space().ok_or(errno::EFAULT)?;
+        let len = memory.size();
+        let start = if flags & MAP_FIXED != 0 {
+            if !is_page_aligned(addr) || !layout::is_user_range(addr, len) {
+                return Err(errno::EINVAL);
+            }
+            VirtAddr::new(addr)
+        } else {
+            find_range(address_space, addr, len).ok_or(errno::ENOMEM)?
+        };
+        check_memory_limit(address_space, max_memory, start, len)?;
+        address_space.remove_range(start, start + len);
+
+        if page_flags.is_empty() {
+            let region = Region {
+                start,
+                end: start + len,
+                flags: page_flags,
+                backing: Backing::Shared { memory, offset: 0 },
+            };
+            address_space
+                .add_region(region)
+                .expect("mapping overlaps a region");
+        } else {
+            address_space
+                .map_shared(start, &memory, page_flags)
+                .map_err(|_| errno::ENOMEM)?;
+        }
+        Ok(start.as_u64())
+    })
+}
+
+/// Fail with `ENOMEM` if mapping `start..start + len`, in place of what is
+/// mapped there, would reserve more than `max_memory` bytes.
+fn check_memory_limit(
+    address_space: &AddressSpace,
+    max_memory: u64,
+    start: VirtAddr,
+    len: u64,
+) -> Result<(), Errno> {
+    let end = start + len;
+    let replaced: u64 = address_space
+        .regions()
+        .iter()
+        .filter(|region| region.overlaps(start, end))
+        .map(|region| region.end.min(end) - region.start.max(start))
+        .sum();
+    if address_space.reserved_size() - replaced + len > max_memory {
+        return Err(errno::ENOMEM);
+    }
+    Ok(())
+}
+
+/// Returns the flags of the pages mapped with the protection `prot`, empty if
+/// the pages must not be mapped.
+fn page_flags(prot: u64) -> PageTableFlags {
+    if prot == 0 {
+        return PageTableFlags::empty();
+    }
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+/// Round `len` up to a multiple of the page size, refusing empty lengths.
+fn page_align(len: u64) -> Result<u64, Errno> {
+    let page_size = Size4KiB::SIZE;
+    match len.checked_add(page_size - 1) {
+        Some(end) if len > 0 => Ok(end & !(page_size - 1)),
+        _ => Err(errno::EINVAL),
+    }
+}
+
+/// Returns `true` if `addr` is a multiple of the page size.
+fn is_page_aligned(addr: u64) -> bool {
+    addr % Size4KiB::SIZE == 0
+}
+
+/// Returns a free range of `len` bytes, at `hint` if possible.
+fn find_range(address_space: &AddressSpace, hint: u64, len: u64) -> Option<VirtAddr> {
+    if hint != 0 && is_page_aligned(hint) && layout::is_user_range(hint, len) {
+        let hint = VirtAddr::new(hint);
+        let end = hint + len;
+        if address_space.find_free_range(hint, end, len).is_some() {
+            return Some(hint);
+        }
+    }
+
+    let end = VirtAddr::new(layout::USER_SPACE_END);
+    address_space
+        .find_free_range(VirtAddr::new(layout::USER_MMAP_START), end, len)
+        .or_else(|| {
+            address_space.find_free_range(VirtAddr::new(layout::USER_SPACE_START), end, len)
+        })
+}
+
+/// Back the pages of the new `region` with zeroed frames.
+///
+/// On failure, the region is removed.
+fn populate(address_space: &mut AddressSpace, region: &Region) -> Result<(), Errno> {
+    let populated = with_frame_allocator(|frame_allocator| {
+        let start = Page::containing_address(region.start);
+        let end = Page::containing_address(region.end);
+        for page in Page::range(start, end) {
+            address_space.map_zeroed(page, region.flags, frame_allocator)?;
+        }
+        Ok(())
+    });
+    if populated.is_err() {
+        address_space.remove_range(region.start, region.end);
+        return Err(errno::ENOMEM);
+    }
+    Ok(())
+}
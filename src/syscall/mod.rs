@@ -0,0 +1,110 @@
+//! This module contains the system call interface of user programs.
+//!
+//! User programs enter the kernel with the `syscall` instruction, following
+//! the Linux conventions : the number in `rax`, the arguments in `rdi`, `rsi`,
+//! `rdx`, `r10`, `r8` and `r9`, and the result in `rax`, errors being returned
+//! as negated `Errno` values.
+//!
+//! User memory is only accessed through `memory::user` : a bad pointer
+//! returns `EFAULT` instead of faulting the kernel.
+//!
+
+// submodules
+mod arch;
+mod clock;
+mod entry;
+mod futex;
+mod io;
+mod ipc;
+mod mman;
+mod process;
+mod resource;
+mod signal;
+
+// public submodules
+pub mod errno;
+pub mod numbers;
+
+// submodules exports
+pub use entry::set_kernel_stack;
+pub use errno::Errno;
+
+// internal crate
+use crate::{
+    memory::address_space::AddressSpace,
+    task::process::{self as task_process, Process},
+};
+
+/// The result of a system call.
+pub type SyscallResult = Result<u64, Errno>;
+
+/// Enable system calls.
+pub fn init() {
+    entry::init();
+}
+
+/// Run the system call `number` with the given arguments.
+///
+/// Returns the value to store in `rax`.
+fn dispatch(number: u64, arguments: [u64; 6]) -> u64 {
+    let [a0, a1, a2, a3, a4, a5] = arguments;
+    let result = match number {
+        numbers::READ => io::read(a0, a1, a2),
+        numbers::WRITE => io::write(a0, a1, a2),
+        numbers::CLOSE => io::close(a0),
+        numbers::MMAP => mman::mmap(a0, a1, a2, a3, a4, a5),
+        numbers::MUNMAP => mman::munmap(a0, a1),
+        numbers::RT_SIGACTION => signal::rt_sigaction(a0, a1, a2, a3),
+        numbers::RT_SIGPROCMASK => signal::rt_sigprocmask(a0, a1, a2, a3),
+        numbers::PIPE => io::pipe(a0),
+        numbers::SCHED_YIELD => process::sched_yield(),
+        numbers::DUP => io::dup(a0),
+        numbers::DUP2 => io::dup2(a0, a1),
+        numbers::GETPID => process::getpid(),
+        numbers::EXIT | numbers::EXIT_GROUP => process::exit(a0),
+        numbers::WAIT4 => process::wait4(a0, a1, a2, a3),
+        numbers::KILL => signal::kill(a0, a1),
+        numbers::GETRLIMIT => resource::getrlimit(a0, a1),
+        numbers::GETRUSAGE => resource::getrusage(a0, a1),
+        numbers::GETPPID => process::getppid(),
+        numbers::ARCH_PRCTL => arch::arch_prctl(a0, a1),
+        numbers::SETRLIMIT => resource::setrlimit(a0, a1),
+        numbers::FUTEX => futex::futex(a0, a1, a2, a3),
+        numbers::CLOCK_GETTIME => clock::clock_gettime(a0, a1),
+        numbers::CHANNEL_CREATE => ipc::channel_create(a0),
+        numbers::CHANNEL_WRITE => ipc::channel_write(a0, a1, a2, a3, a4, a5),
+        numbers::CHANNEL_READ => ipc::channel_read(a0, a1, a2, a3, a4, a5),
+        numbers::HANDLE_CLOSE => ipc::handle_close(a0),
+        numbers::SHM_CREATE => mman::shm_create(a0),
+        numbers::SHM_MAP => mman::shm_map(a0, a1, a2, a3),
+        numbers::SPAWN => process::spawn(a0, a1, a2),
+        _ => Err(errno::ENOSYS),
+    };
+    return_value(result)
+}
+
+/// Returns the value to store in `rax` for `result`.
+fn return_value(result: SyscallResult) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(error) => -i64::from(error.as_i32()) as u64,
+    }
+}
+
+// ! ------------- current process -------------
+
+/// Run `f` with the current process, interrupts disabled.
+fn with_current_process<F, R>(f: F) -> Result<R, Errno>
+where
+    F: FnOnce(&mut Process) -> Result<R, Errno>,
+{
+    task_process::with_process(task_process::current(), f).map_err(|_| errno::ESRCH)?
+}
+
+/// Run `f` with the address space of the current process, interrupts disabled.
+fn with_address_space<F, R>(f: F) -> Result<R, Errno>
+where
+    F: FnOnce(&mut AddressSpace) -> Result<R, Errno>,
+{
+    with_current_process(|process| process.address_space().ok_or(errno::EFAULT).and_then(f))
+}
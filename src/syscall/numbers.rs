@@ -0,0 +1,41 @@
+//! The numbers of the system calls, passed in `rax`.
+//!
+//! They are the ones of Linux on x86_64, so that existing toolchains can
+//! target the kernel.
+//!
+
+pub const READ: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const CLOSE: u64 = 3;
+pub const MMAP: u64 = 9;
+pub const MUNMAP: u64 = 11;
+pub const RT_SIGACTION: u64 = 13;
+pub const RT_SIGPROCMASK: u64 = 14;
+pub const RT_SIGRETURN: u64 = 15;
+pub const PIPE: u64 = 22;
+pub const SCHED_YIELD: u64 = 24;
+pub const DUP: u64 = 32;
+pub const DUP2: u64 = 33;
+pub const GETPID: u64 = 39;
+pub const EXECVE: u64 = 59;
+pub const EXIT: u64 = 60;
+pub const WAIT4: u64 = 61;
+pub const KILL: u64 = 62;
+pub const GETRLIMIT: u64 = 97;
+pub const GETRUSAGE: u64 = 98;
+pub const GETPPID: u64 = 110;
+pub const ARCH_PRCTL: u64 = 158;
+pub const SETRLIMIT: u64 = 160;
+pub const FUTEX: u64 = 202;
+pub const CLOCK_GETTIME: u64 = 228;
+pub const EXIT_GROUP: u64 = 231;
+
+// custom system calls, numbered after the Linux ones
+
+pub const CHANNEL_CREATE: u64 = 512;
+pub const CHANNEL_WRITE: u64 = 513;
+pub const CHANNEL_READ: u64 = 514;
+pub const HANDLE_CLOSE: u64 = 515;
+pub const SHM_CREATE: u64 = 516;
+pub const SHM_MAP: u64 = 517;
+pub const SPAWN: u64 = 518;
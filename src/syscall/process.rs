@@ -0,0 +1,167 @@
+//! The system calls managing processes.
+//!
+//! `execve` is handled by the system call entry, as it replaces every register
+//! of the thread.
+//!
+
+// internal crate
+use super::{errno, resource, return_value, with_current_process, Errno, SyscallResult};
+use crate::{
+    interrupts::trap::TrapFrame,
+    memory::user,
+    task::{
+        exec::{self, UserEntry},
+        process::{self, Pid, ProcessState},
+        scheduler,
+    },
+};
+
+// external crates
+use alloc::vec::Vec;
+use core::mem;
+
+/// Maximum length of a path, without its terminator.
+const PATH_MAX: usize = 4095;
+/// Maximum length of an argument or environment string.
+const MAX_STRING_LENGTH: usize = 4096;
+/// Maximum number of arguments, and of environment strings.
+const MAX_STRINGS: usize = 256;
+
+/// `wait4` returns 0 instead of blocking.
+const WNOHANG: u64 = 0x1;
+
+/// Flags of a program starting : interrupts and the reserved bit.
+const INITIAL_FLAGS: u64 = 0x202;
+
+/// Terminate the current process with the given exit code.
+pub fn exit(code: u64) -> ! {
+    process::exit(code as i32)
+}
+
+/// Give up the rest of the time slice of the current thread.
+pub fn sched_yield() -> SyscallResult {
+    scheduler::yield_now();
+    Ok(0)
+}
+
+/// Returns the identifier of the current process.
+pub fn getpid() -> SyscallResult {
+    Ok(process::current().as_u64())
+}
+
+/// Returns the identifier of the parent of the current process.
+pub fn getppid() -> SyscallResult {
+    with_current_process(|process| Ok(process.parent().as_u64()))
+}
+
+/// Start the executable at `path` in a new process, child of the current one,
+/// with the null-terminated arrays of strings `argv` and `envp`.
+///
+/// The child inherits the file descriptors. Returns its identifier.
+pub fn spawn(path: u64, argv: u64, envp: u64) -> SyscallResult {
+    let path = read_path(path)?;
+    let (argv, envp) = (read_strings(argv)?, read_strings(envp)?);
+    let pid = exec::spawn_path(&path, &as_slices(&argv), &as_slices(&envp))?;
+    Ok(pid.as_u64())
+}
+
+/// Replace the program of the current process by the executable at the path
+/// in `rdi`, with the arguments in `rsi` and the environment in `rdx`.
+///
+/// On success, the thread resumes at the entry of the program with cleared
+/// registers. Otherwise, the error is returned in `rax`.
+pub fn execve(frame: &mut TrapFrame) {
+    match load_program(frame.rdi, frame.rsi, frame.rdx) {
+        Ok(user_entry) => {
+            *frame = TrapFrame {
+                rip: user_entry.entry.as_u64(),
+                cs: frame.cs,
+                rflags: INITIAL_FLAGS,
+                rsp: user_entry.stack_pointer.as_u64(),
+                ss: frame.ss,
+                vector: frame.vector,
+                ..TrapFrame::default()
+            };
+        }
+        Err(error) => frame.rax = return_value(Err(error)),
+    }
+}
+
+/// Wait for the child `pid` to terminate, any child if `pid` is -1, and write
+/// its status to `status` and its resource usage to `rusage` if not null.
+///
+/// Returns the identifier of the child, or 0 if none has terminated and
+/// `WNOHANG` is given. Process groups are not supported.
+pub fn wait4(pid: u64, status: u64, options: u64, rusage: u64) -> SyscallResult {
+    let pid = match pid as i64 {
+        -1 => None,
+        pid if pid > 0 => Some(Pid::from_u64(pid as u64)),
+        _ => return Err(errno::EINVAL),
+    };
+    if options & !WNOHANG != 0 {
+        return Err(errno::EINVAL);
+    }
+    if status != 0 {
+        user::check_range(status, mem::size_of::<u32>() as u64, user::Access::Write)?;
+    }
+    if rusage != 0 {
+        user::check_range(rusage, resource::RUSAGE_SIZE, user::Access::Write)?;
+    }
+
+    let terminated = match process::wait(pid, options & WNOHANG == 0)? {
+        Some(terminated) => terminated,
+        None => return Ok(0),
+    };
+    if status != 0 {
+        // a process terminated by a signal has no exit code
+        let value = match terminated.state {
+            ProcessState::Exited(code) => (code as u32 & 0xff) << 8,
+            ProcessState::Killed(signal) => u32::from(signal.as_u8()),
+            ProcessState::Running => unreachable!("waited process is running"),
+        };
+        user::copy_to_user(status, &value.to_le_bytes())?;
+    }
+    if rusage != 0 {
+        resource::write_rusage(rusage, &terminated.usage)?;
+    }
+    Ok(terminated.pid.as_u64())
+}
+
+/// Load the program of `execve` in the current process.
+fn load_program(path: u64, argv: u64, envp: u64) -> Result<UserEntry, Errno> {
+    let path = read_path(path)?;
+    let (argv, envp) = (read_strings(argv)?, read_strings(envp)?);
+    let image = exec::lookup(&path).ok_or(errno::ENOENT)?;
+    Ok(exec::replace_current(
+        image,
+        &as_slices(&argv),
+        &as_slices(&envp),
+    )?)
+}
+
+/// Read the path at `pointer`.
+fn read_path(pointer: u64) -> Result<Vec<u8>, Errno> {
+    user::read_string(pointer, PATH_MAX)?.ok_or(errno::ENAMETOOLONG)
+}
+
+/// Read the null-terminated array of strings at `pointer`, if not null.
+fn read_strings(pointer: u64) -> Result<Vec<Vec<u8>>, Errno> {
+    let mut strings = Vec::new();
+    if pointer == 0 {
+        return Ok(strings);
+    }
+    loop {
+        let address = pointer + (strings.len() * mem::size_of::<u64>()) as u64;
+        let string = match user::read_u64(address)? {
+            0 => return Ok(strings),
+            _ if strings.len() == MAX_STRINGS => return Err(errno::E2BIG),
+            string => string,
+        };
+        strings.push(user::read_string(string, MAX_STRING_LENGTH)?.ok_or(errno::E2BIG)?);
+    }
+}
+
+/// Returns the slices of `strings`.
+fn as_slices(strings: &[Vec<u8>]) -> Vec<&[u8]> {
+    strings.iter().map(|string| &string[..]).collect()
+}
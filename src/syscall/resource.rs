@@ -0,0 +1,152 @@
+//! The system calls managing the resource limits and usage of the current
+//! process.
+//!
+//! Only some limits are supported : `RLIMIT_AS` for the reserved user memory,
+//! `RLIMIT_NOFILE` for the handles and the file descriptors, and
+//! `RLIMIT_NPROC` for the threads of the process. The other ones are reported
+//! as infinite, and cannot be set. A limit has no distinct soft and hard
+//! values, and can only be lowered.
+//!
+
+// internal crate
+use super::{errno, with_current_process, Errno, SyscallResult};
+use crate::{
+    memory::user::{self, Access},
+    task::resource::{Limits, Usage},
+    time,
+};
+
+// external crates
+use alloc::vec::Vec;
+use core::{convert::TryFrom, mem};
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+/// Limit of the number of threads, of users in Linux.
+const RLIMIT_NPROC: u64 = 6;
+/// Limit of the number of file descriptors.
+const RLIMIT_NOFILE: u64 = 7;
+/// Limit of the size of the address space.
+const RLIMIT_AS: u64 = 9;
+/// Number of limits.
+const RLIMIT_NLIMITS: u64 = 16;
+
+/// The value of an infinite limit.
+const RLIM_INFINITY: u64 = u64::max_value();
+
+/// The usage of the current process.
+const RUSAGE_SELF: u64 = 0;
+/// The usage of the collected children of the current process.
+const RUSAGE_CHILDREN: u64 = -1i64 as u64;
+
+/// Size of a `u64` in user memory.
+const WORD_SIZE: u64 = mem::size_of::<u64>() as u64;
+/// Size of a `rlimit` structure in user memory : the soft and hard limits.
+const RLIMIT_SIZE: u64 = 2 * WORD_SIZE;
+/// Size of a `rusage` structure in user memory : two `timeval` and 14 counters.
+pub(super) const RUSAGE_SIZE: u64 = 18 * WORD_SIZE;
+
+/// Write the limit of `resource` to the `rlimit` at `limit`.
+pub fn getrlimit(resource: u64, limit: u64) -> SyscallResult {
+    if resource >= RLIMIT_NLIMITS {
+        return Err(errno::EINVAL);
+    }
+    let value = with_current_process(|process| Ok(limit_value(&process.limits(), resource)))?;
+    let value = value.unwrap_or(RLIM_INFINITY);
+    user::check_range(limit, RLIMIT_SIZE, Access::Write)?;
+    user::write_u64(limit, value)?;
+    user::write_u64(limit + WORD_SIZE, value)?;
+    Ok(0)
+}
+
+/// Set the limit of `resource` to the soft limit of the `rlimit` at `limit`.
+///
+/// Fails with `EPERM` if it is higher than the current limit.
+pub fn setrlimit(resource: u64, limit: u64) -> SyscallResult {
+    if resource >= RLIMIT_NLIMITS {
+        return Err(errno::EINVAL);
+    }
+    user::check_range(limit, RLIMIT_SIZE, Access::Read)?;
+    let soft = user::read_u64(limit)?;
+    let hard = user::read_u64(limit + WORD_SIZE)?;
+    if soft > hard {
+        return Err(errno::EINVAL);
+    }
+
+    with_current_process(|process| {
+        let mut limits = process.limits();
+        let current = limit_value(&limits, resource).ok_or(errno::EINVAL)?;
+        if soft > current {
+            return Err(errno::EPERM);
+        }
+        match resource {
+            RLIMIT_NPROC => limits.max_threads = to_count(soft),
+            RLIMIT_NOFILE => limits.max_handles = to_count(soft),
+            RLIMIT_AS => limits.max_memory = soft,
+            _ => unreachable!("unsupported limit has a value"),
+        }
+        process.set_limits(limits);
+        Ok(0)
+    })
+}
+
+/// Write the resource usage of the current process, or of its collected
+/// children, to the `rusage` at `usage`.
+pub fn getrusage(who: u64, usage: u64) -> SyscallResult {
+    let value = with_current_process(|process| match who {
+        RUSAGE_SELF => Ok(process.usage()),
+        RUSAGE_CHILDREN => Ok(process.children_usage()),
+        _ => Err(errno::EINVAL),
+    })?;
+    write_rusage(usage, &value)?;
+    Ok(0)
+}
+
+/// Write `usage` as a `rusage` structure to `pointer`.
+///
+/// Only the CPU times and the maximum resident size are known, the other
+/// counters are 0.
+pub(super) fn write_rusage(pointer: u64, usage: &Usage) -> Result<(), Errno> {
+    let (user_seconds, user_microseconds) = timeval(usage.user_ticks);
+    let (system_seconds, system_microseconds) = timeval(usage.system_ticks);
+    // in KiB
+    let max_resident = usage.peak_resident_pages * Size4KiB::SIZE / 1024;
+
+    let mut words = Vec::with_capacity((RUSAGE_SIZE / WORD_SIZE) as usize);
+    words.extend_from_slice(&[
+        user_seconds,
+        user_microseconds,
+        system_seconds,
+        system_microseconds,
+        max_resident,
+    ]);
+    words.resize((RUSAGE_SIZE / WORD_SIZE) as usize, 0);
+
+    let mut bytes = Vec::with_capacity(RUSAGE_SIZE as usize);
+    for word in words {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    user::copy_to_user(pointer, &bytes)?;
+    Ok(())
+}
+
+/// Returns the value of the limit of `resource`, `None` if it is not
+/// supported.
+fn limit_value(limits: &Limits, resource: u64) -> Option<u64> {
+    match resource {
+        RLIMIT_NPROC => Some(limits.max_threads as u64),
+        RLIMIT_NOFILE => Some(limits.max_handles as u64),
+        RLIMIT_AS => Some(limits.max_memory),
+        _ => None,
+    }
+}
+
+/// Convert a limit to a count.
+fn to_count(value: u64) -> usize {
+    usize::try_from(value).unwrap_or(usize::max_value())
+}
+
+/// Convert `ticks` to seconds and microseconds.
+fn timeval(ticks: u64) -> (u64, u64) {
+    let duration = time::ticks_to_duration(ticks);
+    (duration.as_secs(), u64::from(duration.subsec_micros()))
+}
@@ -0,0 +1,162 @@
+//! The system calls managing signals.
+//!
+//! `rt_sigreturn` is handled by the system call entry, as it replaces every
+//! register of the thread.
+//!
+
+// internal crate
+use super::{errno, with_current_process, Errno, SyscallResult};
+use crate::{
+    memory::user,
+    task::{
+        process::{self, Pid},
+        signal::{self, Action, Handler, Signal, SA_RESTORER},
+    },
+};
+
+// external crates
+use core::mem;
+
+/// The handler of the default action.
+const SIG_DFL: u64 = 0;
+/// The handler ignoring the signal.
+const SIG_IGN: u64 = 1;
+
+/// The handler expects a `siginfo` structure, which is not supported.
+const SA_SIGINFO: u64 = 0x4;
+
+/// Block the given signals.
+const SIG_BLOCK: u64 = 0;
+/// Unblock the given signals.
+const SIG_UNBLOCK: u64 = 1;
+/// Replace the blocked signals.
+const SIG_SETMASK: u64 = 2;
+
+/// Size of a signal set in user memory.
+const SIGSET_SIZE: u64 = mem::size_of::<u64>() as u64;
+/// Size of a `sigaction` structure in user memory : the handler, the flags,
+/// the restorer and the mask.
+const SIGACTION_SIZE: u64 = 4 * SIGSET_SIZE;
+
+/// Set the action of `signal` to the `sigaction` at `action`, if not null,
+/// and write the previous one to `old_action`, if not null.
+///
+/// Handlers must return to a restorer, given with `SA_RESTORER`.
+pub fn rt_sigaction(signal: u64, action: u64, old_action: u64, set_size: u64) -> SyscallResult {
+    let signal = Signal::new(signal).ok_or(errno::EINVAL)?;
+    if set_size != SIGSET_SIZE || (action != 0 && !signal.is_catchable()) {
+        return Err(errno::EINVAL);
+    }
+    let action = match action {
+        0 => None,
+        pointer => Some(read_action(pointer)?),
+    };
+    if old_action != 0 {
+        user::check_range(old_action, SIGACTION_SIZE, user::Access::Write)?;
+    }
+
+    let previous = with_current_process(|process| {
+        let signals = process.signals();
+        Ok(match action {
+            Some(action) => signals.set_action(signal, action),
+            None => signals.action(signal),
+        })
+    })?;
+    if old_action != 0 {
+        write_action(old_action, previous)?;
+    }
+    Ok(0)
+}
+
+/// Change the blocked signals as told by `how`, with the set at `set` if not
+/// null, and write the previous set to `old_set` if not null.
+pub fn rt_sigprocmask(how: u64, set: u64, old_set: u64, set_size: u64) -> SyscallResult {
+    if set_size != SIGSET_SIZE {
+        return Err(errno::EINVAL);
+    }
+    let set = match set {
+        0 => None,
+        pointer => Some(user::read_u64(pointer)?),
+    };
+    if old_set != 0 {
+        user::check_range(old_set, SIGSET_SIZE, user::Access::Write)?;
+    }
+
+    let previous = with_current_process(|process| {
+        let signals = process.signals();
+        let previous = signals.blocked();
+        if let Some(set) = set {
+            let blocked = match how {
+                SIG_BLOCK => previous | set,
+                SIG_UNBLOCK => previous & !set,
+                SIG_SETMASK => set,
+                _ => return Err(errno::EINVAL),
+            };
+            signals.set_blocked(blocked);
+        }
+        Ok(previous)
+    })?;
+    if old_set != 0 {
+        user::write_u64(old_set, previous)?;
+    }
+    Ok(0)
+}
+
+/// Send `signal` to the process `pid`.
+///
+/// The signal 0 only checks that the process exists. Process groups are not
+/// supported : `pid` must be positive.
+pub fn kill(pid: u64, signal: u64) -> SyscallResult {
+    if pid as i64 <= 0 {
+        return Err(errno::EINVAL);
+    }
+    let pid = Pid::from_u64(pid);
+    if signal == 0 {
+        let running = process::with_process(pid, |process| {
+            process.state() == process::ProcessState::Running
+        });
+        return match running {
+            Ok(true) => Ok(0),
+            _ => Err(errno::ESRCH),
+        };
+    }
+
+    let signal = Signal::new(signal).ok_or(errno::EINVAL)?;
+    signal::send(pid, signal).map_err(|_| errno::ESRCH)?;
+    Ok(0)
+}
+
+/// Read the `sigaction` structure at `pointer`.
+fn read_action(pointer: u64) -> Result<Action, Errno> {
+    user::check_range(pointer, SIGACTION_SIZE, user::Access::Read)?;
+    let entry = user::read_u64(pointer)?;
+    let flags = user::read_u64(pointer + SIGSET_SIZE)?;
+    let restorer = user::read_u64(pointer + 2 * SIGSET_SIZE)?;
+    let mask = user::read_u64(pointer + 3 * SIGSET_SIZE)?;
+
+    match entry {
+        SIG_DFL => Ok(Action::Default),
+        SIG_IGN => Ok(Action::Ignore),
+        _ if flags & SA_RESTORER == 0 || flags & SA_SIGINFO != 0 => Err(errno::EINVAL),
+        _ => Ok(Action::Handler(Handler {
+            entry,
+            restorer,
+            flags,
+            mask,
+        })),
+    }
+}
+
+/// Write `action` as a `sigaction` structure to `pointer`, whose range has
+/// been checked.
+fn write_action(pointer: u64, action: Action) -> Result<(), Errno> {
+    let words = match action {
+        Action::Default => [SIG_DFL, 0, 0, 0],
+        Action::Ignore => [SIG_IGN, 0, 0, 0],
+        Action::Handler(handler) => [handler.entry, handler.flags, handler.restorer, handler.mask],
+    };
+    for (index, &word) in words.iter().enumerate() {
+        user::write_u64(pointer + index as u64 * SIGSET_SIZE, word)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,374 @@
+//! This module contains a loader for static `ELF64` executables.
+//!
+//! Only what is needed to run a static x86_64 executable is supported : the
+//! `PT_LOAD` segments are mapped in an `AddressSpace`, the `PT_TLS` segment
+//! gives the initial image of the thread-local storage, and dynamically linked
+//! executables are rejected.
+//!
+
+// internal crate
+use crate::memory::{
+    address_space::{AddressSpace, Backing, Region},
+    layout, with_frame_allocator,
+};
+
+// external crates
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use x86_64::{
+    structures::paging::{Page, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+/// Magic bytes at the start of every ELF file.
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+/// Size of the ELF64 file header.
+const HEADER_SIZE: usize = 64;
+/// Size of an ELF64 program header.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// `e_ident[EI_CLASS]` of 64-bit files.
+const CLASS_64: u8 = 2;
+/// `e_ident[EI_DATA]` of little-endian files.
+const DATA_LITTLE_ENDIAN: u8 = 1;
+/// `e_type` of executable files.
+const TYPE_EXECUTABLE: u16 = 2;
+/// `e_machine` of x86_64 files.
+const MACHINE_X86_64: u16 = 0x3e;
+
+/// Segment type of a loadable segment.
+pub const PT_LOAD: u32 = 1;
+/// Segment type of the dynamic linking informations.
+pub const PT_DYNAMIC: u32 = 2;
+/// Segment type of the path of an interpreter.
+pub const PT_INTERP: u32 = 3;
+/// Segment type of the thread-local storage template.
+pub const PT_TLS: u32 = 7;
+
+/// Segment flag of executable segments.
+pub const PF_X: u32 = 1;
+/// Segment flag of writable segments.
+pub const PF_W: u32 = 2;
+/// Segment flag of readable segments.
+pub const PF_R: u32 = 4;
+
+/// An error occuring while parsing or loading an executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file is smaller than its headers describe.
+    Truncated,
+    /// The file does not start with the ELF magic bytes.
+    BadMagic,
+    /// The file is not a little-endian ELF64 file for x86_64.
+    UnsupportedFormat,
+    /// The file is not a static executable.
+    NotExecutable,
+    /// A segment is malformed, or lies outside of the user space.
+    InvalidSegment,
+    /// The entry point is not in an executable segment.
+    InvalidEntry,
+    /// Not enough memory to load the segments.
+    OutOfMemory,
+}
+
+/// A program header, describing a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    /// Type of the segment.
+    pub kind: u32,
+    /// Flags of the segment, combination of `PF_X`, `PF_W` and `PF_R`.
+    pub flags: u32,
+    /// Offset of the segment in the file.
+    pub offset: u64,
+    /// Virtual address of the segment.
+    pub virtual_address: u64,
+    /// Size of the segment in the file.
+    pub file_size: u64,
+    /// Size of the segment in memory, the remaining bytes are zeroed.
+    pub memory_size: u64,
+    /// Alignment of the segment, 0 or 1 if it has none.
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    /// Parse the program header in `bytes`, which must be long enough.
+    fn parse(bytes: &[u8]) -> Self {
+        ProgramHeader {
+            kind: read_u32(bytes, 0),
+            flags: read_u32(bytes, 4),
+            offset: read_u64(bytes, 8),
+            virtual_address: read_u64(bytes, 16),
+            file_size: read_u64(bytes, 32),
+            memory_size: read_u64(bytes, 40),
+            align: read_u64(bytes, 48),
+        }
+    }
+
+    /// Returns the flags of the pages of the segment.
+    fn page_flags(&self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if self.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if self.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+
+    /// Returns `true` if the segment contains the given address.
+    fn contains(&self, addr: u64) -> bool {
+        self.virtual_address <= addr && addr - self.virtual_address < self.memory_size
+    }
+}
+
+/// The template of the thread-local storage of a program : every thread gets a
+/// block starting with `image`, followed by zeroes up to `memory_size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsTemplate<'a> {
+    /// The initialized data.
+    pub image: &'a [u8],
+    /// Size of a block.
+    pub memory_size: u64,
+    /// Alignment of a block, at least 1.
+    pub align: u64,
+}
+
+/// A validated static executable.
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: u64,
+    program_headers_offset: u64,
+    program_headers: Vec<ProgramHeader>,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parse and validate the executable in `data`.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[0..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != CLASS_64
+            || data[5] != DATA_LITTLE_ENDIAN
+            || read_u16(data, 18) != MACHINE_X86_64
+        {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        if read_u16(data, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+
+        let entry = read_u64(data, 24);
+        let program_headers_offset = read_u64(data, 32);
+        let entry_size = read_u16(data, 54) as usize;
+        let count = read_u16(data, 56) as usize;
+        if count > 0 && entry_size != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::UnsupportedFormat);
+        }
+
+        let table_start = program_headers_offset as usize;
+        let table_end = table_start
+            .checked_add(count * PROGRAM_HEADER_SIZE)
+            .filter(|&end| end <= data.len())
+            .ok_or(ElfError::Truncated)?;
+        let program_headers: Vec<ProgramHeader> = data[table_start..table_end]
+            .chunks_exact(PROGRAM_HEADER_SIZE)
+            .map(ProgramHeader::parse)
+            .collect();
+
+        for header in &program_headers {
+            match header.kind {
+                PT_INTERP | PT_DYNAMIC => return Err(ElfError::NotExecutable),
+                PT_LOAD => validate_segment(header, data.len())?,
+                PT_TLS => validate_tls(header, data.len())?,
+                _ => {}
+            }
+        }
+        let tls_segments = program_headers
+            .iter()
+            .filter(|header| header.kind == PT_TLS)
+            .count();
+        if tls_segments > 1 {
+            return Err(ElfError::InvalidSegment);
+        }
+
+        let entry_is_valid = program_headers.iter().any(|header| {
+            header.kind == PT_LOAD && header.flags & PF_X != 0 && header.contains(entry)
+        });
+        if !entry_is_valid {
+            return Err(ElfError::InvalidEntry);
+        }
+
+        Ok(ElfFile {
+            data,
+            entry,
+            program_headers_offset,
+            program_headers,
+        })
+    }
+
+    /// Returns the entry point of the executable.
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.entry)
+    }
+
+    /// Returns the program headers of the executable.
+    pub fn program_headers(&self) -> &[ProgramHeader] {
+        &self.program_headers
+    }
+
+    /// Returns the address of the program headers once loaded, if they are
+    /// part of a loaded segment.
+    pub fn program_headers_address(&self) -> Option<VirtAddr> {
+        self.loaded_segments()
+            .find(|header| {
+                header.offset <= self.program_headers_offset
+                    && self.program_headers_offset - header.offset < header.file_size
+            })
+            .map(|header| {
+                VirtAddr::new(header.virtual_address + self.program_headers_offset - header.offset)
+            })
+    }
+
+    /// Returns the template of the thread-local storage, if the executable has
+    /// a `PT_TLS` segment.
+    pub fn tls_template(&self) -> Option<TlsTemplate<'a>> {
+        let header = self
+            .program_headers
+            .iter()
+            .find(|header| header.kind == PT_TLS)?;
+        let start = header.offset as usize;
+        Some(TlsTemplate {
+            image: &self.data[start..start + header.file_size as usize],
+            memory_size: header.memory_size,
+            align: header.align.max(1),
+        })
+    }
+
+    /// Map the loadable segments in `address_space`, and reserve their
+    /// regions.
+    ///
+    /// The pages shared by several segments get the union of their flags.
+    pub fn load(&self, address_space: &mut AddressSpace) -> Result<(), ElfError> {
+        // the pages of every segment, with their flags, sorted and merged
+        let mut regions: Vec<Region> = Vec::new();
+        for header in self.loaded_segments() {
+            let start = Page::<Size4KiB>::containing_address(VirtAddr::new(header.virtual_address));
+            let end = Page::<Size4KiB>::containing_address(VirtAddr::new(
+                header.virtual_address + header.memory_size - 1,
+            )) + 1;
+            regions.push(Region {
+                start: start.start_address(),
+                end: end.start_address(),
+                flags: header.page_flags(),
+                backing: Backing::Anonymous,
+            });
+        }
+        regions.sort_by_key(|region| region.start);
+        let regions = merge_regions(regions);
+
+        with_frame_allocator(|frame_allocator| {
+            for region in &regions {
+                let start = Page::containing_address(region.start);
+                let end = Page::containing_address(region.end);
+                for page in Page::range(start, end) {
+                    address_space
+                        .map_zeroed(page, region.flags, frame_allocator)
+                        .map_err(|_| ElfError::OutOfMemory)?;
+                }
+            }
+            Ok(())
+        })?;
+
+        for header in self.loaded_segments() {
+            let start = header.offset as usize;
+            let content = &self.data[start..start + header.file_size as usize];
+            address_space
+                .write(VirtAddr::new(header.virtual_address), content)
+                .expect("segment was not mapped");
+        }
+
+        for region in regions {
+            address_space
+                .add_region(region)
+                .map_err(|_| ElfError::InvalidSegment)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the non-empty `PT_LOAD` segments.
+    fn loaded_segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers
+            .iter()
+            .filter(|header| header.kind == PT_LOAD && header.memory_size > 0)
+    }
+}
+
+/// Check that the given `PT_LOAD` segment is in the file and the user space.
+fn validate_segment(header: &ProgramHeader, file_size: usize) -> Result<(), ElfError> {
+    if header.file_size > header.memory_size {
+        return Err(ElfError::InvalidSegment);
+    }
+    match header.offset.checked_add(header.file_size) {
+        Some(end) if end <= file_size as u64 => {}
+        _ => return Err(ElfError::Truncated),
+    }
+    if !layout::is_user_range(header.virtual_address, header.memory_size) {
+        return Err(ElfError::InvalidSegment);
+    }
+    Ok(())
+}
+
+/// Check that the given `PT_TLS` segment is in the file, and that its
+/// alignment is a power of two.
+fn validate_tls(header: &ProgramHeader, file_size: usize) -> Result<(), ElfError> {
+    if header.file_size > header.memory_size {
+        return Err(ElfError::InvalidSegment);
+    }
+    match header.offset.checked_add(header.file_size) {
+        Some(end) if end <= file_size as u64 => {}
+        _ => return Err(ElfError::Truncated),
+    }
+    if header.align > 1 && !header.align.is_power_of_two() {
+        return Err(ElfError::InvalidSegment);
+    }
+    Ok(())
+}
+
+/// Merge the overlapping regions of the sorted `regions`, combining their
+/// flags.
+fn merge_regions(regions: Vec<Region>) -> Vec<Region> {
+    let mut merged: Vec<Region> = Vec::with_capacity(regions.len());
+    for region in regions {
+        match merged.last_mut() {
+            Some(last) if last.overlaps(region.start, region.end) => {
+                // the union of the flags, but `NO_EXECUTE` only if both are
+                let no_execute = last.flags.contains(PageTableFlags::NO_EXECUTE)
+                    && region.flags.contains(PageTableFlags::NO_EXECUTE);
+                last.end = last.end.max(region.end);
+                last.flags |= region.flags;
+                last.flags.set(PageTableFlags::NO_EXECUTE, no_execute);
+            }
+            _ => merged.push(region),
+        }
+    }
+    merged
+}
+
+/// Read the little-endian `u16` at `offset` in `bytes`.
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+/// Read the little-endian `u32` at `offset` in `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read the little-endian `u64` at `offset` in `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,343 @@
+//! This module starts user programs.
+//!
+//! A program is a static `ELF64` executable, for now embedded in the kernel
+//! image and registered under a path with `register`. It runs in a new
+//! process, or replaces the program of the current one, and its first thread
+//! starts in user mode at the entry point of the executable, with a stack
+//! following the System V ABI : `argc`, `argv`, `envp` and the auxiliary
+//! vector.
+//!
+//! If the executable has a `PT_TLS` segment, the first thread also gets a
+//! thread-local storage block, laid out following the x86_64 TLS ABI : the
+//! block ends at the thread pointer loaded in the `FS` base, where a pointer
+//! to itself is stored.
+//!
+
+// internal crate
+use super::{
+    elf::{ElfError, ElfFile, TlsTemplate},
+    process::{self, Pid},
+    scheduler, switch, TaskError,
+};
+use crate::memory::{
+    address_space::{AddressSpace, Backing, Region},
+    layout::{USER_MMAP_START, USER_STACK_TOP},
+    with_frame_allocator,
+};
+
+// external crates
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::mem;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{Page, PageSize, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+/// Number of pages of the stack of the first thread.
+pub const USER_STACK_PAGES: u64 = 16;
+
+/// Size of the thread control block following the thread-local storage : the
+/// pointer to itself.
+const TCB_SIZE: u64 = mem::size_of::<u64>() as u64;
+
+/// Auxiliary vector entry ending the vector.
+const AT_NULL: u64 = 0;
+/// Auxiliary vector entry of the address of the program headers.
+const AT_PHDR: u64 = 3;
+/// Auxiliary vector entry of the size of a program header.
+const AT_PHENT: u64 = 4;
+/// Auxiliary vector entry of the number of program headers.
+const AT_PHNUM: u64 = 5;
+/// Auxiliary vector entry of the size of a page.
+const AT_PAGESZ: u64 = 6;
+/// Auxiliary vector entry of the entry point of the program.
+const AT_ENTRY: u64 = 9;
+
+/// An error occuring while starting a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    /// The executable is invalid.
+    InvalidElf(ElfError),
+    /// Not enough memory to load the program.
+    OutOfMemory,
+    /// The arguments and environment do not fit on the stack.
+    ArgumentsTooLong,
+    /// No executable is registered under the path.
+    NotFound,
+}
+
+impl From<ElfError> for ExecError {
+    fn from(error: ElfError) -> Self {
+        match error {
+            ElfError::OutOfMemory => ExecError::OutOfMemory,
+            error => ExecError::InvalidElf(error),
+        }
+    }
+}
+
+impl From<TaskError> for ExecError {
+    fn from(_: TaskError) -> Self {
+        ExecError::OutOfMemory
+    }
+}
+
+/// Where the first thread of a program starts in user mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserEntry {
+    pub entry: VirtAddr,
+    pub stack_pointer: VirtAddr,
+    /// The `FS` base, null if the program has no thread-local storage.
+    pub thread_pointer: VirtAddr,
+}
+
+lazy_static! {
+    /// The executables embedded in the kernel, by path.
+    static ref PROGRAMS: Mutex<BTreeMap<Vec<u8>, &'static [u8]>> = Mutex::new(BTreeMap::new());
+}
+
+/// Register the executable `image` under `path`, replacing the previous one.
+pub fn register(path: &[u8], image: &'static [u8]) {
+    PROGRAMS.lock().insert(path.to_vec(), image);
+}
+
+/// Returns the executable registered under `path`.
+pub fn lookup(path: &[u8]) -> Option<&'static [u8]> {
+    PROGRAMS.lock().get(path).copied()
+}
+
+/// Start the executable registered under `path` in a new process, like
+/// `spawn`.
+pub fn spawn_path(path: &[u8], argv: &[&[u8]], envp: &[&[u8]]) -> Result<Pid, ExecError> {
+    spawn(lookup(path).ok_or(ExecError::NotFound)?, argv, envp)
+}
+
+/// Start the executable `image` in a new process, child of the current one,
+/// with the given arguments and environment.
+pub fn spawn(image: &[u8], argv: &[&[u8]], envp: &[&[u8]]) -> Result<Pid, ExecError> {
+    let (address_space, user_entry) = load(image, argv, envp)?;
+    let pid = process::create_with(address_space);
+    let user_entry = Box::new(user_entry);
+    let arg = Box::into_raw(user_entry) as usize;
+    match process::spawn_thread(pid, start_user_thread, arg) {
+        Ok(_) => Ok(pid),
+        Err(error) => {
+            drop(unsafe { Box::from_raw(arg as *mut UserEntry) });
+            process::discard(pid);
+            Err(error.into())
+        }
+    }
+}
+
+/// Replace the program of the current process by the executable `image`,
+/// with the given arguments and environment.
+///
+/// On success, the other threads of the process are terminated and the old
+/// address space is freed : the current thread must return to user mode at
+/// the returned entry.
+pub fn replace_current(
+    image: &[u8],
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+) -> Result<UserEntry, ExecError> {
+    let (address_space, user_entry) = load(image, argv, envp)?;
+    process::replace_address_space(address_space);
+    scheduler::set_current_fs_base(user_entry.thread_pointer);
+    Ok(user_entry)
+}
+
+/// Load the executable `image` in a new address space, with a stack holding
+/// the given arguments and environment.
+fn load(
+    image: &[u8],
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+) -> Result<(AddressSpace, UserEntry), ExecError> {
+    let elf = ElfFile::parse(image)?;
+    let mut address_space = AddressSpace::new().map_err(|_| ExecError::OutOfMemory)?;
+    elf.load(&mut address_space)?;
+    let stack_pointer = setup_stack(&mut address_space, &elf, argv, envp)?;
+    let thread_pointer = match elf.tls_template() {
+        Some(template) => setup_tls(&mut address_space, &template)?,
+        None => VirtAddr::new(0),
+    };
+    // the new program is subject to the limits of the current process
+    let max_memory =
+        process::with_process(process::current(), |process| process.limits().max_memory)
+            .map_err(|_| ExecError::OutOfMemory)?;
+    if address_space.reserved_size() > max_memory {
+        return Err(ExecError::OutOfMemory);
+    }
+    let user_entry = UserEntry {
+        entry: elf.entry(),
+        stack_pointer,
+        thread_pointer,
+    };
+    Ok((address_space, user_entry))
+}
+
+/// Kernel entry of the first thread of a program.
+fn start_user_thread(arg: usize) {
+    let user_entry = unsafe { Box::from_raw(arg as *mut UserEntry) };
+    let UserEntry {
+        entry,
+        stack_pointer,
+        thread_pointer,
+    } = *user_entry;
+    scheduler::set_current_fs_base(thread_pointer);
+    unsafe { switch::enter_user_mode(entry, stack_pointer) }
+}
+
+/// Reserve the user stack, and write the arguments, the environment and the
+/// auxiliary vector on it.
+///
+/// Only the pages holding them are mapped, the rest of the stack is mapped on
+/// its first access.
+///
+/// Returns the initial stack pointer, pointing to `argc`.
+fn setup_stack(
+    address_space: &mut AddressSpace,
+    elf: &ElfFile,
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+) -> Result<VirtAddr, ExecError> {
+    let stack_size = USER_STACK_PAGES * Size4KiB::SIZE;
+    let stack_top = VirtAddr::new(USER_STACK_TOP);
+    let stack_bottom = stack_top - stack_size;
+
+    // the strings, at the top of the stack
+    let mut strings: Vec<u8> = Vec::new();
+    let mut offsets: Vec<usize> = Vec::with_capacity(argv.len() + envp.len());
+    for string in argv.iter().chain(envp.iter()) {
+        offsets.push(strings.len());
+        strings.extend_from_slice(string);
+        strings.push(0);
+    }
+    let strings_start = (USER_STACK_TOP - strings.len() as u64) & !0x7;
+
+    // the auxiliary vector
+    let mut auxv: Vec<(u64, u64)> = Vec::new();
+    if let Some(address) = elf.program_headers_address() {
+        auxv.push((AT_PHDR, address.as_u64()));
+    }
+    auxv.push((AT_PHENT, 56));
+    auxv.push((AT_PHNUM, elf.program_headers().len() as u64));
+    auxv.push((AT_PAGESZ, Size4KiB::SIZE));
+    auxv.push((AT_ENTRY, elf.entry().as_u64()));
+    auxv.push((AT_NULL, 0));
+
+    // argc, argv, NULL, envp, NULL, auxv
+    let mut words: Vec<u64> = Vec::new();
+    words.push(argv.len() as u64);
+    let string_address = |index: usize| strings_start + offsets[index] as u64;
+    words.extend((0..argv.len()).map(string_address));
+    words.push(0);
+    words.extend((argv.len()..argv.len() + envp.len()).map(string_address));
+    words.push(0);
+    for (key, value) in auxv {
+        words.push(key);
+        words.push(value);
+    }
+
+    let words_size = (words.len() * mem::size_of::<u64>()) as u64;
+    let stack_pointer = match strings_start.checked_sub(words_size) {
+        Some(address) if address & !0xf >= stack_bottom.as_u64() => address & !0xf,
+        _ => return Err(ExecError::ArgumentsTooLong),
+    };
+
+    let region = Region {
+        start: stack_bottom,
+        end: stack_top,
+        flags: PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE,
+        backing: Backing::Anonymous,
+    };
+    with_frame_allocator(|frame_allocator| {
+        let start = Page::containing_address(VirtAddr::new(stack_pointer));
+        let end = Page::containing_address(region.end);
+        for page in Page::range(start, end) {
+            address_space
+                .map_zeroed(page, region.flags, frame_allocator)
+                .map_err(|_| ExecError::OutOfMemory)?;
+        }
+        Ok(())
+    })?;
+    address_space
+        .add_region(region)
+        .map_err(|_| ExecError::ArgumentsTooLong)?;
+
+    let mut bytes: Vec<u8> = Vec::with_capacity(words_size as usize);
+    for word in words {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    address_space
+        .write(VirtAddr::new(strings_start), &strings)
+        .and_then(|_| address_space.write(VirtAddr::new(stack_pointer), &bytes))
+        .expect("user stack was not mapped");
+    Ok(VirtAddr::new(stack_pointer))
+}
+
+/// Map a thread-local storage block initialized from `template`, followed by
+/// the thread control block.
+///
+/// Returns the thread pointer, the address of the thread control block.
+fn setup_tls(
+    address_space: &mut AddressSpace,
+    template: &TlsTemplate,
+) -> Result<VirtAddr, ExecError> {
+    // the block starts at a page, the thread pointer must be aligned too
+    if template.align > Size4KiB::SIZE {
+        return Err(ExecError::InvalidElf(ElfError::InvalidSegment));
+    }
+    let align = template.align.max(TCB_SIZE);
+    let block_size = template
+        .memory_size
+        .checked_add(align - 1)
+        .map(|size| size & !(align - 1))
+        .ok_or(ExecError::OutOfMemory)?;
+    let pages = block_size
+        .checked_add(TCB_SIZE + Size4KiB::SIZE - 1)
+        .ok_or(ExecError::OutOfMemory)?
+        / Size4KiB::SIZE;
+
+    let len = pages * Size4KiB::SIZE;
+    let start = address_space
+        .find_free_range(
+            VirtAddr::new(USER_MMAP_START),
+            VirtAddr::new(USER_STACK_TOP),
+            len,
+        )
+        .ok_or(ExecError::OutOfMemory)?;
+    let region = Region {
+        start,
+        end: start + len,
+        flags: PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE,
+        backing: Backing::Anonymous,
+    };
+    with_frame_allocator(|frame_allocator| {
+        let start = Page::containing_address(region.start);
+        let end = Page::containing_address(region.end);
+        for page in Page::range(start, end) {
+            address_space
+                .map_zeroed(page, region.flags, frame_allocator)
+                .map_err(|_| ExecError::OutOfMemory)?;
+        }
+        Ok(())
+    })?;
+    address_space
+        .add_region(region)
+        .expect("thread-local storage overlaps a region");
+
+    let thread_pointer = start + block_size;
+    address_space
+        .write(start, template.image)
+        .and_then(|_| address_space.write(thread_pointer, &thread_pointer.as_u64().to_le_bytes()))
+        .expect("thread-local storage was not mapped");
+    Ok(thread_pointer)
+}
@@ -0,0 +1,166 @@
+//! This module contains the file descriptor table of a process.
+//!
+//! A file descriptor names an open `File` : the console, an end of a pipe...
+//! Files are reference-counted : duplicated descriptors share the same file,
+//! which is closed once the last descriptor naming it is.
+//!
+
+// internal crate
+use super::wait_queue::Interrupted;
+
+// external crates
+use alloc::{collections::BTreeMap, sync::Arc};
+
+/// Maximum number of file descriptors of a process.
+pub const MAX_FILES: usize = 256;
+
+/// The standard input.
+pub const STDIN: Fd = Fd(0);
+/// The standard output.
+pub const STDOUT: Fd = Fd(1);
+/// The standard error.
+pub const STDERR: Fd = Fd(2);
+
+/// An error occuring while using files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileError {
+    /// The descriptor does not name any file.
+    BadDescriptor,
+    /// The process has too many descriptors.
+    TableFull,
+    /// The file cannot be read.
+    NotReadable,
+    /// The file cannot be written.
+    NotWritable,
+    /// The pipe has no reader anymore.
+    BrokenPipe,
+    /// A blocking operation was interrupted by a signal.
+    Interrupted,
+}
+
+impl From<Interrupted> for FileError {
+    fn from(_: Interrupted) -> Self {
+        FileError::Interrupted
+    }
+}
+
+/// An open file.
+pub trait File: Send + Sync {
+    /// Read into `buffer`, blocking until some bytes are available.
+    ///
+    /// Returns the number of bytes read, 0 at the end of the file.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, FileError>;
+
+    /// Write `data`, blocking until it is written.
+    ///
+    /// Returns the number of bytes written, which may be less than the length
+    /// of `data` if the call was interrupted.
+    fn write(&self, data: &[u8]) -> Result<usize, FileError>;
+}
+
+/// A file descriptor, naming a file in a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fd(u32);
+
+impl Fd {
+    /// Create a descriptor from its value.
+    pub fn new(value: u32) -> Self {
+        Fd(value)
+    }
+
+    /// Convert the descriptor to `u32`.
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+/// The file descriptors of a process.
+#[derive(Clone)]
+pub struct FileTable {
+    files: BTreeMap<Fd, Arc<dyn File>>,
+    /// Maximum number of descriptors, at most `MAX_FILES`. The descriptors
+    /// are below it.
+    limit: usize,
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileTable {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        FileTable {
+            files: BTreeMap::new(),
+            limit: MAX_FILES,
+        }
+    }
+
+    /// Create a table whose standard input and outputs are `file`.
+    pub fn with_standard(file: Arc<dyn File>) -> Self {
+        let mut table = Self::new();
+        table.files.insert(STDIN, file.clone());
+        table.files.insert(STDOUT, file.clone());
+        table.files.insert(STDERR, file);
+        table
+    }
+
+    /// Set the maximum number of descriptors, capped to `MAX_FILES`.
+    ///
+    /// The existing descriptors stay valid.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.min(MAX_FILES);
+    }
+
+    /// Returns the number of descriptors.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns `true` if the table has no descriptor.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Add `file` to the table, with the lowest free descriptor.
+    pub fn insert(&mut self, file: Arc<dyn File>) -> Result<Fd, FileError> {
+        if self.files.len() >= self.limit {
+            return Err(FileError::TableFull);
+        }
+        // the descriptors are sorted : the first gap is the lowest free one
+        let mut fd = Fd(0);
+        for &used in self.files.keys() {
+            if used != fd {
+                break;
+            }
+            fd = Fd(fd.0 + 1);
+        }
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    /// Add `file` to the table with the descriptor `fd`, returning the file it
+    /// replaces.
+    pub fn insert_at(
+        &mut self,
+        fd: Fd,
+        file: Arc<dyn File>,
+    ) -> Result<Option<Arc<dyn File>>, FileError> {
+        if fd.0 as usize >= self.limit {
+            return Err(FileError::BadDescriptor);
+        }
+        Ok(self.files.insert(fd, file))
+    }
+
+    /// Returns the file named by `fd`.
+    pub fn get(&self, fd: Fd) -> Result<Arc<dyn File>, FileError> {
+        self.files.get(&fd).cloned().ok_or(FileError::BadDescriptor)
+    }
+
+    /// Remove `fd` from the table, returning its file.
+    pub fn remove(&mut self, fd: Fd) -> Result<Arc<dyn File>, FileError> {
+        self.files.remove(&fd).ok_or(FileError::BadDescriptor)
+    }
+}
@@ -0,0 +1,199 @@
+//! This module contains the handle table of a process.
+//!
+//! A handle is an integer naming a kernel object in a process, like a file
+//! descriptor. The objects are reference-counted : closing a handle only frees
+//! the object once no other handle or message refers to it.
+//!
+
+// internal crate
+use crate::{ipc::channel::Endpoint, memory::shared::SharedMemory};
+
+// external crates
+use alloc::{collections::BTreeMap, sync::Arc};
+
+/// Maximum number of handles of a process.
+pub const MAX_HANDLES: usize = 1024;
+
+/// A handle, naming a kernel object in a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Handle(u32);
+
+impl Handle {
+    /// Create a handle from its value.
+    pub fn new(value: u32) -> Self {
+        Handle(value)
+    }
+
+    /// Convert the handle to `u32`.
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+/// A kernel object that can be named by a handle.
+#[derive(Clone)]
+pub enum KernelObject {
+    /// An endpoint of a channel.
+    Channel(Arc<Endpoint>),
+    /// A shared memory object.
+    SharedMemory(Arc<SharedMemory>),
+}
+
+/// An error occuring while managing handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The handle does not name any object.
+    BadHandle,
+    /// The process has too many handles.
+    TableFull,
+    /// The handle names an object of another type.
+    WrongType,
+}
+
+/// The handles of a process.
+pub struct HandleTable {
+    objects: BTreeMap<Handle, KernelObject>,
+    /// Objects being sent in a message, whose handles are kept until the
+    /// message is queued.
+    reserved: BTreeMap<Handle, KernelObject>,
+    /// Maximum number of handles, at most `MAX_HANDLES`.
+    limit: usize,
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HandleTable {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        HandleTable {
+            objects: BTreeMap::new(),
+            reserved: BTreeMap::new(),
+            limit: MAX_HANDLES,
+        }
+    }
+
+    /// Set the maximum number of handles, capped to `MAX_HANDLES`.
+    ///
+    /// The existing handles stay valid.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.min(MAX_HANDLES);
+    }
+
+    /// Returns the number of handles, including the reserved ones.
+    pub fn len(&self) -> usize {
+        self.objects.len() + self.reserved.len()
+    }
+
+    /// Returns the number of handles which can still be inserted.
+    pub fn available(&self) -> usize {
+        self.limit.saturating_sub(self.len())
+    }
+
+    /// Returns `true` if the table has no handle.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add `object` to the table, with the lowest free handle.
+    pub fn insert(&mut self, object: KernelObject) -> Result<Handle, HandleError> {
+        if self.len() >= self.limit {
+            return Err(HandleError::TableFull);
+        }
+        let mut handle = Handle(0);
+        while self.objects.contains_key(&handle) || self.reserved.contains_key(&handle) {
+            handle = Handle(handle.0 + 1);
+        }
+        self.objects.insert(handle, object);
+        Ok(handle)
+    }
+
+    /// Reserve `handle` while its object is sent in a message, returning the
+    /// object.
+    ///
+    /// The handle names no object anymore, but it is not given to another
+    /// object until it is released with `release` or `restore`.
+    pub fn reserve(&mut self, handle: Handle) -> Result<KernelObject, HandleError> {
+        let object = self.remove(handle)?;
+        self.reserved.insert(handle, object.clone());
+        Ok(object)
+    }
+
+    /// Free the reserved `handle`, once its object has been sent.
+    ///
+    /// Returns the copy of the object kept by the table.
+    pub fn release(&mut self, handle: Handle) -> Option<KernelObject> {
+        self.reserved.remove(&handle)
+    }
+
+    /// Make the reserved `handle` name its object again, as it was not sent.
+    pub fn restore(&mut self, handle: Handle) {
+        if let Some(object) = self.reserved.remove(&handle) {
+            self.objects.insert(handle, object);
+        }
+    }
+
+    /// Returns the object named by `handle`.
+    pub fn get(&self, handle: Handle) -> Result<&KernelObject, HandleError> {
+        self.objects.get(&handle).ok_or(HandleError::BadHandle)
+    }
+
+    /// Remove `handle` from the table, returning its object.
+    pub fn remove(&mut self, handle: Handle) -> Result<KernelObject, HandleError> {
+        self.objects.remove(&handle).ok_or(HandleError::BadHandle)
+    }
+
+    /// Returns the endpoint named by `handle`.
+    pub fn channel(&self, handle: Handle) -> Result<Arc<Endpoint>, HandleError> {
+        match self.get(handle)? {
+            KernelObject::Channel(endpoint) => Ok(endpoint.clone()),
+            _ => Err(HandleError::WrongType),
+        }
+    }
+
+    /// Returns the shared memory object named by `handle`.
+    pub fn shared_memory(&self, handle: Handle) -> Result<Arc<SharedMemory>, HandleError> {
+        match self.get(handle)? {
+            KernelObject::SharedMemory(memory) => Ok(memory.clone()),
+            _ => Err(HandleError::WrongType),
+        }
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_reserved_handles() {
+    serial_print!("test_reserved_handles... ");
+    let memory = Arc::new(SharedMemory::new(4096).unwrap());
+    let object = || KernelObject::SharedMemory(memory.clone());
+    let mut table = HandleTable::new();
+    table.set_limit(3);
+    let first = table.insert(object()).unwrap();
+    let second = table.insert(object()).unwrap();
+
+    // a reserved handle is neither usable nor given to another object
+    table.reserve(first).unwrap();
+    assert_eq!(table.get(first).err(), Some(HandleError::BadHandle));
+    assert_eq!(table.reserve(first).err(), Some(HandleError::BadHandle));
+    assert_eq!(table.available(), 1);
+    let third = table.insert(object()).unwrap();
+    assert!(third != first && third != second);
+    assert_eq!(table.available(), 0);
+    assert_eq!(table.insert(object()).err(), Some(HandleError::TableFull));
+
+    table.restore(first);
+    assert!(table.shared_memory(first).is_ok());
+    table.reserve(second).unwrap();
+    assert!(table.release(second).is_some());
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.insert(object()).unwrap(), second);
+    serial_println!("[ok]");
+}
@@ -0,0 +1,179 @@
+//! This module contains the thread-local storage of kernel threads.
+//!
+//! The kernel image has no `PT_TLS` segment set up by the bootloader, so
+//! `#[thread_local]` statics cannot be used. The `kernel_thread_local!` macro
+//! declares statics with the same syntax and behaviour instead : each thread
+//! lazily gets its own value, initialized on its first access and dropped
+//! when the thread is freed.
+//!
+//! The values of a thread are stored in its `Thread`, the scheduler makes
+//! them current on each context switch.
+//!
+//! ```ignore
+//! kernel_thread_local! {
+//!     static COUNTER: Cell<u64> = Cell::new(0);
+//! }
+//!
+//! COUNTER.with(|counter| counter.set(counter.get() + 1));
+//! ```
+//!
+
+// external crates
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    any::Any,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+use x86_64::instructions::interrupts;
+
+/// Index of a key which has not been used yet.
+const NO_INDEX: usize = usize::max_value();
+
+/// The kernel thread-locals of the current thread, null before the scheduler
+/// is initialized.
+static CURRENT: AtomicPtr<LocalStorage> = AtomicPtr::new(ptr::null_mut());
+
+/// The next index given to a key.
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// Declare kernel thread-local statics, of type `LocalKey`.
+///
+/// The values must be `Send`, as they are dropped by the thread freeing their
+/// thread.
+#[macro_export]
+macro_rules! kernel_thread_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])* $vis static $name: $crate::task::local::LocalKey<$t> = {
+            fn init() -> $t {
+                $init
+            }
+            $crate::task::local::LocalKey::new(init)
+        };
+        $crate::kernel_thread_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $crate::kernel_thread_local!($(#[$attr])* $vis static $name: $t = $init;);
+    };
+}
+
+/// A kernel thread-local static, declared with `kernel_thread_local!`.
+pub struct LocalKey<T: Send + 'static> {
+    /// The index of the values in the storage of the threads, given on first
+    /// use.
+    index: AtomicUsize,
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> LocalKey<T> {
+    /// Create a key whose values are initialized with `init`.
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        LocalKey {
+            index: AtomicUsize::new(NO_INDEX),
+            init,
+        }
+    }
+
+    /// Run `f` with the value of the current thread, initializing it first if
+    /// needed.
+    ///
+    /// Must not be used from interrupt handlers, which would get the value of
+    /// the interrupted thread.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let index = self.index();
+        let value = match unsafe { current() }.get(index) {
+            Some(value) => value,
+            None => {
+                let value = (self.init)();
+                // `init` may have initialized it meanwhile
+                let storage = unsafe { current() };
+                if storage.get(index).is_none() {
+                    storage.set(index, Box::new(value));
+                }
+                storage.get(index).unwrap()
+            }
+        };
+        let value = value
+            .downcast_ref::<T>()
+            .expect("kernel thread-local has the wrong type");
+        f(value)
+    }
+
+    /// Returns the index of the key, taking a new one on first use.
+    fn index(&self) -> usize {
+        let index = self.index.load(Ordering::Acquire);
+        if index != NO_INDEX {
+            return index;
+        }
+        let new_index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+        match self
+            .index
+            .compare_exchange(NO_INDEX, new_index, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => new_index,
+            // another thread gave it an index first, this one is lost
+            Err(index) => index,
+        }
+    }
+}
+
+/// The values of the kernel thread-locals of a thread, by key index.
+pub(super) struct LocalStorage {
+    values: Vec<Option<Box<dyn Any + Send>>>,
+}
+
+impl LocalStorage {
+    /// Create an empty storage.
+    pub(super) fn new() -> Self {
+        LocalStorage { values: Vec::new() }
+    }
+
+    /// Returns the value at `index`, if initialized.
+    ///
+    /// The value is boxed : it does not move when other values are added.
+    fn get(&self, index: usize) -> Option<&'static (dyn Any + Send)> {
+        let value = self.values.get(index)?.as_ref()?;
+        // it lives as long as the thread, longer than any access by it
+        Some(unsafe { &*(value.as_ref() as *const (dyn Any + Send)) })
+    }
+
+    /// Set the value at `index`, which must not be initialized.
+    fn set(&mut self, index: usize, value: Box<dyn Any + Send>) {
+        interrupts::without_interrupts(|| {
+            if self.values.len() <= index {
+                self.values.resize_with(index + 1, || None);
+            }
+            self.values[index] = Some(value);
+        });
+    }
+}
+
+/// Make `storage` the kernel thread-locals of the current thread.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `storage`
+/// is the storage of the thread about to run, valid until the next call.
+pub(super) unsafe fn set_current(storage: *mut LocalStorage) {
+    CURRENT.store(storage, Ordering::Release);
+}
+
+/// Returns the kernel thread-locals of the current thread.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that only the
+/// current thread uses the returned reference.
+unsafe fn current() -> &'static mut LocalStorage {
+    let storage = CURRENT.load(Ordering::Acquire);
+    assert!(
+        !storage.is_null(),
+        "kernel thread-local used before the scheduler is initialized"
+    );
+    &mut *storage
+}
@@ -0,0 +1,52 @@
+//! This module contains the multitasking parts of the kernel : processes,
+//! threads and the scheduler.
+//!
+//! Every thread has its own kernel stack, and belongs to a process owning an
+//! `AddressSpace`. The kernel itself is the process `KERNEL_PID`, whose
+//! threads run in the kernel page table.
+//!
+
+// submodules
+mod switch;
+
+// public submodules
+pub mod elf;
+pub mod exec;
+pub mod file;
+pub mod handle;
+pub mod local;
+pub mod process;
+pub mod resource;
+pub mod scheduler;
+pub mod signal;
+pub mod thread;
+pub mod wait_queue;
+
+// external crates
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
+
+/// An error occuring while managing processes and threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskError {
+    /// Not enough memory to create the stack or the page tables.
+    OutOfMemory,
+    /// The given process does not exist or has exited.
+    NoSuchProcess,
+    /// A resource limit of the process would be exceeded.
+    LimitExceeded,
+}
+
+impl From<MapToError<Size4KiB>> for TaskError {
+    fn from(_: MapToError<Size4KiB>) -> Self {
+        TaskError::OutOfMemory
+    }
+}
+
+/// Initialize multitasking.
+///
+/// The running code becomes the first thread of the kernel process. Memory
+/// must have been initialized before.
+pub fn init() {
+    process::init();
+    scheduler::init();
+}
@@ -0,0 +1,595 @@
+//! This module contains the `Process` structure and the process table.
+//!
+//! Every process but the kernel has a parent, the process which created it.
+//! A terminated process stays in the table as a zombie until its parent
+//! collects its final state with `wait`. The children of a terminated process
+//! are given to the init process, see `set_init`, which is the kernel until a
+//! user process is chosen.
+//!
+
+// internal crate
+use super::{
+    file::FileTable,
+    handle::HandleTable,
+    resource::{Accounting, Limits, Usage},
+    scheduler,
+    signal::{self, Signal, SignalState, SIGCHLD},
+    thread::ThreadId,
+    wait_queue::WaitQueue,
+    TaskError,
+};
+use crate::{
+    drivers::console::Console,
+    memory::{
+        self,
+        address_space::{AddressSpace, FaultError},
+        kernel_level_4_frame,
+    },
+};
+
+// external crates
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    structures::{idt::PageFaultErrorCode, paging::PhysFrame},
+    VirtAddr,
+};
+
+/// Identifier of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(u64);
+
+impl Pid {
+    /// Returns a new unique identifier.
+    fn new() -> Self {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Create an identifier from its value, as given by user programs.
+    pub fn from_u64(value: u64) -> Self {
+        Pid(value)
+    }
+
+    /// Convert the identifier to `u64`.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// The identifier of the kernel process.
+pub const KERNEL_PID: Pid = Pid(0);
+
+/// An error occuring while waiting for a child process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The current process has no child matching the request.
+    NoChildren,
+    /// The wait was interrupted by a signal.
+    Interrupted,
+}
+
+/// A child process collected by `wait`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Terminated {
+    pub pid: Pid,
+    /// The final state of the child.
+    pub state: ProcessState,
+    /// The resources used by the child and its own collected children.
+    pub usage: Usage,
+}
+
+/// The state of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// At least one thread of the process is alive.
+    Running,
+    /// The process exited with the given code.
+    Exited(i32),
+    /// The process was terminated by the given signal.
+    Killed(Signal),
+}
+
+/// A process : an address space and the threads running in it.
+pub struct Process {
+    pid: Pid,
+    parent: Pid,
+    /// The children, including the terminated ones not waited for yet.
+    children: Vec<Pid>,
+    state: ProcessState,
+    /// The address space, `None` for the kernel process and exited processes.
+    address_space: Option<AddressSpace>,
+    threads: Vec<ThreadId>,
+    handles: HandleTable,
+    files: FileTable,
+    signals: SignalState,
+    limits: Limits,
+    accounting: Accounting,
+    /// The resources used by the collected children.
+    children_usage: Usage,
+}
+
+impl Process {
+    /// Returns the identifier of the process.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns the parent of the process.
+    pub fn parent(&self) -> Pid {
+        self.parent
+    }
+
+    /// Returns the children of the process.
+    pub fn children(&self) -> &[Pid] {
+        &self.children
+    }
+
+    /// Returns the state of the process.
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
+    /// Returns the address space of the process, `None` for the kernel.
+    pub fn address_space(&mut self) -> Option<&mut AddressSpace> {
+        self.address_space.as_mut()
+    }
+
+    /// Returns the handle table of the process.
+    pub fn handles(&mut self) -> &mut HandleTable {
+        &mut self.handles
+    }
+
+    /// Returns the file descriptor table of the process.
+    pub fn files(&mut self) -> &mut FileTable {
+        &mut self.files
+    }
+
+    /// Returns the signal state of the process.
+    pub fn signals(&mut self) -> &mut SignalState {
+        &mut self.signals
+    }
+
+    /// Returns the alive threads of the process.
+    pub fn threads(&self) -> &[ThreadId] {
+        &self.threads
+    }
+
+    /// Returns the resource limits of the process.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Set the resource limits of the process.
+    ///
+    /// The resources already used above the new limits are kept.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.handles.set_limit(limits.max_handles);
+        self.files.set_limit(limits.max_handles);
+    }
+
+    /// Returns the resources used by the process.
+    pub fn usage(&self) -> Usage {
+        let (resident_pages, peak_resident_pages) = match &self.address_space {
+            Some(address_space) => (
+                address_space.resident_pages(),
+                address_space.peak_resident_pages(),
+            ),
+            None => (0, 0),
+        };
+        Usage {
+            user_ticks: self.accounting.user_ticks,
+            system_ticks: self.accounting.system_ticks,
+            resident_pages,
+            peak_resident_pages: peak_resident_pages.max(self.accounting.peak_resident_pages),
+            handles: self.handles.len() + self.files.len(),
+            threads: self.threads.len(),
+            heap_bytes: self.accounting.heap_bytes,
+            peak_heap_bytes: self.accounting.peak_heap_bytes,
+        }
+    }
+
+    /// Returns the resources used by the terminated children collected by
+    /// `wait`, and their own collected children.
+    pub fn children_usage(&self) -> Usage {
+        self.children_usage
+    }
+
+    /// Charge at most `bytes` of kernel heap to the process, all of them if
+    /// `exact` is set, within its limit.
+    pub(super) fn charge_heap(&mut self, bytes: usize, exact: bool) -> Result<usize, TaskError> {
+        self.accounting
+            .charge_heap(bytes, self.limits.max_heap, exact)
+    }
+
+    /// Release `bytes` of kernel heap charged before.
+    pub(super) fn uncharge_heap(&mut self, bytes: usize) {
+        self.accounting.uncharge_heap(bytes);
+    }
+
+    /// Take the address space of the process, keeping its peak usage.
+    fn take_address_space(&mut self) -> Option<AddressSpace> {
+        let address_space = self.address_space.take()?;
+        let peak = &mut self.accounting.peak_resident_pages;
+        *peak = (*peak).max(address_space.peak_resident_pages());
+        Some(address_space)
+    }
+
+    /// Returns the level 4 page table used by the threads of the process.
+    fn page_table(&self) -> PhysFrame {
+        match &self.address_space {
+            Some(address_space) => address_space.level_4_frame(),
+            None => kernel_level_4_frame(),
+        }
+    }
+}
+
+lazy_static! {
+    /// The table of every process, including the exited ones.
+    ///
+    /// It must only be locked with interrupts disabled, and before the
+    /// scheduler if both are needed.
+    static ref PROCESSES: Mutex<BTreeMap<Pid, Process>> = Mutex::new(BTreeMap::new());
+}
+
+/// The process adopting orphans.
+static INIT_PID: AtomicU64 = AtomicU64::new(KERNEL_PID.0);
+
+/// The threads waiting for a child process to terminate.
+static CHILD_EXITED: WaitQueue = WaitQueue::new();
+
+/// Create the kernel process.
+pub(super) fn init() {
+    let kernel = Process {
+        pid: KERNEL_PID,
+        parent: KERNEL_PID,
+        children: Vec::new(),
+        state: ProcessState::Running,
+        address_space: None,
+        threads: Vec::new(),
+        handles: HandleTable::new(),
+        files: FileTable::new(),
+        signals: SignalState::default(),
+        limits: Limits::default(),
+        accounting: Accounting::default(),
+        children_usage: Usage::default(),
+    };
+    interrupts::without_interrupts(|| PROCESSES.lock().insert(KERNEL_PID, kernel));
+}
+
+/// Run `f` with the given process.
+///
+/// Interrupts are disabled meanwhile.
+pub fn with_process<F, R>(pid: Pid, f: F) -> Result<R, TaskError>
+where
+    F: FnOnce(&mut Process) -> R,
+{
+    interrupts::without_interrupts(|| {
+        PROCESSES
+            .lock()
+            .get_mut(&pid)
+            .map(f)
+            .ok_or(TaskError::NoSuchProcess)
+    })
+}
+
+/// Resolve a page fault at `addr` in the address space of the current
+/// process, see `AddressSpace::handle_fault`.
+///
+/// Usable from the page fault handler : fails with `FaultError::Unavailable`
+/// instead of waiting if the scheduler, the process table or the frame
+/// allocator is already locked, for example by the faulting kernel code.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> Result<(), FaultError> {
+    interrupts::without_interrupts(|| {
+        let (_, pid) = scheduler::try_current().ok_or(FaultError::Unavailable)?;
+        let mut processes = PROCESSES.try_lock().ok_or(FaultError::Unavailable)?;
+        let address_space = processes
+            .get_mut(&pid)
+            .and_then(Process::address_space)
+            .ok_or(FaultError::NotReserved)?;
+        memory::try_with_frame_allocator(|frame_allocator| {
+            address_space.handle_fault(addr, error_code, frame_allocator)
+        })
+        .unwrap_or(Err(FaultError::Unavailable))
+    })
+}
+
+/// Returns the process of the current thread.
+pub fn current() -> Pid {
+    scheduler::current().1
+}
+
+/// Create a new process with an empty address space and no thread.
+pub fn create() -> Result<Pid, TaskError> {
+    Ok(create_with(AddressSpace::new()?))
+}
+
+/// Create a new process owning `address_space`, with no thread, child of the
+/// current process.
+///
+/// It inherits the file descriptors and the resource limits of its parent,
+/// the children of the kernel having the console as standard input and
+/// outputs, and the default limits.
+pub fn create_with(address_space: AddressSpace) -> Pid {
+    let pid = Pid::new();
+    let parent = current();
+    interrupts::without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        let parent_process = processes
+            .get_mut(&parent)
+            .expect("current process does not exist");
+        parent_process.children.push(pid);
+        let (files, limits) = match parent {
+            KERNEL_PID => (FileTable::with_standard(Console::file()), Limits::default()),
+            _ => (parent_process.files.clone(), parent_process.limits),
+        };
+
+        let mut process = Process {
+            pid,
+            parent,
+            children: Vec::new(),
+            state: ProcessState::Running,
+            address_space: Some(address_space),
+            threads: Vec::new(),
+            handles: HandleTable::new(),
+            files,
+            signals: SignalState::default(),
+            limits,
+            accounting: Accounting::default(),
+            children_usage: Usage::default(),
+        };
+        process.set_limits(limits);
+        processes.insert(pid, process);
+    });
+    pid
+}
+
+/// Remove a process which never had any thread, freeing its address space.
+pub(super) fn discard(pid: Pid) {
+    let process = interrupts::without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        let process = processes.remove(&pid)?;
+        if let Some(parent) = processes.get_mut(&process.parent) {
+            parent.children.retain(|&child| child != pid);
+        }
+        Some(process)
+    });
+    if let Some(process) = process {
+        assert!(process.threads.is_empty(), "discarded process has threads");
+    }
+}
+
+/// Make `pid` the process adopting the orphans, instead of the kernel.
+///
+/// If it terminates, the kernel becomes the init process again.
+pub fn set_init(pid: Pid) {
+    INIT_PID.store(pid.0, Ordering::Relaxed);
+}
+
+/// Returns the process adopting the orphans.
+pub fn init_pid() -> Pid {
+    Pid(INIT_PID.load(Ordering::Relaxed))
+}
+
+/// Replace the address space of the current process, when it starts a new
+/// program, and load it.
+///
+/// The other threads of the process are terminated, and the signal handlers
+/// are reset.
+pub(super) fn replace_address_space(address_space: AddressSpace) {
+    let (id, pid) = scheduler::current();
+    assert_ne!(pid, KERNEL_PID, "the kernel process cannot start a program");
+
+    let previous = interrupts::without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        let process = processes
+            .get_mut(&pid)
+            .expect("current process does not exist");
+        for &thread in process.threads.iter().filter(|&&thread| thread != id) {
+            scheduler::terminate(thread);
+        }
+        process.threads.retain(|&thread| thread == id);
+        process.signals.reset_handlers();
+
+        let page_table = address_space.level_4_frame();
+        scheduler::set_current_page_table(page_table);
+        // the kernel code and stack are mapped in every address space
+        unsafe { address_space.activate() };
+        let previous = process.take_address_space();
+        process.address_space = Some(address_space);
+        previous
+    });
+    // not active anymore : freeing it keeps the page table loaded
+    drop(previous);
+}
+
+/// Start a new thread in the given process, running `entry(arg)` in kernel
+/// mode.
+///
+/// Fails with `TaskError::LimitExceeded` if the process has as many threads
+/// as its limit, except for the kernel.
+pub fn spawn_thread(pid: Pid, entry: fn(usize), arg: usize) -> Result<ThreadId, TaskError> {
+    interrupts::without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        let process = processes
+            .get_mut(&pid)
+            .filter(|process| process.state == ProcessState::Running)
+            .ok_or(TaskError::NoSuchProcess)?;
+        if pid != KERNEL_PID && process.threads.len() >= process.limits.max_threads {
+            return Err(TaskError::LimitExceeded);
+        }
+
+        let id = scheduler::spawn(pid, process.page_table(), entry, arg)?;
+        process.threads.push(id);
+        Ok(id)
+    })
+}
+
+/// Charge a tick to the current process, spent in user mode if `user` is set.
+///
+/// Called by the timer interrupt handler.
+pub fn account_tick(user: bool) {
+    // the tables are never locked by interrupted code, as they are locked with
+    // interrupts disabled, but may not be initialized yet
+    let pid = match scheduler::try_current() {
+        Some((_, pid)) => pid,
+        None => return,
+    };
+    let mut processes = match PROCESSES.try_lock() {
+        Some(processes) => processes,
+        None => return,
+    };
+    if let Some(process) = processes.get_mut(&pid) {
+        if user {
+            process.accounting.user_ticks += 1;
+        } else {
+            process.accounting.system_ticks += 1;
+        }
+    }
+}
+
+/// Terminate the current thread, and its process if it was the last thread.
+pub fn exit_thread() -> ! {
+    let (id, pid) = scheduler::current();
+    let last = interrupts::without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        let process = processes.get_mut(&pid).expect("current process does not exist");
+        process.threads.retain(|&thread| thread != id);
+        process.threads.is_empty() && pid != KERNEL_PID
+    });
+
+    if last {
+        exit(0)
+    } else {
+        scheduler::exit_current()
+    }
+}
+
+/// Terminate the current process with the given exit code.
+///
+/// Every thread of the process is terminated, and its address space is freed.
+pub fn exit(code: i32) -> ! {
+    terminate(ProcessState::Exited(code))
+}
+
+/// Terminate the current process because of the given signal.
+pub fn kill_current(signal: Signal) -> ! {
+    terminate(ProcessState::Killed(signal))
+}
+
+/// Terminate the current process, which gets the final `state`.
+///
+/// Its children are given to the init process, and its parent is notified.
+fn terminate(state: ProcessState) -> ! {
+    let pid = current();
+    assert_ne!(pid, KERNEL_PID, "the kernel process cannot exit");
+
+    interrupts::disable();
+    let mut init = init_pid();
+    if init == pid {
+        set_init(KERNEL_PID);
+        init = KERNEL_PID;
+    }
+    let (parent, address_space, handles, files) = {
+        let mut processes = PROCESSES.lock();
+        let process = processes.get_mut(&pid).expect("current process does not exist");
+        process.state = state;
+        for thread in process.threads.drain(..) {
+            scheduler::terminate(thread);
+        }
+        let parent = process.parent;
+        let children = mem::take(&mut process.children);
+        let address_space = process.take_address_space();
+        let handles = mem::take(&mut process.handles);
+        let files = mem::take(&mut process.files);
+
+        for &child in &children {
+            if let Some(child) = processes.get_mut(&child) {
+                child.parent = init;
+            }
+        }
+        processes
+            .get_mut(&init)
+            .expect("init process does not exist")
+            .children
+            .extend(children);
+        (parent, address_space, handles, files)
+    };
+
+    // closing the handles and the files may wake threads of other processes
+    drop(handles);
+    drop(files);
+    // switches to the kernel page table before freeing the user space
+    drop(address_space);
+
+    // the kernel and exited parents ignore the signal
+    let _ = signal::send(parent, SIGCHLD);
+    // the init process may have adopted terminated children too
+    CHILD_EXITED.wake_all();
+    scheduler::exit_current()
+}
+
+/// Wait for a child of the current process to terminate, `pid` or any child
+/// if `None`, and remove it from the process table.
+///
+/// If no child has terminated, blocks until one does, or returns `None` if
+/// `blocking` is not set. The resources used by the collected child are added
+/// to the usage of the children of the current process.
+pub fn wait(pid: Option<Pid>, blocking: bool) -> Result<Option<Terminated>, WaitError> {
+    let parent = current();
+    let mut result = None;
+    let waited = CHILD_EXITED.wait_until_interruptible(|| {
+        let mut processes = PROCESSES.lock();
+        let children = &processes
+            .get(&parent)
+            .expect("current process does not exist")
+            .children;
+        let mut matching = children
+            .iter()
+            .copied()
+            .filter(|&child| pid.map_or(true, |pid| child == pid))
+            .peekable();
+        if matching.peek().is_none() {
+            result = Some(Err(WaitError::NoChildren));
+            return true;
+        }
+        let terminated = matching.find(|child| {
+            processes
+                .get(child)
+                .map_or(false, |child| child.state != ProcessState::Running)
+        });
+
+        match terminated {
+            Some(child) => {
+                let process = processes.remove(&child).unwrap();
+                let mut usage = process.usage();
+                usage.add_child(&process.children_usage);
+                let parent = processes.get_mut(&parent).unwrap();
+                parent.children.retain(|&other| other != child);
+                parent.children_usage.add_child(&usage);
+                result = Some(Ok(Some(Terminated {
+                    pid: child,
+                    state: process.state,
+                    usage,
+                })));
+                true
+            }
+            None if !blocking => {
+                result = Some(Ok(None));
+                true
+            }
+            None => false,
+        }
+    });
+    match waited {
+        Ok(()) => result.unwrap(),
+        Err(_) => Err(WaitError::Interrupted),
+    }
+}
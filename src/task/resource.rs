@@ -0,0 +1,184 @@
+//! This module contains the resource accounting and the limits of processes.
+//!
+//! The limits keep a misbehaving program from exhausting what the kernel
+//! shares between every process : the physical frames backing user memory,
+//! the handle tables, the threads and their kernel stacks, and the kernel
+//! heap, growing up to `memory::heap::HEAP_MAX_SIZE` bytes, used by system
+//! calls for their buffers. Reaching a limit makes the request fail, the
+//! process keeps running.
+//!
+
+// internal crate
+use super::{
+    file::MAX_FILES,
+    handle::MAX_HANDLES,
+    process::{self, Pid},
+    TaskError,
+};
+
+/// The limits of the resources of a process.
+///
+/// A new process inherits the limits of its parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of bytes of user memory reserved by the address space.
+    pub max_memory: u64,
+    /// Maximum number of handles, and of file descriptors.
+    pub max_handles: usize,
+    /// Maximum number of threads.
+    pub max_threads: usize,
+    /// Maximum number of bytes of kernel heap used by the system calls of the
+    /// process at once.
+    pub max_heap: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_memory: 32 << 20,
+            max_handles: MAX_FILES.min(MAX_HANDLES),
+            max_threads: 16,
+            max_heap: 32 << 10,
+        }
+    }
+}
+
+/// The resources used by a process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Ticks spent running user code.
+    pub user_ticks: u64,
+    /// Ticks spent in the kernel, on behalf of the process.
+    pub system_ticks: u64,
+    /// Pages of user memory mapped.
+    pub resident_pages: u64,
+    /// Highest number of pages of user memory mapped at once.
+    pub peak_resident_pages: u64,
+    /// Handles and file descriptors.
+    pub handles: usize,
+    /// Alive threads.
+    pub threads: usize,
+    /// Bytes of kernel heap used by the system calls in progress.
+    pub heap_bytes: usize,
+    /// Highest number of bytes of kernel heap used at once.
+    pub peak_heap_bytes: usize,
+}
+
+impl Usage {
+    /// Add the usage of the terminated `child`, for the usage of the children
+    /// of a process : the CPU times add up, the peaks are the highest ones.
+    pub fn add_child(&mut self, child: &Usage) {
+        self.user_ticks += child.user_ticks;
+        self.system_ticks += child.system_ticks;
+        self.peak_resident_pages = self.peak_resident_pages.max(child.peak_resident_pages);
+        self.peak_heap_bytes = self.peak_heap_bytes.max(child.peak_heap_bytes);
+    }
+}
+
+/// The counters of a process which are not computed from its state.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Accounting {
+    pub(super) user_ticks: u64,
+    pub(super) system_ticks: u64,
+    pub(super) heap_bytes: usize,
+    pub(super) peak_heap_bytes: usize,
+    /// The peak of the address spaces the process no longer has.
+    pub(super) peak_resident_pages: u64,
+}
+
+impl Accounting {
+    /// Charge at most `bytes` of kernel heap within `limit`, all of them if
+    /// `exact` is set.
+    ///
+    /// Returns the number of bytes charged.
+    pub(super) fn charge_heap(
+        &mut self,
+        bytes: usize,
+        limit: usize,
+        exact: bool,
+    ) -> Result<usize, TaskError> {
+        let available = limit.saturating_sub(self.heap_bytes);
+        let charged = bytes.min(available);
+        if (charged == 0 && bytes > 0) || (exact && charged < bytes) {
+            return Err(TaskError::LimitExceeded);
+        }
+        self.heap_bytes += charged;
+        self.peak_heap_bytes = self.peak_heap_bytes.max(self.heap_bytes);
+        Ok(charged)
+    }
+
+    /// Release `bytes` of kernel heap charged before.
+    pub(super) fn uncharge_heap(&mut self, bytes: usize) {
+        self.heap_bytes -= bytes;
+    }
+}
+
+/// Kernel heap charged to the current process, released when dropped.
+///
+/// System calls take a charge for the size of their buffers before allocating
+/// them.
+#[derive(Debug)]
+pub struct HeapCharge {
+    pid: Pid,
+    bytes: usize,
+}
+
+impl HeapCharge {
+    /// Charge `bytes` to the current process.
+    pub fn new(bytes: usize) -> Result<Self, TaskError> {
+        Self::charge(bytes, true)
+    }
+
+    /// Charge at most `bytes` to the current process, as many as its limit
+    /// allows, but at least one if `bytes` is not 0.
+    ///
+    /// Used by transfers which may be shorter than requested.
+    pub fn up_to(bytes: usize) -> Result<Self, TaskError> {
+        Self::charge(bytes, false)
+    }
+
+    /// Returns the number of bytes charged.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn charge(bytes: usize, exact: bool) -> Result<Self, TaskError> {
+        let pid = process::current();
+        let bytes = process::with_process(pid, |process| process.charge_heap(bytes, exact))??;
+        Ok(HeapCharge { pid, bytes })
+    }
+}
+
+impl Drop for HeapCharge {
+    fn drop(&mut self) {
+        let bytes = self.bytes;
+        // the process may have been terminated meanwhile
+        let _ = process::with_process(self.pid, |process| process.uncharge_heap(bytes));
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_heap_charge() {
+    serial_print!("test_heap_charge... ");
+    let mut accounting = Accounting::default();
+    assert_eq!(accounting.charge_heap(100, 150, true), Ok(100));
+    assert_eq!(
+        accounting.charge_heap(100, 150, true),
+        Err(TaskError::LimitExceeded)
+    );
+    assert_eq!(accounting.charge_heap(100, 150, false), Ok(50));
+    assert_eq!(
+        accounting.charge_heap(1, 150, false),
+        Err(TaskError::LimitExceeded)
+    );
+    accounting.uncharge_heap(150);
+    assert_eq!(accounting.heap_bytes, 0);
+    assert_eq!(accounting.peak_heap_bytes, 150);
+    serial_println!("[ok]");
+}
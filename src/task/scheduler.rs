@@ -0,0 +1,373 @@
+//! This module contains the scheduler.
+//!
+//! It is a simple preemptive round-robin scheduler : every ready thread runs
+//! for at most `TIME_SLICE` ticks before being put back at the end of the
+//! queue. When no thread is ready, an idle thread halts the processor.
+//!
+
+// internal crate
+use super::{
+    local::{self, LocalStorage},
+    process::{Pid, KERNEL_PID},
+    switch,
+    thread::{Thread, ThreadId, ThreadState},
+    TaskError,
+};
+use crate::{
+    interrupts::gdt,
+    memory::{self, kernel_level_4_frame, mapping},
+    syscall,
+};
+
+// external crates
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use spin::Mutex;
+use x86_64::{
+    instructions::{self, interrupts},
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::PhysFrame,
+    VirtAddr,
+};
+
+/// Number of ticks a thread can run before being preempted.
+pub const TIME_SLICE: u64 = 10;
+
+/// The scheduler, available once `init` has been called.
+///
+/// It must only be locked with interrupts disabled.
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+/// The state of the scheduler.
+struct Scheduler {
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    ready: VecDeque<ThreadId>,
+    current: ThreadId,
+    idle: ThreadId,
+    /// Exited threads, freed once another thread runs.
+    exited: Vec<ThreadId>,
+    /// Ticks left before the current thread is preempted.
+    slice_left: u64,
+}
+
+/// A context switch decided by the scheduler.
+struct Switch {
+    old_stack_pointer: *mut u64,
+    new_stack_pointer: u64,
+    page_table: PhysFrame,
+    kernel_stack_end: Option<VirtAddr>,
+    fs_base: u64,
+    locals: *mut LocalStorage,
+}
+
+impl Scheduler {
+    /// Returns the current thread.
+    fn current_mut(&mut self) -> &mut Thread {
+        self.threads
+            .get_mut(&self.current)
+            .expect("current thread does not exist")
+    }
+
+    /// Free the threads that exited and their kernel stack, except the
+    /// current one.
+    fn reap(&mut self) {
+        let current = self.current;
+        let threads = &mut self.threads;
+        self.exited.retain(|&id| {
+            if id == current {
+                return true;
+            }
+            if let Some(stack) = threads.get(&id).and_then(|thread| thread.kernel_stack()) {
+                let freed = memory::try_with_kernel_memory(|mapper, frame_allocator| unsafe {
+                    mapping::free_stack(stack, mapper, frame_allocator)
+                });
+                match freed {
+                    Some(freed) => freed.expect("kernel stack not mapped"),
+                    // the interrupted code holds the page table : retry later
+                    None => return true,
+                }
+            }
+            threads.remove(&id);
+            false
+        });
+    }
+
+    /// Choose the next thread to run, and update the states accordingly.
+    ///
+    /// Returns `None` if the current thread keeps running.
+    fn next_switch(&mut self) -> Option<Switch> {
+        self.reap();
+
+        let current = self.current;
+        let current_runnable = self.current_mut().state() == ThreadState::Running;
+
+        let mut next = None;
+        while let Some(id) = self.ready.pop_front() {
+            let is_ready = self
+                .threads
+                .get(&id)
+                .map_or(false, |thread| thread.state() == ThreadState::Ready);
+            if is_ready {
+                next = Some(id);
+                break;
+            }
+        }
+        let next = match next {
+            Some(id) => id,
+            None if current_runnable => {
+                self.slice_left = TIME_SLICE;
+                return None;
+            }
+            None => self.idle,
+        };
+        if next == current {
+            return None;
+        }
+
+        let idle = self.idle;
+        let thread = self.current_mut();
+        match thread.state() {
+            ThreadState::Running => {
+                thread.set_state(ThreadState::Ready);
+                if current != idle {
+                    self.ready.push_back(current);
+                }
+            }
+            ThreadState::Exited => self.exited.push(current),
+            ThreadState::Ready | ThreadState::Blocked => {}
+        }
+
+        self.current = next;
+        self.slice_left = TIME_SLICE;
+        let thread = self.current_mut();
+        thread.set_state(ThreadState::Running);
+        let new_stack_pointer = thread.stack_pointer();
+        let page_table = thread.page_table();
+        let kernel_stack_end = thread.kernel_stack().map(|stack| stack.end());
+        let fs_base = thread.fs_base();
+        let locals = thread.locals_mut();
+
+        let old_stack_pointer = self
+            .threads
+            .get_mut(&current)
+            .expect("previous thread does not exist")
+            .stack_pointer_mut();
+
+        Some(Switch {
+            old_stack_pointer,
+            new_stack_pointer,
+            page_table,
+            kernel_stack_end,
+            fs_base,
+            locals,
+        })
+    }
+}
+
+/// Initialize the scheduler, making the running code the first thread of the
+/// kernel process.
+pub(super) fn init() {
+    let page_table = kernel_level_4_frame();
+    let mut boot = Box::new(Thread::bootstrap(KERNEL_PID, page_table));
+    let idle = Thread::new(KERNEL_PID, page_table, idle_loop, 0)
+        .expect("idle thread creation failed");
+
+    let mut threads = BTreeMap::new();
+    let (boot_id, idle_id) = (boot.id(), idle.id());
+    let boot_locals = boot.locals_mut();
+    threads.insert(boot_id, boot);
+    threads.insert(idle_id, Box::new(idle));
+
+    interrupts::without_interrupts(|| {
+        unsafe { local::set_current(boot_locals) };
+        *SCHEDULER.lock() = Some(Scheduler {
+            threads,
+            ready: VecDeque::new(),
+            current: boot_id,
+            idle: idle_id,
+            exited: Vec::new(),
+            slice_left: TIME_SLICE,
+        })
+    });
+}
+
+/// The thread running when no other thread is ready.
+fn idle_loop(_: usize) {
+    loop {
+        instructions::hlt();
+    }
+}
+
+/// Run `f` with the scheduler, interrupts disabled.
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Scheduler) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        f(scheduler.as_mut().expect("scheduler is not initialized"))
+    })
+}
+
+/// Add a new thread of `process`, running `entry(arg)`, to the ready queue.
+pub(super) fn spawn(
+    process: Pid,
+    page_table: PhysFrame,
+    entry: fn(usize),
+    arg: usize,
+) -> Result<ThreadId, TaskError> {
+    let thread = Thread::new(process, page_table, entry, arg)?;
+    let id = thread.id();
+    with_scheduler(|scheduler| {
+        scheduler.threads.insert(id, Box::new(thread));
+        scheduler.ready.push_back(id);
+    });
+    Ok(id)
+}
+
+/// Returns the current thread and its process.
+pub fn current() -> (ThreadId, Pid) {
+    with_scheduler(|scheduler| {
+        let thread = scheduler.current_mut();
+        (thread.id(), thread.process())
+    })
+}
+
+/// Returns the current thread and its process, or `None` before the
+/// scheduler is initialized.
+///
+/// Usable from interrupt handlers.
+pub fn try_current() -> Option<(ThreadId, Pid)> {
+    let mut scheduler = SCHEDULER.try_lock()?;
+    let thread = scheduler.as_mut()?.current_mut();
+    Some((thread.id(), thread.process()))
+}
+
+/// Set the level 4 page table of the current thread, when its process replaces
+/// its address space.
+///
+/// The caller loads it in `CR3`.
+pub(super) fn set_current_page_table(page_table: PhysFrame) {
+    with_scheduler(|scheduler| scheduler.current_mut().set_page_table(page_table));
+}
+
+/// Set the base of the `FS` segment of the current thread, and load it.
+pub fn set_current_fs_base(fs_base: VirtAddr) {
+    with_scheduler(|scheduler| {
+        scheduler.current_mut().set_fs_base(fs_base.as_u64());
+        unsafe { switch::set_fs_base(fs_base.as_u64()) };
+    });
+}
+
+/// Returns the base of the `FS` segment of the current thread.
+pub fn current_fs_base() -> VirtAddr {
+    with_scheduler(|scheduler| VirtAddr::new(scheduler.current_mut().fs_base()))
+}
+
+/// Returns the state of the given thread, if it still exists.
+pub fn state(id: ThreadId) -> Option<ThreadState> {
+    with_scheduler(|scheduler| scheduler.threads.get(&id).map(|thread| thread.state()))
+}
+
+/// Switch to the next ready thread, if any.
+pub fn schedule() {
+    interrupts::without_interrupts(|| {
+        let switch = match SCHEDULER.lock().as_mut() {
+            Some(scheduler) => scheduler.next_switch(),
+            None => None,
+        };
+
+        if let Some(switch) = switch {
+            unsafe {
+                if Cr3::read().0 != switch.page_table {
+                    Cr3::write(switch.page_table, Cr3Flags::empty());
+                }
+                if let Some(stack_end) = switch.kernel_stack_end {
+                    gdt::set_kernel_stack(stack_end);
+                    syscall::set_kernel_stack(stack_end);
+                }
+                switch::set_fs_base(switch.fs_base);
+                local::set_current(switch.locals);
+                switch::switch(switch.old_stack_pointer, switch.new_stack_pointer);
+            }
+        }
+    });
+}
+
+/// Give up the rest of the time slice of the current thread.
+pub fn yield_now() {
+    schedule();
+}
+
+/// Count a tick for the current thread, and preempt it at the end of its time
+/// slice.
+///
+/// Must only be called by the timer interrupt handler.
+pub fn tick() {
+    let preempt = match SCHEDULER.try_lock() {
+        Some(mut scheduler) => match scheduler.as_mut() {
+            Some(scheduler) => {
+                scheduler.slice_left = scheduler.slice_left.saturating_sub(1);
+                scheduler.slice_left == 0
+            }
+            None => false,
+        },
+        None => false,
+    };
+    if preempt {
+        schedule();
+    }
+}
+
+/// Block the current thread until `unblock` is called on it.
+///
+/// To avoid missing a wake up, interrupts should be disabled from the
+/// registration of the thread as a waiter to this call.
+pub fn block_current() {
+    interrupts::without_interrupts(|| {
+        with_scheduler(|scheduler| scheduler.current_mut().set_state(ThreadState::Blocked));
+        schedule();
+    });
+}
+
+/// Make the given blocked thread ready again.
+///
+/// Returns `false` if the thread was not blocked.
+pub fn unblock(id: ThreadId) -> bool {
+    with_scheduler(|scheduler| match scheduler.threads.get_mut(&id) {
+        Some(thread) if thread.state() == ThreadState::Blocked => {
+            thread.set_state(ThreadState::Ready);
+            scheduler.ready.push_back(id);
+            true
+        }
+        _ => false,
+    })
+}
+
+/// Mark the given thread as exited : it will not be scheduled anymore.
+///
+/// If it is the current thread, it keeps running until the next call to
+/// `schedule`.
+pub(super) fn terminate(id: ThreadId) {
+    with_scheduler(|scheduler| {
+        if let Some(thread) = scheduler.threads.get_mut(&id) {
+            thread.set_state(ThreadState::Exited);
+            if id != scheduler.current {
+                scheduler.exited.push(id);
+            }
+        }
+    });
+}
+
+/// Terminate the current thread and switch to the next one.
+///
+/// The process of the thread is not updated, see `process::exit_thread`.
+pub(super) fn exit_current() -> ! {
+    interrupts::disable();
+    with_scheduler(|scheduler| scheduler.current_mut().set_state(ThreadState::Exited));
+    schedule();
+    unreachable!("exited thread was scheduled again");
+}
@@ -0,0 +1,495 @@
+//! This module contains signals : asynchronous notifications sent to user
+//! processes, by other processes or by the kernel when they fault.
+//!
+//! Signals are process-wide : the pending and blocked sets are shared by every
+//! thread of the process. A pending signal is delivered when a thread returns
+//! to user mode, after a system call, an exception or an interrupt. Its action
+//! is either to terminate the process, to ignore the signal, or to run a user
+//! handler on a `SignalFrame` pushed on the user stack : the handler returns to
+//! its restorer, which calls `rt_sigreturn` to resume the interrupted code.
+//!
+//! A thread blocked in an interruptible wait is woken when a signal becomes
+//! deliverable : its system call fails with `EINTR`.
+//!
+
+// internal crate
+use super::{
+    process::{self, Pid, ProcessState},
+    scheduler, TaskError,
+};
+use crate::{
+    interrupts::trap::TrapFrame,
+    memory::{layout, user},
+};
+
+// external crates
+use core::{mem, slice};
+
+/// Number of supported signals, real-time signals are not.
+pub const SIGNAL_COUNT: usize = 31;
+
+/// A signal number, between 1 and `SIGNAL_COUNT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Signal(u8);
+
+pub const SIGHUP: Signal = Signal(1);
+pub const SIGINT: Signal = Signal(2);
+pub const SIGQUIT: Signal = Signal(3);
+pub const SIGILL: Signal = Signal(4);
+pub const SIGTRAP: Signal = Signal(5);
+pub const SIGABRT: Signal = Signal(6);
+pub const SIGBUS: Signal = Signal(7);
+pub const SIGFPE: Signal = Signal(8);
+pub const SIGKILL: Signal = Signal(9);
+pub const SIGUSR1: Signal = Signal(10);
+pub const SIGSEGV: Signal = Signal(11);
+pub const SIGUSR2: Signal = Signal(12);
+pub const SIGPIPE: Signal = Signal(13);
+pub const SIGALRM: Signal = Signal(14);
+pub const SIGTERM: Signal = Signal(15);
+pub const SIGCHLD: Signal = Signal(17);
+pub const SIGCONT: Signal = Signal(18);
+pub const SIGSTOP: Signal = Signal(19);
+pub const SIGURG: Signal = Signal(23);
+pub const SIGWINCH: Signal = Signal(28);
+
+/// The signals which cannot be caught, ignored nor blocked.
+const UNBLOCKABLE: u64 = 1 << (SIGKILL.0 - 1) | 1 << (SIGSTOP.0 - 1);
+
+/// The handler does not block its own signal.
+pub const SA_NODEFER: u64 = 0x4000_0000;
+/// The action is reset to the default one when the handler runs.
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+/// The handler returns to the given restorer.
+pub const SA_RESTORER: u64 = 0x0400_0000;
+
+/// Size of the area below the user stack pointer that functions may use
+/// without moving it, preserved when a handler runs.
+const RED_ZONE_SIZE: u64 = 128;
+
+/// Flags of `rflags` user programs can change : carry, parity, adjust, zero,
+/// sign, trap, direction and overflow.
+const USER_FLAGS: u64 = 0xdd5;
+/// Interrupt enable flag.
+const INTERRUPT_FLAG: u64 = 0x200;
+/// Trap flag.
+const TRAP_FLAG: u64 = 0x100;
+/// Direction flag.
+const DIRECTION_FLAG: u64 = 0x400;
+
+impl Signal {
+    /// Returns the signal with the given number, if it is supported.
+    pub fn new(number: u64) -> Option<Self> {
+        if number >= 1 && number <= SIGNAL_COUNT as u64 {
+            Some(Signal(number as u8))
+        } else {
+            None
+        }
+    }
+
+    /// Convert the signal to its number.
+    pub fn as_u8(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the bit of the signal in a signal set.
+    pub fn bit(self) -> u64 {
+        1 << (self.0 - 1)
+    }
+
+    /// Returns `true` if the signal can be caught, ignored or blocked.
+    pub fn is_catchable(self) -> bool {
+        self.bit() & UNBLOCKABLE == 0
+    }
+
+    /// Returns `true` if the default action of the signal is to ignore it.
+    ///
+    /// Stopping processes is not supported : the stop signals are ignored.
+    fn is_ignored_by_default(self) -> bool {
+        match self {
+            SIGCHLD | SIGCONT | SIGURG | SIGWINCH => true,
+            // stop, terminal stop, terminal input and output
+            Signal(19..=22) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A user signal handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handler {
+    /// Address of the handler, called with the signal number.
+    pub entry: u64,
+    /// Address the handler returns to, calling `rt_sigreturn`.
+    pub restorer: u64,
+    /// The `SA_*` flags.
+    pub flags: u64,
+    /// The signals blocked while the handler runs.
+    pub mask: u64,
+}
+
+/// The action taken when a signal is delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Terminate the process, or ignore the signal for some signals.
+    Default,
+    /// Ignore the signal.
+    Ignore,
+    /// Run a user handler.
+    Handler(Handler),
+}
+
+/// A signal taken from the pending set, and how to deliver it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Terminate the process.
+    Terminate(Signal),
+    /// Run `handler`, then restore the `blocked` set.
+    Handle {
+        signal: Signal,
+        handler: Handler,
+        blocked: u64,
+    },
+}
+
+/// The signal state of a process.
+#[derive(Debug, Clone)]
+pub struct SignalState {
+    actions: [Action; SIGNAL_COUNT],
+    pending: u64,
+    blocked: u64,
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        SignalState {
+            actions: [Action::Default; SIGNAL_COUNT],
+            pending: 0,
+            blocked: 0,
+        }
+    }
+}
+
+impl SignalState {
+    /// Returns the action of `signal`.
+    pub fn action(&self, signal: Signal) -> Action {
+        self.actions[signal.0 as usize - 1]
+    }
+
+    /// Set the action of the catchable `signal`, returning the previous one.
+    ///
+    /// A pending signal which becomes ignored is discarded.
+    pub fn set_action(&mut self, signal: Signal, action: Action) -> Action {
+        assert!(signal.is_catchable(), "action of an uncatchable signal");
+        let previous = mem::replace(&mut self.actions[signal.0 as usize - 1], action);
+        if self.is_ignored(signal) {
+            self.pending &= !signal.bit();
+        }
+        previous
+    }
+
+    /// Reset the handled signals to their default action, when the process
+    /// starts a new program : the handlers do not exist in it.
+    ///
+    /// The ignored signals stay ignored.
+    pub fn reset_handlers(&mut self) {
+        for action in self.actions.iter_mut() {
+            if let Action::Handler(_) = action {
+                *action = Action::Default;
+            }
+        }
+    }
+
+    /// Returns the set of blocked signals.
+    pub fn blocked(&self) -> u64 {
+        self.blocked
+    }
+
+    /// Set the set of blocked signals, the uncatchable ones being ignored.
+    pub fn set_blocked(&mut self, blocked: u64) {
+        self.blocked = blocked & !UNBLOCKABLE & ((1 << SIGNAL_COUNT) - 1);
+    }
+
+    /// Returns the set of pending signals.
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    /// Returns `true` if a pending signal is not blocked.
+    pub fn has_deliverable(&self) -> bool {
+        self.pending & !self.blocked != 0
+    }
+
+    /// Make `signal` pending, unless it is ignored.
+    ///
+    /// Returns `true` if it can be delivered right away.
+    pub fn post(&mut self, signal: Signal) -> bool {
+        if self.is_ignored(signal) {
+            return false;
+        }
+        self.pending |= signal.bit();
+        self.blocked & signal.bit() == 0
+    }
+
+    /// Make `signal` pending even if it is blocked or ignored, in which case
+    /// its action is reset to the default one.
+    ///
+    /// It is used for faults : the faulting code cannot be resumed.
+    pub fn force(&mut self, signal: Signal) {
+        let index = signal.0 as usize - 1;
+        if self.blocked & signal.bit() != 0 || self.actions[index] == Action::Ignore {
+            self.blocked &= !signal.bit();
+            self.actions[index] = Action::Default;
+        }
+        self.pending |= signal.bit();
+    }
+
+    /// Take the next deliverable signal, discarding the ignored ones.
+    pub fn take_next(&mut self) -> Option<Delivery> {
+        loop {
+            let deliverable = self.pending & !self.blocked;
+            if deliverable == 0 {
+                return None;
+            }
+            let signal = Signal(deliverable.trailing_zeros() as u8 + 1);
+            self.pending &= !signal.bit();
+
+            match self.action(signal) {
+                Action::Default if signal.is_ignored_by_default() => {}
+                Action::Default => return Some(Delivery::Terminate(signal)),
+                Action::Ignore => {}
+                Action::Handler(handler) => {
+                    let blocked = self.blocked;
+                    let mut mask = handler.mask;
+                    if handler.flags & SA_NODEFER == 0 {
+                        mask |= signal.bit();
+                    }
+                    self.set_blocked(blocked | mask);
+                    if handler.flags & SA_RESETHAND != 0 {
+                        self.actions[signal.0 as usize - 1] = Action::Default;
+                    }
+                    return Some(Delivery::Handle {
+                        signal,
+                        handler,
+                        blocked,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if `signal` is discarded when sent.
+    fn is_ignored(&self, signal: Signal) -> bool {
+        match self.action(signal) {
+            Action::Default => signal.is_ignored_by_default(),
+            Action::Ignore => true,
+            Action::Handler(_) => false,
+        }
+    }
+}
+
+// ! ------------- sending -------------
+
+/// Send `signal` to the process `pid`.
+///
+/// If the signal can be delivered, the threads of the process blocked in an
+/// interruptible wait are woken.
+pub fn send(pid: Pid, signal: Signal) -> Result<(), TaskError> {
+    process::with_process(pid, |process| {
+        if process.state() != ProcessState::Running || process.pid() == process::KERNEL_PID {
+            return Err(TaskError::NoSuchProcess);
+        }
+        if process.signals().post(signal) {
+            for &thread in process.threads() {
+                scheduler::unblock(thread);
+            }
+        }
+        Ok(())
+    })?
+}
+
+/// Force `signal` on the current process, after a fault of its user code.
+pub fn force(signal: Signal) {
+    process::with_process(process::current(), |process| {
+        process.signals().force(signal)
+    })
+    .expect("current process does not exist");
+}
+
+/// Returns `true` if a signal can be delivered to the current process.
+pub fn has_pending() -> bool {
+    process::with_process(process::current(), |process| {
+        process.signals().has_deliverable()
+    })
+    .unwrap_or(false)
+}
+
+// ! ------------- delivery -------------
+
+/// The frame pushed on the user stack to run a handler.
+#[derive(Debug, Clone, Default)]
+#[repr(C)]
+struct SignalFrame {
+    /// The return address of the handler.
+    restorer: u64,
+    /// The registers of the interrupted code.
+    registers: TrapFrame,
+    /// The blocked signals of the interrupted code.
+    blocked: u64,
+}
+
+impl SignalFrame {
+    /// Returns the bytes of the frame.
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self as *const _ as *const u8, mem::size_of::<Self>()) }
+    }
+
+    /// Returns the bytes of the frame, mutably.
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self as *mut _ as *mut u8, mem::size_of::<Self>()) }
+    }
+}
+
+/// Deliver the pending signals of the current process, before returning to
+/// the user code interrupted in `frame`.
+///
+/// Either the process is terminated, or `frame` is changed to run a handler.
+pub fn deliver(frame: &mut TrapFrame) {
+    debug_assert!(frame.is_user(), "signal delivered to kernel code");
+
+    let delivery =
+        process::with_process(process::current(), |process| process.signals().take_next())
+            .expect("current process does not exist");
+
+    match delivery {
+        None => {}
+        Some(Delivery::Terminate(signal)) => process::kill_current(signal),
+        Some(Delivery::Handle {
+            signal,
+            handler,
+            blocked,
+        }) => {
+            if push_frame(frame, signal, &handler, blocked).is_err() {
+                // the handler cannot run, as the stack is unusable
+                process::kill_current(SIGSEGV);
+            }
+        }
+    }
+}
+
+/// Push a `SignalFrame` on the user stack of `frame`, and make `frame` run
+/// `handler`.
+fn push_frame(
+    frame: &mut TrapFrame,
+    signal: Signal,
+    handler: &Handler,
+    blocked: u64,
+) -> Result<(), user::BadUserAddress> {
+    let signal_frame = SignalFrame {
+        restorer: handler.restorer,
+        registers: frame.clone(),
+        blocked,
+    };
+    let size = mem::size_of::<SignalFrame>() as u64;
+    // the stack is aligned on 16 bytes before the return address is pushed
+    let address = frame
+        .rsp
+        .checked_sub(RED_ZONE_SIZE + size)
+        .map(|address| (address & !0xf) - 8)
+        .ok_or(user::BadUserAddress)?;
+    user::copy_to_user(address, signal_frame.as_bytes())?;
+
+    frame.rip = handler.entry;
+    frame.rsp = address;
+    frame.rdi = u64::from(signal.0);
+    frame.rsi = 0;
+    frame.rdx = 0;
+    frame.rax = 0;
+    frame.rflags &= !(TRAP_FLAG | DIRECTION_FLAG);
+    Ok(())
+}
+
+/// Return from a handler, restoring the registers saved in the `SignalFrame`
+/// below the user stack pointer of `frame`.
+///
+/// The current process is killed if the frame cannot be read or is invalid.
+pub fn sigreturn(frame: &mut TrapFrame) {
+    let mut signal_frame = SignalFrame::default();
+    // the return address has been popped by the handler
+    let address = frame.rsp.wrapping_sub(8);
+    let restored = user::copy_from_user(signal_frame.as_bytes_mut(), address)
+        .ok()
+        .map(|_| signal_frame.registers)
+        .filter(|registers| {
+            layout::is_user_range(registers.rip, 1) && registers.rsp <= layout::USER_SPACE_END
+        });
+    let registers = match restored {
+        Some(registers) => registers,
+        None => {
+            force(SIGSEGV);
+            return;
+        }
+    };
+
+    // the segments and the privileged flags stay the ones of user mode
+    *frame = TrapFrame {
+        rflags: registers.rflags & USER_FLAGS | INTERRUPT_FLAG,
+        cs: frame.cs,
+        ss: frame.ss,
+        vector: frame.vector,
+        error_code: frame.error_code,
+        ..registers
+    };
+    let blocked = signal_frame.blocked;
+    process::with_process(process::current(), |process| {
+        process.signals().set_blocked(blocked)
+    })
+    .expect("current process does not exist");
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_signal_state() {
+    serial_print!("test_signal_state... ");
+    let mut state = SignalState::default();
+    let handler = Handler {
+        entry: 0x1000,
+        restorer: 0x2000,
+        flags: SA_RESTORER | SA_RESETHAND,
+        mask: SIGUSR2.bit(),
+    };
+    state.set_action(SIGUSR1, Action::Handler(handler));
+    state.set_blocked(SIGTERM.bit() | SIGKILL.bit());
+    assert_eq!(state.blocked(), SIGTERM.bit());
+
+    // ignored signals are discarded, blocked ones stay pending
+    assert!(!state.post(SIGCHLD));
+    assert!(!state.post(SIGTERM));
+    assert!(state.post(SIGUSR1));
+    assert_eq!(state.pending(), SIGTERM.bit() | SIGUSR1.bit());
+
+    assert_eq!(
+        state.take_next(),
+        Some(Delivery::Handle {
+            signal: SIGUSR1,
+            handler,
+            blocked: SIGTERM.bit(),
+        })
+    );
+    assert_eq!(
+        state.blocked(),
+        SIGTERM.bit() | SIGUSR1.bit() | SIGUSR2.bit()
+    );
+    assert_eq!(state.action(SIGUSR1), Action::Default);
+    assert_eq!(state.take_next(), None);
+
+    // faults are delivered even if blocked
+    state.force(SIGTERM);
+    assert_eq!(state.take_next(), Some(Delivery::Terminate(SIGTERM)));
+    serial_println!("[ok]");
+}
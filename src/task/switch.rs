@@ -0,0 +1,174 @@
+//! This module contains the low-level context switch between threads.
+//!
+//! The context of a thread is saved on its own kernel stack : only its stack
+//! pointer needs to be stored elsewhere. The `FS` base, pointing to the
+//! thread-local storage of user programs, is kept in the `Thread` and loaded
+//! on each switch.
+//!
+
+// internal crate
+use super::process;
+use crate::interrupts::gdt;
+
+// external crates
+use core::mem;
+use x86_64::{instructions::interrupts, registers::model_specific::Msr, VirtAddr};
+
+/// Base of the `FS` segment.
+const FS_BASE: u32 = 0xc000_0100;
+
+global_asm!(
+    r#"
+.intel_syntax noprefix
+
+// fn nit_switch_context(old_stack_pointer: *mut u64, new_stack_pointer: u64)
+.global nit_switch_context
+nit_switch_context:
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    pushfq
+    mov [rdi], rsp
+    mov rsp, rsi
+    popfq
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    ret
+
+// first code run by a new thread : the entry point and its argument have been
+// placed in r12 and r13 by `init_stack`
+.global nit_thread_trampoline
+nit_thread_trampoline:
+    mov rdi, r12
+    mov rsi, r13
+    call nit_thread_start
+    ud2
+
+// fn nit_enter_user_mode(entry: u64, stack_pointer: u64, code_selector: u64, data_selector: u64)
+.global nit_enter_user_mode
+nit_enter_user_mode:
+    push rcx
+    push rsi
+    push 0x202
+    push rdx
+    push rdi
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor edi, edi
+    xor ebp, ebp
+    xor r8, r8
+    xor r9, r9
+    xor r10, r10
+    xor r11, r11
+    xor r12, r12
+    xor r13, r13
+    xor r14, r14
+    xor r15, r15
+    iretq
+
+.att_syntax prefix
+"#
+);
+
+extern "C" {
+    fn nit_switch_context(old_stack_pointer: *mut u64, new_stack_pointer: u64);
+    fn nit_thread_trampoline();
+    fn nit_enter_user_mode(entry: u64, stack_pointer: u64, code_selector: u64, data_selector: u64)
+        -> !;
+}
+
+/// Save the callee-saved registers on the current stack, store the stack
+/// pointer in `old_stack_pointer`, and restore the context saved on
+/// `new_stack_pointer`.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that
+/// `new_stack_pointer` was saved by this function or prepared by `init_stack`,
+/// and that interrupts are disabled.
+pub unsafe fn switch(old_stack_pointer: *mut u64, new_stack_pointer: u64) {
+    nit_switch_context(old_stack_pointer, new_stack_pointer)
+}
+
+/// Set the base of the `FS` segment, used by the thread-local storage of user
+/// programs.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that `base` is a
+/// canonical address.
+pub unsafe fn set_fs_base(base: u64) {
+    Msr::new(FS_BASE).write(base);
+}
+
+/// Prepare the stack ending at `stack_end`, so that switching to it runs
+/// `entry(arg)`.
+///
+/// Returns the stack pointer to switch to.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the stack is
+/// mapped and unused.
+pub unsafe fn init_stack(stack_end: VirtAddr, entry: fn(usize), arg: usize) -> u64 {
+    // context popped by `nit_switch_context`, lowest address first
+    let context: [u64; 10] = [
+        // rflags : interrupts are enabled by `nit_thread_start`
+        0x2,
+        // r15, r14
+        0,
+        0,
+        // r13, r12
+        arg as u64,
+        entry as usize as u64,
+        // rbx, rbp
+        0,
+        0,
+        // return address
+        nit_thread_trampoline as usize as u64,
+        // padding, so that the stack is 16-bytes aligned in the trampoline
+        0,
+        0,
+    ];
+    let stack_pointer = stack_end.as_u64() - mem::size_of_val(&context) as u64;
+    (stack_pointer as *mut [u64; 10]).write(context);
+    stack_pointer
+}
+
+/// Entry point of every new thread, called by `nit_thread_trampoline`.
+#[no_mangle]
+extern "C" fn nit_thread_start(entry: usize, arg: usize) -> ! {
+    interrupts::enable();
+    let entry: fn(usize) = unsafe { mem::transmute(entry) };
+    entry(arg);
+    process::exit_thread()
+}
+
+/// Jump to `entry` in user mode, with the given stack pointer, interrupts
+/// enabled and every other register cleared.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must guarantee that the current
+/// address space maps `entry` and the stack in user space, and that the kernel
+/// stack of the thread is set in the `Task State Segment`.
+pub unsafe fn enter_user_mode(entry: VirtAddr, stack_pointer: VirtAddr) -> ! {
+    let code_selector = gdt::user_code_selector().0 | 3;
+    let data_selector = gdt::user_data_selector().0 | 3;
+    nit_enter_user_mode(
+        entry.as_u64(),
+        stack_pointer.as_u64(),
+        code_selector.into(),
+        data_selector.into(),
+    )
+}
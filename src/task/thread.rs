@@ -0,0 +1,168 @@
+//! This module contains the `Thread` structure.
+//!
+
+// internal crate
+use super::{local::LocalStorage, process::Pid, switch, TaskError};
+use crate::memory::{
+    self,
+    mapping::{alloc_stack, StackBounds},
+};
+
+// external crates
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::PhysFrame;
+
+/// Size of the kernel stack of a thread, in pages.
+pub const KERNEL_STACK_PAGES: u64 = 4;
+
+/// Identifier of a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// Returns a new unique identifier.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Convert the identifier to `u64`.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// The scheduling state of a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Waiting to be scheduled.
+    Ready,
+    /// Currently running.
+    Running,
+    /// Waiting for an event, see `scheduler::block_current`.
+    Blocked,
+    /// Terminated, waiting to be freed.
+    Exited,
+}
+
+/// A thread of execution.
+pub struct Thread {
+    id: ThreadId,
+    process: Pid,
+    state: ThreadState,
+    /// The kernel stack, `None` for the boot thread which runs on the stack
+    /// given by the bootloader.
+    kernel_stack: Option<StackBounds>,
+    /// The stack pointer saved by the last context switch.
+    stack_pointer: u64,
+    /// The level 4 page table to load when switching to this thread.
+    page_table: PhysFrame,
+    /// The base of the `FS` segment, the thread pointer of user programs.
+    fs_base: u64,
+    /// The values of the kernel thread-locals.
+    locals: LocalStorage,
+}
+
+impl Thread {
+    /// Create a thread representing the code currently running.
+    pub(super) fn bootstrap(process: Pid, page_table: PhysFrame) -> Self {
+        Thread {
+            id: ThreadId::new(),
+            process,
+            state: ThreadState::Running,
+            kernel_stack: None,
+            stack_pointer: 0,
+            page_table,
+            fs_base: 0,
+            locals: LocalStorage::new(),
+        }
+    }
+
+    /// Create a new thread of `process`, running `entry(arg)` in kernel mode.
+    ///
+    /// The thread returns to the scheduler when `entry` returns.
+    pub(super) fn new(
+        process: Pid,
+        page_table: PhysFrame,
+        entry: fn(usize),
+        arg: usize,
+    ) -> Result<Self, TaskError> {
+        let kernel_stack = memory::with_kernel_memory(|mapper, frame_allocator| {
+            alloc_stack(KERNEL_STACK_PAGES, mapper, frame_allocator)
+        })?;
+        let stack_pointer = unsafe { switch::init_stack(kernel_stack.end(), entry, arg) };
+
+        Ok(Thread {
+            id: ThreadId::new(),
+            process,
+            state: ThreadState::Ready,
+            kernel_stack: Some(kernel_stack),
+            stack_pointer,
+            page_table,
+            fs_base: 0,
+            locals: LocalStorage::new(),
+        })
+    }
+
+    /// Returns the identifier of the thread.
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Returns the process owning the thread.
+    pub fn process(&self) -> Pid {
+        self.process
+    }
+
+    /// Returns the scheduling state of the thread.
+    pub fn state(&self) -> ThreadState {
+        self.state
+    }
+
+    /// Set the scheduling state of the thread.
+    pub(super) fn set_state(&mut self, state: ThreadState) {
+        self.state = state;
+    }
+
+    /// Returns the kernel stack of the thread.
+    pub fn kernel_stack(&self) -> Option<StackBounds> {
+        self.kernel_stack
+    }
+
+    /// Returns the level 4 page table used by the thread.
+    pub fn page_table(&self) -> PhysFrame {
+        self.page_table
+    }
+
+    /// Set the level 4 page table used by the thread, loaded on the next
+    /// switch to it.
+    pub(super) fn set_page_table(&mut self, page_table: PhysFrame) {
+        self.page_table = page_table;
+    }
+
+    /// Returns the base of the `FS` segment of the thread.
+    pub fn fs_base(&self) -> u64 {
+        self.fs_base
+    }
+
+    /// Set the base of the `FS` segment of the thread, loaded on the next
+    /// switch to it.
+    pub(super) fn set_fs_base(&mut self, fs_base: u64) {
+        self.fs_base = fs_base;
+    }
+
+    /// Returns a pointer to the kernel thread-locals, for the context switch.
+    pub(super) fn locals_mut(&mut self) -> *mut LocalStorage {
+        &mut self.locals
+    }
+
+    /// Returns a pointer to the saved stack pointer, for the context switch.
+    pub(super) fn stack_pointer_mut(&mut self) -> *mut u64 {
+        &mut self.stack_pointer
+    }
+
+    /// Returns the saved stack pointer, for the context switch.
+    pub(super) fn stack_pointer(&self) -> u64 {
+        self.stack_pointer
+    }
+}
@@ -0,0 +1,116 @@
+//! This module contains the `WaitQueue`, on which threads block until an
+//! event occurs.
+//!
+//! A blocked thread may be woken spuriously, for example when a signal is sent
+//! to its process : the waits check their condition again after each wake up.
+//!
+
+// internal crate
+use super::{scheduler, signal, thread::ThreadId};
+
+// external crates
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// An error returned when an interruptible wait is interrupted by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
+
+/// A queue of threads blocked until an event occurs.
+///
+/// Waking threads is possible from interrupt handlers.
+pub struct WaitQueue {
+    /// The waiting threads, in arrival order.
+    waiters: Mutex<Vec<ThreadId>>,
+}
+
+impl WaitQueue {
+    /// Create an empty queue.
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Block the current thread until `condition` returns `true`.
+    ///
+    /// The condition is checked with interrupts disabled, before blocking and
+    /// after each wake up.
+    pub fn wait_until<F>(&self, mut condition: F)
+    where
+        F: FnMut() -> bool,
+    {
+        interrupts::without_interrupts(|| loop {
+            if condition() {
+                self.unregister_current();
+                return;
+            }
+            self.register_current();
+            scheduler::block_current();
+        })
+    }
+
+    /// Block the current thread until `condition` returns `true`, or until a
+    /// signal can be delivered to its process.
+    pub fn wait_until_interruptible<F>(&self, mut condition: F) -> Result<(), Interrupted>
+    where
+        F: FnMut() -> bool,
+    {
+        interrupts::without_interrupts(|| loop {
+            if condition() {
+                self.unregister_current();
+                return Ok(());
+            }
+            if signal::has_pending() {
+                self.unregister_current();
+                return Err(Interrupted);
+            }
+            self.register_current();
+            scheduler::block_current();
+        })
+    }
+
+    /// Add the current thread to the waiters, unless a spurious wake up left
+    /// it there.
+    fn register_current(&self) {
+        let (id, _) = scheduler::current();
+        let mut waiters = self.waiters.lock();
+        if !waiters.contains(&id) {
+            waiters.push(id);
+        }
+    }
+
+    /// Remove the current thread from the waiters, where a spurious wake up
+    /// may have left it : `wake_one` would otherwise pick it instead of a
+    /// thread really waiting, even once it waits for something else.
+    fn unregister_current(&self) {
+        let (id, _) = scheduler::current();
+        self.waiters.lock().retain(|&waiter| waiter != id);
+    }
+
+    /// Wake the first waiting thread.
+    ///
+    /// Returns `false` if no thread was waiting.
+    pub fn wake_one(&self) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            while !waiters.is_empty() {
+                let id = waiters.remove(0);
+                if scheduler::unblock(id) {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    /// Wake every waiting thread.
+    pub fn wake_all(&self) {
+        interrupts::without_interrupts(|| {
+            for id in self.waiters.lock().drain(..) {
+                scheduler::unblock(id);
+            }
+        })
+    }
+}
@@ -0,0 +1,168 @@
+//! This module keeps track of the time elapsed since boot, and permits to run
+//! code after a delay.
+//!
+//! Time is counted in ticks of the hardware timer interrupt, occurring at
+//! `TICK_FREQUENCY`. Delayed and periodic callbacks are stored in a
+//! hierarchical `TimerWheel`, advanced at every tick.
+//!
+//! Callbacks are run inside the timer interrupt handler : they must be short
+//! and must not block.
+//!
+
+// submodules
+mod wheel;
+
+// public submodules
+pub mod sleep;
+
+// submodules exports
+pub use sleep::{sleep, Sleep};
+pub use wheel::TimerId;
+
+// internal crate
+use crate::drivers::pit;
+use wheel::TimerWheel;
+
+// external crates
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Frequency of the timer interrupt, in Hz.
+pub const TICK_FREQUENCY: u64 = 1000;
+
+/// Number of nanoseconds in a tick.
+const NANOS_PER_TICK: u64 = 1_000_000_000 / TICK_FREQUENCY;
+
+/// Number of timer interrupts since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    /// The timer wheel containing every pending timer.
+    ///
+    /// Outside of the timer interrupt, it must only be locked with interrupts
+    /// disabled.
+    static ref TIMERS: Mutex<TimerWheel> = Mutex::new(TimerWheel::new(0));
+}
+
+/// Initialize the hardware timer to fire at `TICK_FREQUENCY`.
+pub fn init() {
+    pit::set_frequency(TICK_FREQUENCY as u32);
+}
+
+/// Returns the number of ticks since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// Returns the time elapsed since boot.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Convert a duration to a number of ticks, rounded up.
+///
+/// Durations too long to fit are saturated to `u64::max_value()` ticks.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos();
+    let ticks = (nanos + u128::from(NANOS_PER_TICK) - 1) / u128::from(NANOS_PER_TICK);
+    ticks.min(u128::from(u64::max_value())) as u64
+}
+
+/// Convert a number of ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks * NANOS_PER_TICK)
+}
+
+// ! ------------- timers -------------
+
+/// Run `callback` once, after `delay`.
+pub fn schedule_once(delay: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    schedule_at(ticks().saturating_add(duration_to_ticks(delay)), callback)
+}
+
+/// Run `callback` once, when the tick count reaches `deadline`.
+///
+/// If the deadline has already passed, the callback is run at the next tick.
+pub fn schedule_at(deadline: u64, callback: impl FnMut() + Send + 'static) -> TimerId {
+    let callback = Box::new(callback);
+    interrupts::without_interrupts(|| TIMERS.lock().add(deadline, None, callback))
+}
+
+/// Run `callback` every `period`, starting after a first `period`.
+pub fn schedule_periodic(period: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    let period = duration_to_ticks(period).max(1);
+    let callback = Box::new(callback);
+    interrupts::without_interrupts(|| {
+        TIMERS
+            .lock()
+            .add(ticks().saturating_add(period), Some(period), callback)
+    })
+}
+
+/// Cancel the given timer.
+///
+/// Returns `false` if the timer has already expired or been cancelled.
+pub fn cancel(id: TimerId) -> bool {
+    interrupts::without_interrupts(|| TIMERS.lock().cancel(id))
+}
+
+/// Count a new tick and run the expired timers.
+///
+/// Must only be called by the timer interrupt handler.
+pub fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let expired = match TIMERS.try_lock() {
+        Some(mut timers) => timers.advance(now),
+        // the wheel will catch up at the next tick
+        None => return,
+    };
+    for mut timer in expired {
+        timer.run();
+        TIMERS.lock().rearm(timer);
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_timer_callback() {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicBool;
+
+    serial_print!("test_timer_callback... ");
+
+    let fired = Arc::new(AtomicBool::new(false));
+    let flag = fired.clone();
+    schedule_once(Duration::from_millis(5), move || {
+        flag.store(true, Ordering::SeqCst)
+    });
+    while !fired.load(Ordering::SeqCst) {
+        x86_64::instructions::hlt();
+    }
+    assert!(uptime() >= Duration::from_millis(5));
+
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_duration_to_ticks_saturates() {
+    serial_print!("test_duration_to_ticks_saturates... ");
+
+    assert_eq!(duration_to_ticks(Duration::from_nanos(1)), 1);
+    assert_eq!(duration_to_ticks(Duration::from_millis(5)), 5);
+    let longest = Duration::new(i64::max_value() as u64, 999_999_999);
+    assert_eq!(duration_to_ticks(longest), u64::max_value());
+
+    serial_println!("[ok]");
+}
@@ -0,0 +1,117 @@
+//! This module contains the `Sleep` future, completing after a delay.
+//!
+
+// internal crate
+use super::{duration_to_ticks, ticks, TimerId};
+
+// external crates
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// A future completing when the tick count reaches a deadline.
+///
+/// The timer is only registered on the first poll, and cancelled on drop.
+pub struct Sleep {
+    deadline: u64,
+    timer: Option<TimerId>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl Sleep {
+    /// Create a future completing when the tick count reaches `deadline`.
+    pub fn until(deadline: u64) -> Self {
+        Sleep {
+            deadline,
+            timer: None,
+            waker: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the tick at which the future completes.
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+}
+
+/// Returns a future completing after `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::until(ticks().saturating_add(duration_to_ticks(duration)))
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        // the waker is taken by the timer interrupt
+        interrupts::without_interrupts(|| *self.waker.lock() = Some(cx.waker().clone()));
+
+        if self.timer.is_none() {
+            let waker = self.waker.clone();
+            self.timer = Some(super::schedule_at(self.deadline, move || {
+                if let Some(waker) = waker.lock().take() {
+                    waker.wake();
+                }
+            }));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            super::cancel(timer);
+        }
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_sleep_future() {
+    use core::{
+        sync::atomic::{AtomicBool, Ordering},
+        task::{RawWaker, RawWakerVTable},
+    };
+
+    serial_print!("test_sleep_future... ");
+
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+
+    fn clone_waker(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    fn wake_waker(_: *const ()) {
+        WOKEN.store(true, Ordering::SeqCst);
+    }
+    fn drop_waker(_: *const ()) {}
+    static VTABLE: RawWakerVTable =
+        RawWakerVTable::new(clone_waker, wake_waker, wake_waker, drop_waker);
+
+    let waker = unsafe { Waker::from_raw(clone_waker(core::ptr::null())) };
+    let mut cx = Context::from_waker(&waker);
+
+    let mut future = sleep(Duration::from_millis(10));
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+    while !WOKEN.load(Ordering::SeqCst) {
+        x86_64::instructions::hlt();
+    }
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(()));
+
+    serial_println!("[ok]");
+}
@@ -0,0 +1,283 @@
+//! This module contains the `TimerWheel`.
+//!
+//! It is a hierarchical timer wheel : timers are sorted into `LEVELS` wheels of
+//! `SLOTS` slots each. The first wheel has a granularity of one tick, and each
+//! next wheel is `SLOTS` times coarser than the previous one. When a wheel
+//! completes a revolution, the current slot of the coarser wheel is cascaded
+//! into the finer ones.
+//!
+
+// external crates
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::mem;
+
+/// Number of bits of the tick count used to index the slots of a wheel.
+const SLOT_BITS: u32 = 6;
+/// Number of slots in each wheel.
+const SLOTS: usize = 1 << SLOT_BITS;
+/// Number of wheels.
+const LEVELS: usize = 4;
+/// The farthest delay, in ticks, that can be stored without being clamped.
+///
+/// Timers with a longer delay are stored at the end of the coarsest wheel, and
+/// are reinserted when cascaded.
+const MAX_DELAY: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+/// Identifier of a timer, used to cancel it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+/// A callback registered in the `TimerWheel`.
+pub struct Timer {
+    id: TimerId,
+    expires: u64,
+    period: Option<u64>,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+impl Timer {
+    /// Run the callback of the timer.
+    pub fn run(&mut self) {
+        (self.callback)()
+    }
+}
+
+/// A hierarchical timer wheel.
+///
+/// The wheel does not run the callbacks itself : `advance` returns the expired
+/// timers, that must be run by the caller and given back with `rearm`.
+pub struct TimerWheel {
+    /// The last tick processed by the wheel.
+    now: u64,
+    next_id: u64,
+    /// The slots of every wheel, finer wheel first.
+    slots: Vec<Vec<Timer>>,
+    /// Slot index of every pending timer, or `None` if it is currently running.
+    pending: BTreeMap<TimerId, Option<usize>>,
+}
+
+impl TimerWheel {
+    /// Create an empty `TimerWheel`, starting at the tick `now`.
+    pub fn new(now: u64) -> Self {
+        let mut slots = Vec::with_capacity(LEVELS * SLOTS);
+        slots.resize_with(LEVELS * SLOTS, Vec::new);
+        TimerWheel {
+            now,
+            next_id: 0,
+            slots,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Add a timer expiring at the tick `expires`, and repeating every `period`
+    /// ticks if given.
+    pub fn add(
+        &mut self,
+        expires: u64,
+        period: Option<u64>,
+        callback: Box<dyn FnMut() + Send>,
+    ) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.insert(Timer {
+            id,
+            expires,
+            period: period.map(|p| p.max(1)),
+            callback,
+        });
+        id
+    }
+
+    /// Cancel the given timer.
+    ///
+    /// Returns `false` if the timer has already expired or been cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        match self.pending.remove(&id) {
+            Some(Some(slot)) => {
+                self.slots[slot].retain(|timer| timer.id != id);
+                true
+            }
+            // the timer is running, it will not be rearmed
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Returns `true` if the given timer is still pending or running.
+    pub fn is_pending(&self, id: TimerId) -> bool {
+        self.pending.contains_key(&id)
+    }
+
+    /// Advance the wheel up to the tick `now`.
+    ///
+    /// Returns the timers that expired meanwhile, in expiration order.
+    pub fn advance(&mut self, now: u64) -> Vec<Timer> {
+        let mut expired = Vec::new();
+        while self.now < now {
+            self.now += 1;
+
+            // cascade the coarser wheels when the finer ones complete a revolution
+            for level in 1..LEVELS {
+                let shift = SLOT_BITS * level as u32;
+                if self.now & ((1 << shift) - 1) != 0 {
+                    break;
+                }
+                let slot = level * SLOTS + ((self.now >> shift) as usize & (SLOTS - 1));
+                for timer in mem::take(&mut self.slots[slot]) {
+                    self.cascade(timer);
+                }
+            }
+
+            let slot = self.now as usize & (SLOTS - 1);
+            for timer in mem::take(&mut self.slots[slot]) {
+                if timer.expires <= self.now {
+                    self.pending.insert(timer.id, None);
+                    expired.push(timer);
+                } else {
+                    // timer clamped to `MAX_DELAY`
+                    self.insert(timer);
+                }
+            }
+        }
+        expired
+    }
+
+    /// Give back a timer returned by `advance`, once it has run.
+    ///
+    /// Periodic timers are reinserted, except if they were cancelled meanwhile.
+    pub fn rearm(&mut self, mut timer: Timer) {
+        match (timer.period, self.pending.get(&timer.id)) {
+            (Some(period), Some(None)) => {
+                // do not try to catch up with missed periods
+                timer.expires = timer.expires.saturating_add(period).max(self.now + 1);
+                self.insert(timer);
+            }
+            _ => {
+                self.pending.remove(&timer.id);
+            }
+        }
+    }
+
+    /// Move down a timer from a coarser wheel being cascaded.
+    ///
+    /// A timer expiring at the current tick goes to the current slot of the
+    /// first wheel, drained right after the cascade : `insert` would delay it
+    /// by one tick.
+    fn cascade(&mut self, timer: Timer) {
+        if timer.expires > self.now {
+            self.insert(timer);
+            return;
+        }
+        let slot = self.now as usize & (SLOTS - 1);
+        self.pending.insert(timer.id, Some(slot));
+        self.slots[slot].push(timer);
+    }
+
+    /// Insert a timer in the slot corresponding to its expiration, at least
+    /// one tick ahead.
+    fn insert(&mut self, timer: Timer) {
+        let delta = (timer.expires.max(self.now + 1) - self.now).min(MAX_DELAY);
+        let target = self.now + delta;
+
+        let level = (0..LEVELS)
+            .find(|&level| delta < 1 << (SLOT_BITS * (level as u32 + 1)))
+            .unwrap_or(LEVELS - 1);
+        let shift = SLOT_BITS * level as u32;
+        let slot = level * SLOTS + ((target >> shift) as usize & (SLOTS - 1));
+
+        self.pending.insert(timer.id, Some(slot));
+        self.slots[slot].push(timer);
+    }
+}
+
+// ! ------------- tests -------------
+
+// internal crate
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+// external crates
+#[cfg(test)]
+use alloc::sync::Arc;
+#[cfg(test)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Returns a callback incrementing the given counter.
+#[cfg(test)]
+fn counting_callback(counter: &Arc<AtomicUsize>) -> Box<dyn FnMut() + Send> {
+    let counter = counter.clone();
+    Box::new(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    })
+}
+
+#[test_case]
+fn test_timer_wheel_expiration() {
+    serial_print!("test_timer_wheel_expiration... ");
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut wheel = TimerWheel::new(0);
+    let near = wheel.add(10, None, counting_callback(&counter));
+    let far = wheel.add(100_000, None, counting_callback(&counter));
+
+    assert!(wheel.advance(9).is_empty());
+    let expired = wheel.advance(10);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].id, near);
+
+    // the far timer must be cascaded down to the first wheel before expiring
+    assert!(wheel.advance(99_999).is_empty());
+    let expired = wheel.advance(100_000);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].id, far);
+
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_timer_wheel_cascade_on_time() {
+    serial_print!("test_timer_wheel_cascade_on_time... ");
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut wheel = TimerWheel::new(0);
+    // expirations cascaded exactly on their tick, from the second and third wheels
+    let second = wheel.add(128, None, counting_callback(&counter));
+    let third = wheel.add(8192, None, counting_callback(&counter));
+
+    assert!(wheel.advance(127).is_empty());
+    let expired = wheel.advance(128);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].id, second);
+
+    assert!(wheel.advance(8191).is_empty());
+    let expired = wheel.advance(8192);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].id, third);
+
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_timer_wheel_periodic_and_cancel() {
+    serial_print!("test_timer_wheel_periodic_and_cancel... ");
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut wheel = TimerWheel::new(0);
+    let periodic = wheel.add(5, Some(5), counting_callback(&counter));
+    let cancelled = wheel.add(7, None, counting_callback(&counter));
+    assert!(wheel.cancel(cancelled));
+    assert!(!wheel.cancel(cancelled));
+
+    for now in 1..=20 {
+        for mut timer in wheel.advance(now) {
+            timer.run();
+            wheel.rearm(timer);
+        }
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 4);
+
+    assert!(wheel.cancel(periodic));
+    assert!(wheel.advance(40).is_empty());
+
+    serial_println!("[ok]");
+}
@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture::{
+        self,
+        testing::executable::{executable, run},
+    },
+    memory, serial_print, serial_println,
+    task::{self, process::ProcessState, signal::SIGSEGV},
+};
+
+// external crates used
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+    task::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+/// Code mapping `len` bytes of anonymous memory with the protection `prot`,
+/// whose address is left in `rax`.
+fn mmap(len: u32, prot: u8) -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x31, 0xff]); // xor edi, edi
+    code.push(0xbe); // mov esi, len
+    code.extend_from_slice(&len.to_le_bytes());
+    code.extend_from_slice(&[0xba, prot, 0, 0, 0]); // mov edx, prot
+    code.extend_from_slice(&[0x41, 0xba, 0x22, 0, 0, 0]); // mov r10d, MAP_PRIVATE | MAP_ANONYMOUS
+    code.extend_from_slice(&[0x49, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]); // mov r8, -1
+    code.extend_from_slice(&[0x45, 0x31, 0xc9]); // xor r9d, r9d
+    code.extend_from_slice(&[0xb8, 9, 0, 0, 0]); // mov eax, MMAP
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code
+}
+
+/// Code exiting with the status in `edi`.
+const EXIT: [u8; 7] = [0xb8, 60, 0, 0, 0, 0x0f, 0x05];
+
+#[test_case]
+fn anonymous_mapping() {
+    serial_print!("anonymous_mapping... ");
+    let mut code = mmap(0x2000, 3);
+    code.extend_from_slice(&[0xc6, 0x80, 0, 0x10, 0, 0, 42]); // mov byte ptr [rax+0x1000], 42
+    code.extend_from_slice(&[0x0f, 0xb6, 0xb8, 0, 0x10, 0, 0]); // movzx edi, byte ptr [rax+0x1000]
+    code.extend_from_slice(&[0x0f, 0xb6, 0x08]); // movzx ecx, byte ptr [rax]
+    code.extend_from_slice(&[0x01, 0xcf]); // add edi, ecx
+    code.extend_from_slice(&EXIT);
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(42));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn stack_growth() {
+    serial_print!("stack_growth... ");
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0x81, 0xec, 0x00, 0x80, 0, 0]); // sub rsp, 0x8000
+    code.extend_from_slice(&[0xc6, 0x04, 0x24, 42]); // mov byte ptr [rsp], 42
+    code.extend_from_slice(&[0x0f, 0xb6, 0x3c, 0x24]); // movzx edi, byte ptr [rsp]
+    code.extend_from_slice(&EXIT);
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(42));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn system_call_on_unmapped_page() {
+    serial_print!("system_call_on_unmapped_page... ");
+    // the kernel writes the time to a page never accessed by the program
+    let mut code = mmap(0x1000, 3);
+    code.extend_from_slice(&[0x48, 0x89, 0xc6]); // mov rsi, rax
+    code.extend_from_slice(&[0xbf, 1, 0, 0, 0]); // mov edi, CLOCK_MONOTONIC
+    code.extend_from_slice(&[0xb8, 228, 0, 0, 0]); // mov eax, CLOCK_GETTIME
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x8d, 0x78, 42]); // lea edi, [rax + 42]
+    code.extend_from_slice(&EXIT);
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(42));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn read_only_mapping_write_kills() {
+    serial_print!("read_only_mapping_write_kills... ");
+    let mut code = mmap(0x1000, 1);
+    code.extend_from_slice(&[0x0f, 0xb6, 0x08]); // movzx ecx, byte ptr [rax]
+    code.extend_from_slice(&[0xc6, 0x00, 42]); // mov byte ptr [rax], 42
+    code.extend_from_slice(&[0xeb, 0xfe]); // jmp $
+    assert_eq!(run(&executable(&code)), ProcessState::Killed(SIGSEGV));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn inaccessible_mapping_kills() {
+    serial_print!("inaccessible_mapping_kills... ");
+    let mut code = mmap(0x1000, 0);
+    code.extend_from_slice(&[0x0f, 0xb6, 0x08]); // movzx ecx, byte ptr [rax]
+    code.extend_from_slice(&[0xeb, 0xfe]); // jmp $
+    assert_eq!(run(&executable(&code)), ProcessState::Killed(SIGSEGV));
+    serial_println!("[ok]");
+}
@@ -72,6 +72,8 @@ fn many_boxes() {
     serial_println!("[ok]");
 }
 
+// a bump allocator cannot reuse memory while an allocation lives
+#[cfg(not(feature = "allocator_bump"))]
 #[test_case]
 fn many_boxes_long_lived() {
     serial_print!("many_boxes_long_lived... ");
@@ -83,3 +85,16 @@ fn many_boxes_long_lived() {
     assert_eq!(*long_lived, 1); // new
     serial_println!("[ok]");
 }
+
+#[test_case]
+fn heap_statistics() {
+    serial_print!("heap_statistics... ");
+    let before = heap::stats();
+    assert_eq!(before.size, HEAP_SIZE);
+    let heap_value = Box::new([0u64; 4]);
+    let stats = heap::stats();
+    assert!(stats.used >= before.used + 32);
+    assert_eq!(stats.free(), stats.size - stats.used);
+    drop(heap_value);
+    serial_println!("[ok]");
+}
@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture,
+    memory::{
+        self,
+        heap::{self, HEAP_MAX_SIZE, HEAP_SIZE, LARGE_SIZE},
+    },
+    serial_print, serial_println,
+};
+
+// external crates used
+use alloc::{
+    alloc::{alloc, dealloc, Layout},
+    boxed::Box,
+    vec,
+    vec::Vec,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn heap_grows() {
+    serial_print!("heap_grows... ");
+    // more than the initial heap, in allocations too large for the slabs and
+    // too small to be large ones
+    let boxes: Vec<Box<[u8; 4096]>> = (0..2 * HEAP_SIZE / 4096)
+        .map(|i| Box::new([i as u8; 4096]))
+        .collect();
+    assert!(heap::size() > HEAP_SIZE);
+    for (i, block) in boxes.iter().enumerate() {
+        assert!(block.iter().all(|&byte| byte == i as u8));
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn large_allocations_freed() {
+    serial_print!("large_allocations_freed... ");
+    let size = heap::size();
+    let frames = memory::with_frame_allocator(|frame_allocator| frame_allocator.stats());
+
+    let large = vec![7u8; 1 << 20];
+    assert_eq!(heap::size(), size + (1 << 20));
+    assert!(large.iter().all(|&byte| byte == 7));
+    drop(large);
+
+    // the frames are given back at once
+    assert_eq!(heap::size(), size);
+    let after = memory::with_frame_allocator(|frame_allocator| frame_allocator.stats());
+    assert_eq!(after, frames);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn maximum_size() {
+    serial_print!("maximum_size... ");
+    heap::set_max_size(heap::size());
+    // larger than the free memory of the heap
+    let layout = Layout::from_size_align(8 << 20, 4096).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    heap::set_max_size(HEAP_MAX_SIZE);
+    assert!(ptr.is_null());
+
+    // below the maximum again
+    let layout = Layout::from_size_align(LARGE_SIZE, 4096).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { dealloc(ptr, layout) };
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn heap_statistics() {
+    use nit_os::memory::allocators::{size_class, SIZE_CLASS_COUNT};
+
+    serial_print!("heap_statistics... ");
+    let before = heap::stats();
+    let boxes: Vec<Box<[u8; 100]>> = (0..10).map(|_| Box::new([0; 100])).collect();
+    let stats = heap::stats();
+    assert!(stats.live >= before.live + 10);
+    assert!(stats.used >= before.used + 10 * 100);
+    assert!(stats.peak >= stats.used);
+    let class = size_class(100);
+    assert_eq!(class, size_class(128));
+    assert!(stats.allocations[class] >= before.allocations[class] + 10);
+    assert_eq!(size_class(1 << 20), SIZE_CLASS_COUNT - 1);
+    drop(boxes);
+    let after = heap::stats();
+    assert_eq!(after.live, before.live);
+    assert_eq!(after.used, before.used);
+    assert!(after.peak >= stats.used);
+
+    // a failed allocation is counted
+    let layout = Layout::from_size_align(2 * HEAP_MAX_SIZE, 4096).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    assert!(ptr.is_null());
+    assert_eq!(heap::stats().failed, after.failed + 1);
+    assert!(heap::allocator_stats().size <= heap::size());
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn heap_reserved() {
+    use nit_os::memory::{
+        heap::{HEAP_RESERVED, HEAP_START},
+        layout::KernelRegion,
+        vma::{self, VmaError},
+    };
+    use x86_64::VirtAddr;
+
+    serial_print!("heap_reserved... ");
+    let start = VirtAddr::new(HEAP_START as u64);
+    let area = vma::find(start + 4096u64).expect("heap not reserved");
+    assert_eq!(area.name, "heap");
+    assert_eq!((area.start, area.size()), (start, HEAP_RESERVED as u64));
+    assert_eq!(
+        vma::reserve_at(KernelRegion::Heap, start, 4096, "overlap"),
+        Err(VmaError::Overlap(area))
+    );
+    let other = vma::reserve(KernelRegion::Heap, 4096, "after heap").unwrap();
+    assert_eq!(other.start, area.end);
+    assert_eq!(vma::free(other.start), Ok(other));
+    serial_println!("[ok]");
+}
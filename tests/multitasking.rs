@@ -0,0 +1,224 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture, kernel_thread_local, memory, serial_print, serial_println,
+    task::{
+        self,
+        process::{self, ProcessState, KERNEL_PID},
+        scheduler,
+        thread::{ThreadId, ThreadState},
+        wait_queue::WaitQueue,
+    },
+};
+
+// external crates used
+use bootloader::{entry_point, BootInfo};
+use core::{
+    cell::Cell,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use x86_64::{
+    structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+    task::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn increment(times: usize) {
+    for _ in 0..times {
+        COUNTER.fetch_add(1, Ordering::SeqCst);
+        scheduler::yield_now();
+    }
+}
+
+#[test_case]
+fn kernel_threads() {
+    serial_print!("kernel_threads... ");
+    process::spawn_thread(KERNEL_PID, increment, 10).expect("spawn failed");
+    process::spawn_thread(KERNEL_PID, increment, 10).expect("spawn failed");
+    while COUNTER.load(Ordering::SeqCst) < 20 {
+        scheduler::yield_now();
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn isolated_address_spaces() {
+    serial_print!("isolated_address_spaces... ");
+    let page = Page::containing_address(VirtAddr::new(memory::layout::USER_SPACE_START));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    let mut frames = [None, None];
+    for frame in frames.iter_mut() {
+        let pid = process::create().expect("process creation failed");
+        *frame = process::with_process(pid, |process| {
+            let address_space = process.address_space().unwrap();
+            let new_frame = memory::with_frame_allocator(|frame_allocator| {
+                let new_frame = frame_allocator.allocate_frame().unwrap();
+                address_space
+                    .map(page, new_frame, flags, frame_allocator)
+                    .expect("mapping failed");
+                new_frame
+            });
+            assert_eq!(address_space.translate(page), Some((new_frame, flags)));
+            new_frame
+        })
+        .unwrap();
+    }
+    assert_ne!(frames[0], frames[1]);
+
+    // the user page is not mapped in the kernel page table
+    memory::with_kernel_memory(|mapper, _| assert!(mapper.translate_page(page).is_err()));
+    serial_println!("[ok]");
+}
+
+fn exit_process(code: usize) {
+    process::exit(code as i32);
+}
+
+#[test_case]
+fn process_exit() {
+    serial_print!("process_exit... ");
+    let pid = process::create().expect("process creation failed");
+    process::spawn_thread(pid, exit_process, 42).expect("spawn failed");
+
+    while process::with_process(pid, |process| process.state()).unwrap() == ProcessState::Running {
+        scheduler::yield_now();
+    }
+    let (state, freed) = process::with_process(pid, |process| {
+        (process.state(), process.address_space().is_none())
+    })
+    .unwrap();
+    assert_eq!(state, ProcessState::Exited(42));
+    assert!(freed);
+    serial_println!("[ok]");
+}
+
+kernel_thread_local! {
+    static LOCAL: Cell<usize> = Cell::new(1);
+}
+
+static LOCAL_CHECKED: AtomicBool = AtomicBool::new(false);
+
+fn use_local(value: usize) {
+    assert_eq!(LOCAL.with(|local| local.get()), 1);
+    LOCAL.with(|local| local.set(value));
+    scheduler::yield_now();
+    assert_eq!(LOCAL.with(|local| local.get()), value);
+    LOCAL_CHECKED.store(true, Ordering::SeqCst);
+}
+
+#[test_case]
+fn kernel_thread_locals() {
+    serial_print!("kernel_thread_locals... ");
+    LOCAL.with(|local| local.set(42));
+    process::spawn_thread(KERNEL_PID, use_local, 7).expect("spawn failed");
+    while !LOCAL_CHECKED.load(Ordering::SeqCst) {
+        scheduler::yield_now();
+    }
+    assert_eq!(LOCAL.with(|local| local.get()), 42);
+    serial_println!("[ok]");
+}
+
+fn do_nothing(_: usize) {}
+
+#[test_case]
+fn kernel_stacks_freed() {
+    use alloc::vec::Vec;
+    use nit_os::memory::{layout::KernelRegion, vma};
+
+    serial_print!("kernel_stacks_freed... ");
+    let before = vma::usage(KernelRegion::Stacks);
+    let threads: Vec<_> = (0..16)
+        .map(|_| process::spawn_thread(KERNEL_PID, do_nothing, 0).expect("spawn failed"))
+        .collect();
+    assert_eq!(vma::usage(KernelRegion::Stacks).0, before.0 + 16);
+    while threads.iter().any(|&id| scheduler::state(id).is_some()) {
+        scheduler::yield_now();
+    }
+    // the stacks of the reaped threads are unmapped and their ranges freed
+    assert_eq!(vma::usage(KernelRegion::Stacks), before);
+    serial_println!("[ok]");
+}
+
+static QUEUE: WaitQueue = WaitQueue::new();
+static OTHER_QUEUE: WaitQueue = WaitQueue::new();
+/// The conditions waited for, indexed by the argument of `wait_on_queue`.
+static CONDITIONS: [AtomicBool; 3] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+static DONE: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+/// Wait on `QUEUE` for the condition `index`, and then, for the first thread,
+/// on `OTHER_QUEUE`.
+fn wait_on_queue(index: usize) {
+    QUEUE.wait_until(|| CONDITIONS[index].load(Ordering::SeqCst));
+    DONE[index].store(true, Ordering::SeqCst);
+    if index == 0 {
+        OTHER_QUEUE.wait_until(|| CONDITIONS[2].load(Ordering::SeqCst));
+    }
+}
+
+/// Yield until the given thread is blocked.
+fn wait_blocked(id: ThreadId) {
+    while scheduler::state(id) != Some(ThreadState::Blocked) {
+        scheduler::yield_now();
+    }
+}
+
+#[test_case]
+fn spurious_wake_up() {
+    serial_print!("spurious_wake_up... ");
+    let first = process::spawn_thread(KERNEL_PID, wait_on_queue, 0).expect("spawn failed");
+    wait_blocked(first);
+    let second = process::spawn_thread(KERNEL_PID, wait_on_queue, 1).expect("spawn failed");
+    wait_blocked(second);
+
+    // woken like by a signal, the first thread finds its condition true
+    CONDITIONS[0].store(true, Ordering::SeqCst);
+    assert!(scheduler::unblock(first));
+    while !DONE[0].load(Ordering::SeqCst) {
+        scheduler::yield_now();
+    }
+    wait_blocked(first);
+
+    // it is not a waiter anymore : the second thread is woken
+    CONDITIONS[1].store(true, Ordering::SeqCst);
+    assert!(QUEUE.wake_one());
+    for _ in 0..100 {
+        scheduler::yield_now();
+    }
+    assert!(DONE[1].load(Ordering::SeqCst));
+    assert_eq!(scheduler::state(first), Some(ThreadState::Blocked));
+
+    CONDITIONS[2].store(true, Ordering::SeqCst);
+    OTHER_QUEUE.wake_all();
+    serial_println!("[ok]");
+}
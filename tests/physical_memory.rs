@@ -0,0 +1,191 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture,
+    memory::{
+        self,
+        buddy::{self, MAX_ORDER},
+        zone::{Zone, DMA_LIMIT, REAL_MODE_LIMIT},
+        BootInfoFrameAllocator,
+    },
+    serial_print, serial_println,
+};
+
+// external crates used
+use alloc::boxed::Box;
+use bootloader::{
+    bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType},
+    entry_point, BootInfo,
+};
+use core::panic::PanicInfo;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+/// Number of frames managed by the standalone `BootInfoFrameAllocator`.
+const STANDALONE_FRAMES: u64 = 8;
+
+/// Run `f` with a `BootInfoFrameAllocator` whose memory map is made of a
+/// single block, taken from the kernel frame allocator meanwhile.
+fn with_standalone_allocator<F>(f: F)
+where
+    F: FnOnce(&mut BootInfoFrameAllocator),
+{
+    let order = buddy::order_for(STANDALONE_FRAMES).unwrap();
+    let block = memory::with_frame_allocator(|frame_allocator| frame_allocator.allocate(order))
+        .expect("allocation failed");
+    let start = block.start_address().as_u64();
+    let mut memory_map = MemoryMap::new();
+    memory_map.add_region(MemoryRegion {
+        range: FrameRange::new(start, start + STANDALONE_FRAMES * 4096),
+        region_type: MemoryRegionType::Usable,
+    });
+    let memory_map: &'static MemoryMap = Box::leak(Box::new(memory_map));
+
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(memory_map) };
+    f(&mut frame_allocator);
+    memory::with_frame_allocator(|frame_allocator| frame_allocator.deallocate(block, order));
+}
+
+#[test_case]
+fn frame_statistics() {
+    serial_print!("frame_statistics... ");
+    with_standalone_allocator(|frame_allocator| {
+        let first = frame_allocator.allocate_frame().expect("allocation failed");
+        let before = frame_allocator.stats();
+        assert_eq!(before.total, STANDALONE_FRAMES);
+        assert_eq!(before.allocated, 1);
+
+        let frame = frame_allocator.allocate_frame().expect("allocation failed");
+        let stats = frame_allocator.stats();
+        assert_eq!(stats.allocated, before.allocated + 1);
+        assert_eq!(stats.free(), before.free() - 1);
+
+        frame_allocator.deallocate_frame(frame);
+        assert_eq!(frame_allocator.stats().recycled, before.recycled + 1);
+        assert_eq!(frame_allocator.stats().allocated, before.allocated);
+        frame_allocator.deallocate_frame(first);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn recycled_frames_reused() {
+    serial_print!("recycled_frames_reused... ");
+    with_standalone_allocator(|frame_allocator| {
+        let first = frame_allocator.allocate_frame().expect("allocation failed");
+        let second = frame_allocator.allocate_frame().expect("allocation failed");
+        assert_ne!(first, second);
+        frame_allocator.deallocate_frame(first);
+        frame_allocator.deallocate_frame(second);
+        // the last deallocated frame is reused first
+        assert_eq!(frame_allocator.allocate_frame(), Some(second));
+        assert_eq!(frame_allocator.allocate_frame(), Some(first));
+        assert_eq!(frame_allocator.stats().recycled, 0);
+
+        // then the frames never allocated, until the memory map is exhausted
+        for _ in 2..STANDALONE_FRAMES {
+            assert!(frame_allocator.allocate_frame().is_some());
+        }
+        assert_eq!(frame_allocator.allocate_frame(), None);
+        assert_eq!(frame_allocator.stats().free(), 0);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn buddy_statistics() {
+    serial_print!("buddy_statistics... ");
+    memory::with_frame_allocator(|frame_allocator| {
+        let before = frame_allocator.stats();
+        assert!(before.allocated > 0 && before.allocated <= before.total);
+
+        let frame = frame_allocator.allocate_frame().expect("allocation failed");
+        let stats = frame_allocator.stats();
+        assert_eq!(stats.allocated, before.allocated + 1);
+        assert_eq!(stats.free(), before.free() - 1);
+
+        frame_allocator.deallocate_frame(frame);
+        assert_eq!(frame_allocator.stats(), before);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn contiguous_blocks() {
+    serial_print!("contiguous_blocks... ");
+    memory::with_frame_allocator(|frame_allocator| {
+        let order = buddy::order_for(5).unwrap();
+        assert_eq!(order, 3);
+        let block = frame_allocator.allocate(order).expect("allocation failed");
+        // aligned to its size
+        assert_eq!(block.start_address().as_u64() % (8 * 4096), 0);
+        let frame = frame_allocator.allocate_frame().expect("allocation failed");
+        assert!(frame < block || frame >= block + 8);
+        frame_allocator.deallocate_frame(frame);
+        frame_allocator.deallocate(block, order);
+    });
+    assert_eq!(buddy::order_for(1 << (MAX_ORDER + 1)), None);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn buddies_merged() {
+    serial_print!("buddies_merged... ");
+    memory::with_frame_allocator(|frame_allocator| {
+        let before = frame_allocator.stats();
+        let block = frame_allocator.allocate(1).expect("allocation failed");
+        // freeing both halves gives the whole block back
+        frame_allocator.deallocate_frame(block);
+        frame_allocator.deallocate_frame(block + 1);
+        assert_eq!(frame_allocator.stats(), before);
+        assert_eq!(frame_allocator.allocate(1), Some(block));
+        frame_allocator.deallocate(block, 1);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn zone_constraints() {
+    serial_print!("zone_constraints... ");
+    memory::with_frame_allocator(|frame_allocator| {
+        let before = frame_allocator.zone_stats(Zone::Dma).expect("no DMA zone");
+        let block = frame_allocator
+            .allocate_in(2, Zone::Dma)
+            .expect("allocation failed");
+        assert!(block.start_address().as_u64() + 4 * 4096 <= DMA_LIMIT);
+        // accounted in its zone only
+        let stats = frame_allocator.zone_stats(Zone::Dma).unwrap();
+        assert_eq!(stats.allocated, before.allocated + 4);
+        frame_allocator.deallocate(block, 2);
+        assert_eq!(frame_allocator.zone_stats(Zone::Dma), Some(before));
+
+        // there may be no usable memory left below 1 MiB
+        if let Some(frame) = frame_allocator.allocate_below(0, REAL_MODE_LIMIT) {
+            assert!(frame.start_address().as_u64() < REAL_MODE_LIMIT);
+            frame_allocator.deallocate(frame, 0);
+        }
+    });
+    serial_println!("[ok]");
+}
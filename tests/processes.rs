@@ -0,0 +1,168 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture::{
+        self,
+        testing::executable::{executable, patch, wait},
+    },
+    memory, serial_print, serial_println,
+    task::{
+        self, exec,
+        process::{self, Pid, ProcessState, WaitError, KERNEL_PID},
+        signal::{self, SIGKILL},
+    },
+};
+
+// external crates used
+use alloc::{boxed::Box, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+    task::init();
+
+    exec::register(b"/child", leak(executable(&exit_program(3))));
+    // jmp $
+    exec::register(b"/loop", leak(executable(&[0xeb, 0xfe])));
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+/// Keep `image` for the lifetime of the kernel, to register it.
+fn leak(image: Vec<u8>) -> &'static [u8] {
+    Box::leak(image.into_boxed_slice())
+}
+
+/// Code exiting with `code`.
+fn exit_program(code: u8) -> Vec<u8> {
+    let mut program = Vec::new();
+    program.extend_from_slice(&[0xbf, code, 0, 0, 0]); // mov edi, code
+    program.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    program.extend_from_slice(&[0x0f, 0x05]); // syscall
+    program
+}
+
+/// Code calling the system call `number` with the path `path` and no
+/// arguments nor environment, followed by `rest`, then the path.
+fn path_call(number: u16, path: &[u8], rest: &[u8]) -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0x8d, 0x3d, 0, 0, 0, 0]); // lea rdi, [rip + path]
+    let path_displacement = code.len() - 4;
+    code.extend_from_slice(&[0x31, 0xf6]); // xor esi, esi
+    code.extend_from_slice(&[0x31, 0xd2]); // xor edx, edx
+    code.push(0xb8); // mov eax, number
+    code.extend_from_slice(&u32::from(number).to_le_bytes());
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(rest);
+
+    let path_offset = code.len();
+    code.extend_from_slice(path);
+    code.push(0);
+    patch(&mut code, path_displacement, path_offset);
+    code
+}
+
+/// Code exiting with the value of `rax`.
+const EXIT_WITH_RESULT: [u8; 9] = [
+    0x89, 0xc7, // mov edi, eax
+    0xb8, 60, 0, 0, 0, // mov eax, EXIT
+    0x0f, 0x05, // syscall
+];
+
+#[test_case]
+fn wait_reaps_child() {
+    serial_print!("wait_reaps_child... ");
+    let pid = exec::spawn_path(b"/child", &[], &[]).expect("spawn failed");
+    assert!(process::with_process(KERNEL_PID, |kernel| kernel.children().contains(&pid)).unwrap());
+    assert_eq!(wait(pid), ProcessState::Exited(3));
+    // the zombie is gone
+    assert!(process::with_process(pid, |_| ()).is_err());
+    assert_eq!(process::wait(Some(pid), true), Err(WaitError::NoChildren));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn spawn_missing_program() {
+    serial_print!("spawn_missing_program... ");
+    assert_eq!(
+        exec::spawn_path(b"/missing", &[], &[]),
+        Err(exec::ExecError::NotFound)
+    );
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn spawn_and_wait4() {
+    serial_print!("spawn_and_wait4... ");
+    let mut rest = Vec::new();
+    // wait4(-1, rsp - 16, 0, 0)
+    rest.extend_from_slice(&[0x48, 0x83, 0xec, 0x10]); // sub rsp, 16
+    rest.extend_from_slice(&[0x48, 0xc7, 0xc7, 0xff, 0xff, 0xff, 0xff]); // mov rdi, -1
+    rest.extend_from_slice(&[0x48, 0x89, 0xe6]); // mov rsi, rsp
+    rest.extend_from_slice(&[0x31, 0xd2]); // xor edx, edx
+    rest.extend_from_slice(&[0x45, 0x31, 0xd2]); // xor r10d, r10d
+    rest.extend_from_slice(&[0xb8, 61, 0, 0, 0]); // mov eax, WAIT4
+    rest.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // exit with the exit code of the child
+    rest.extend_from_slice(&[0x8b, 0x3c, 0x24]); // mov edi, [rsp]
+    rest.extend_from_slice(&[0xc1, 0xef, 0x08]); // shr edi, 8
+    rest.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    rest.extend_from_slice(&[0x0f, 0x05]); // syscall
+    let code = path_call(518, b"/child", &rest);
+
+    let pid = exec::spawn(&executable(&code), &[], &[]).expect("spawn failed");
+    assert_eq!(wait(pid), ProcessState::Exited(3));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn execve_replaces_program() {
+    serial_print!("execve_replaces_program... ");
+    let code = path_call(59, b"/child", &EXIT_WITH_RESULT);
+    let pid = exec::spawn(&executable(&code), &[], &[]).expect("spawn failed");
+    assert_eq!(wait(pid), ProcessState::Exited(3));
+
+    // -ENOENT
+    let code = path_call(59, b"/missing", &EXIT_WITH_RESULT);
+    let pid = exec::spawn(&executable(&code), &[], &[]).expect("spawn failed");
+    assert_eq!(wait(pid), ProcessState::Exited(-2));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn orphan_reparented_to_init() {
+    serial_print!("orphan_reparented_to_init... ");
+    // the parent exits with the identifier of its child
+    let code = path_call(518, b"/loop", &EXIT_WITH_RESULT);
+    let parent = exec::spawn(&executable(&code), &[], &[]).expect("spawn failed");
+    let child = match wait(parent) {
+        ProcessState::Exited(pid) => Pid::from_u64(pid as u64),
+        state => panic!("parent terminated with {:?}", state),
+    };
+
+    assert_eq!(process::init_pid(), KERNEL_PID);
+    let adopted = process::with_process(child, |child| child.parent()).unwrap();
+    assert_eq!(adopted, KERNEL_PID);
+    signal::send(child, SIGKILL).unwrap();
+    assert_eq!(wait(child), ProcessState::Killed(SIGKILL));
+    serial_println!("[ok]");
+}
@@ -0,0 +1,180 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture::{
+        self,
+        testing::executable::{executable, patch, run, wait},
+    },
+    memory, serial_print, serial_println,
+    task::{
+        self, exec,
+        process::ProcessState,
+        scheduler,
+        signal::{self, SIGILL, SIGPIPE, SIGSEGV, SIGTERM},
+    },
+};
+
+// external crates used
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+    task::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+/// Code installing a handler for `SIGUSR1` and sending it to itself.
+///
+/// The handler stores 35 below the stack pointer of the program, and clobbers
+/// `r12`. Once resumed, the program exits with the sum of the stored value
+/// and `r12`, which must be 42.
+fn handler_program() -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0x8d, 0x05, 0, 0, 0, 0]); // lea rax, [rip + handler]
+    let handler_displacement = code.len() - 4;
+    code.extend_from_slice(&[0x48, 0x8d, 0x0d, 0, 0, 0, 0]); // lea rcx, [rip + restorer]
+    let restorer_displacement = code.len() - 4;
+    // sigaction structure
+    code.extend_from_slice(&[0x6a, 0]); // push 0
+    code.extend_from_slice(&[0x51]); // push rcx
+    code.extend_from_slice(&[0x68, 0, 0, 0, 0x04]); // push SA_RESTORER
+    code.extend_from_slice(&[0x50]); // push rax
+    code.extend_from_slice(&[0x48, 0x89, 0xe3]); // mov rbx, rsp
+
+    // rt_sigaction(SIGUSR1, rsp, 0, 8)
+    code.extend_from_slice(&[0xbf, 10, 0, 0, 0]); // mov edi, SIGUSR1
+    code.extend_from_slice(&[0x48, 0x89, 0xe6]); // mov rsi, rsp
+    code.extend_from_slice(&[0x31, 0xd2]); // xor edx, edx
+    code.extend_from_slice(&[0x41, 0xba, 8, 0, 0, 0]); // mov r10d, 8
+    code.extend_from_slice(&[0xb8, 13, 0, 0, 0]); // mov eax, RT_SIGACTION
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // kill(getpid(), SIGUSR1)
+    code.extend_from_slice(&[0xb8, 39, 0, 0, 0]); // mov eax, GETPID
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x41, 0xbc, 7, 0, 0, 0]); // mov r12d, 7
+    code.extend_from_slice(&[0x89, 0xc7]); // mov edi, eax
+    code.extend_from_slice(&[0xbe, 10, 0, 0, 0]); // mov esi, SIGUSR1
+    code.extend_from_slice(&[0xb8, 62, 0, 0, 0]); // mov eax, KILL
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // resumed after the handler
+    code.extend_from_slice(&[0x48, 0x8b, 0x3b]); // mov rdi, [rbx]
+    code.extend_from_slice(&[0x44, 0x01, 0xe7]); // add edi, r12d
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    let handler = code.len();
+    code.extend_from_slice(&[0x41, 0xbc, 99, 0, 0, 0]); // mov r12d, 99
+    code.extend_from_slice(&[0x48, 0xc7, 0x03, 35, 0, 0, 0]); // mov qword ptr [rbx], 35
+    code.extend_from_slice(&[0xc3]); // ret
+
+    let restorer = code.len();
+    code.extend_from_slice(&[0xb8, 15, 0, 0, 0]); // mov eax, RT_SIGRETURN
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    patch(&mut code, handler_displacement, handler);
+    patch(&mut code, restorer_displacement, restorer);
+    code
+}
+
+#[test_case]
+fn page_fault_kills() {
+    serial_print!("page_fault_kills... ");
+    // xor eax, eax ; mov byte ptr [rax], al ; jmp $
+    let code = [0x31, 0xc0, 0x88, 0x00, 0xeb, 0xfe];
+    assert_eq!(run(&executable(&code)), ProcessState::Killed(SIGSEGV));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn invalid_opcode_kills() {
+    serial_print!("invalid_opcode_kills... ");
+    // ud2
+    let code = [0x0f, 0x0b];
+    assert_eq!(run(&executable(&code)), ProcessState::Killed(SIGILL));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn handler_and_sigreturn() {
+    serial_print!("handler_and_sigreturn... ");
+    assert_eq!(
+        run(&executable(&handler_program())),
+        ProcessState::Exited(42)
+    );
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn kill_running_process() {
+    serial_print!("kill_running_process... ");
+    // jmp $
+    let code = [0xeb, 0xfe];
+    let pid = exec::spawn(&executable(&code), &[], &[]).expect("spawn failed");
+    scheduler::yield_now();
+    signal::send(pid, SIGTERM).unwrap();
+    assert_eq!(wait(pid), ProcessState::Killed(SIGTERM));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn kill_blocked_process() {
+    serial_print!("kill_blocked_process... ");
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x31, 0xff]); // xor edi, edi
+    code.extend_from_slice(&[0x48, 0x8d, 0x74, 0x24, 0xc0]); // lea rsi, [rsp - 64]
+    code.extend_from_slice(&[0xba, 1, 0, 0, 0]); // mov edx, 1
+    code.extend_from_slice(&[0x31, 0xc0]); // xor eax, eax
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0xeb, 0xfe]); // jmp $
+    let pid = exec::spawn(&executable(&code), &[], &[]).expect("spawn failed");
+    // the read blocks, as no key is typed
+    scheduler::yield_now();
+    signal::send(pid, SIGTERM).unwrap();
+    assert_eq!(wait(pid), ProcessState::Killed(SIGTERM));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn broken_pipe_kills() {
+    serial_print!("broken_pipe_kills... ");
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0x83, 0xec, 0x10]); // sub rsp, 16
+    code.extend_from_slice(&[0x48, 0x89, 0xe7]); // mov rdi, rsp
+    code.extend_from_slice(&[0xb8, 22, 0, 0, 0]); // mov eax, PIPE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x8b, 0x3c, 0x24]); // mov edi, [rsp]
+    code.extend_from_slice(&[0xb8, 3, 0, 0, 0]); // mov eax, CLOSE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // write(writer, rsp, 1)
+    code.extend_from_slice(&[0x8b, 0x7c, 0x24, 0x04]); // mov edi, [rsp + 4]
+    code.extend_from_slice(&[0x48, 0x89, 0xe6]); // mov rsi, rsp
+    code.extend_from_slice(&[0xba, 1, 0, 0, 0]); // mov edx, 1
+    code.extend_from_slice(&[0xb8, 1, 0, 0, 0]); // mov eax, WRITE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0xeb, 0xfe]); // jmp $
+    assert_eq!(run(&executable(&code)), ProcessState::Killed(SIGPIPE));
+    serial_println!("[ok]");
+}
@@ -0,0 +1,320 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture::{
+        self,
+        testing::executable::{executable, run, wait},
+    },
+    memory, serial_print, serial_println,
+    task::{
+        self, exec,
+        process::{self, ProcessState},
+        scheduler,
+        signal::{self, SIGKILL},
+    },
+    time,
+};
+
+// external crates used
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+    task::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+/// Code writing "hello" from `rip + displacement`, and exiting with the result.
+fn write_and_exit(displacement: u32) -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0xb8, 1, 0, 0, 0]); // mov eax, WRITE
+    code.extend_from_slice(&[0xbf, 1, 0, 0, 0]); // mov edi, STDOUT
+    code.extend_from_slice(&[0x48, 0x8d, 0x35]); // lea rsi, [rip + displacement]
+    code.extend_from_slice(&displacement.to_le_bytes());
+    code.extend_from_slice(&[0xba, 5, 0, 0, 0]); // mov edx, 5
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x89, 0xc7]); // mov edi, eax
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0xeb, 0xfe]); // jmp $
+    code.extend_from_slice(b"hello");
+    code
+}
+
+/// Offset of the message from the end of the `lea` instruction.
+const MESSAGE_DISPLACEMENT: u32 = 18;
+
+#[test_case]
+fn write_then_exit() {
+    serial_print!("write_then_exit... ");
+    let code = write_and_exit(MESSAGE_DISPLACEMENT);
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(5));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn bad_pointer() {
+    serial_print!("bad_pointer... ");
+    // the message is in an unmapped page
+    let code = write_and_exit(MESSAGE_DISPLACEMENT + 0x10_0000);
+    // -EFAULT
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(-14));
+    serial_println!("[ok]");
+}
+
+/// Code waiting on a futex on the stack holding 0, with the given expected
+/// value and a timeout of `seconds` and 10 ms, and exiting with the result.
+fn futex_wait(expected: u8, seconds: u64) -> Vec<u8> {
+    let mut code = Vec::new();
+    // timespec { seconds, 10 ms }
+    code.extend_from_slice(&[0x68, 0x80, 0x96, 0x98, 0x00]); // push 10_000_000
+    code.extend_from_slice(&[0x48, 0xb8]); // mov rax, seconds
+    code.extend_from_slice(&seconds.to_le_bytes());
+    code.push(0x50); // push rax
+    code.extend_from_slice(&[0x49, 0x89, 0xe2]); // mov r10, rsp
+
+    // the futex value
+    code.extend_from_slice(&[0x6a, 0]); // push 0
+    code.extend_from_slice(&[0x48, 0x89, 0xe7]); // mov rdi, rsp
+    code.extend_from_slice(&[0x31, 0xf6]); // xor esi, esi (FUTEX_WAIT)
+    code.extend_from_slice(&[0xba, expected, 0, 0, 0]); // mov edx, expected
+    code.extend_from_slice(&[0xb8, 0xca, 0, 0, 0]); // mov eax, FUTEX
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x89, 0xc7]); // mov edi, eax
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code
+}
+
+#[test_case]
+fn futex_value_changed() {
+    serial_print!("futex_value_changed... ");
+    // -EAGAIN
+    assert_eq!(
+        run(&executable(&futex_wait(1, 0))),
+        ProcessState::Exited(-11)
+    );
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn futex_timeout() {
+    serial_print!("futex_timeout... ");
+    // -ETIMEDOUT
+    assert_eq!(
+        run(&executable(&futex_wait(0, 0))),
+        ProcessState::Exited(-110)
+    );
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn futex_longest_timeout() {
+    serial_print!("futex_longest_timeout... ");
+    let seconds = i64::max_value() as u64;
+    // -EAGAIN, the deadline computation must not overflow
+    assert_eq!(
+        run(&executable(&futex_wait(1, seconds))),
+        ProcessState::Exited(-11)
+    );
+
+    // the wait must not time out early
+    let pid = exec::spawn(&executable(&futex_wait(0, seconds)), &[], &[]).expect("spawn failed");
+    let start = time::ticks();
+    while time::ticks() < start + 20 {
+        scheduler::yield_now();
+    }
+    let state = process::with_process(pid, |process| process.state()).unwrap();
+    assert_eq!(state, ProcessState::Running);
+
+    signal::send(pid, SIGKILL).unwrap();
+    assert_eq!(wait(pid), ProcessState::Killed(SIGKILL));
+    serial_println!("[ok]");
+}
+
+/// Code writing 5 bytes to a pipe through a descriptor duplicated with `dup2`,
+/// closing the writing end, and reading the pipe twice.
+///
+/// Exits with the first read count times 16 plus the second one, which must be
+/// 0 at the end of the file.
+fn pipe_program() -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0x83, 0xec, 0x40]); // sub rsp, 64
+    code.extend_from_slice(&[0x48, 0x89, 0xe7]); // mov rdi, rsp
+    code.extend_from_slice(&[0xb8, 22, 0, 0, 0]); // mov eax, PIPE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // dup2(writer, 10) ; close(writer)
+    code.extend_from_slice(&[0x8b, 0x7c, 0x24, 0x04]); // mov edi, [rsp + 4]
+    code.extend_from_slice(&[0xbe, 10, 0, 0, 0]); // mov esi, 10
+    code.extend_from_slice(&[0xb8, 33, 0, 0, 0]); // mov eax, DUP2
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x8b, 0x7c, 0x24, 0x04]); // mov edi, [rsp + 4]
+    code.extend_from_slice(&[0xb8, 3, 0, 0, 0]); // mov eax, CLOSE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // write(10, rsp + 16, 5) ; close(10)
+    code.extend_from_slice(&[0xbf, 10, 0, 0, 0]); // mov edi, 10
+    code.extend_from_slice(&[0x48, 0x8d, 0x74, 0x24, 0x10]); // lea rsi, [rsp + 16]
+    code.extend_from_slice(&[0xba, 5, 0, 0, 0]); // mov edx, 5
+    code.extend_from_slice(&[0xb8, 1, 0, 0, 0]); // mov eax, WRITE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0xbf, 10, 0, 0, 0]); // mov edi, 10
+    code.extend_from_slice(&[0xb8, 3, 0, 0, 0]); // mov eax, CLOSE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // read(reader, rsp + 32, 16), twice
+    for _ in 0..2 {
+        code.extend_from_slice(&[0x89, 0xc3]); // mov ebx, eax
+        code.extend_from_slice(&[0x8b, 0x3c, 0x24]); // mov edi, [rsp]
+        code.extend_from_slice(&[0x48, 0x8d, 0x74, 0x24, 0x20]); // lea rsi, [rsp + 32]
+        code.extend_from_slice(&[0xba, 16, 0, 0, 0]); // mov edx, 16
+        code.extend_from_slice(&[0x31, 0xc0]); // xor eax, eax
+        code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    }
+
+    code.extend_from_slice(&[0xc1, 0xe3, 0x04]); // shl ebx, 4
+    code.extend_from_slice(&[0x8d, 0x3c, 0x03]); // lea edi, [rbx + rax]
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code
+}
+
+#[test_case]
+fn pipe_end_of_file() {
+    serial_print!("pipe_end_of_file... ");
+    assert_eq!(
+        run(&executable(&pipe_program())),
+        ProcessState::Exited(5 * 16)
+    );
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn close_bad_descriptor() {
+    serial_print!("close_bad_descriptor... ");
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0xbf, 42, 0, 0, 0]); // mov edi, 42
+    code.extend_from_slice(&[0xb8, 3, 0, 0, 0]); // mov eax, CLOSE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x89, 0xc7]); // mov edi, eax
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // -EBADF
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(-9));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn descriptor_limit() {
+    serial_print!("descriptor_limit... ");
+    let mut code = Vec::new();
+    // setrlimit(RLIMIT_NOFILE, { 3, 3 }), the standard descriptors are open
+    code.extend_from_slice(&[0x6a, 3]); // push 3
+    code.extend_from_slice(&[0x6a, 3]); // push 3
+    code.extend_from_slice(&[0x48, 0x89, 0xe6]); // mov rsi, rsp
+    code.extend_from_slice(&[0xbf, 7, 0, 0, 0]); // mov edi, RLIMIT_NOFILE
+    code.extend_from_slice(&[0xb8, 160, 0, 0, 0]); // mov eax, SETRLIMIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // dup(STDIN)
+    code.extend_from_slice(&[0x31, 0xff]); // xor edi, edi
+    code.extend_from_slice(&[0xb8, 32, 0, 0, 0]); // mov eax, DUP
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x89, 0xc7]); // mov edi, eax
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // -EMFILE
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(-24));
+    serial_println!("[ok]");
+}
+
+/// Code reading a message with at most one handle from the channel handle at
+/// `rsp + 4`, in non-blocking mode.
+const CHANNEL_READ: [u8; 41] = [
+    0x8b, 0x7c, 0x24, 0x04, // mov edi, [rsp + 4]
+    0x48, 0x8d, 0x74, 0x24, 0x28, // lea rsi, [rsp + 40]
+    0xba, 8, 0, 0, 0, // mov edx, 8
+    0x4c, 0x8d, 0x54, 0x24, 0x20, // lea r10, [rsp + 32]
+    0x41, 0xb8, 1, 0, 0, 0, // mov r8d, 1
+    0x41, 0xb9, 1, 0, 0, 0, // mov r9d, CHANNEL_NONBLOCK
+    0xb8, 0x02, 0x02, 0, 0, // mov eax, CHANNEL_READ
+    0x0f, 0x05, // syscall
+    0x49, 0x89, 0xc4, // mov r12, rax
+];
+
+#[test_case]
+fn channel_handle_limit() {
+    serial_print!("channel_handle_limit... ");
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0x83, 0xec, 0x40]); // sub rsp, 64
+
+    // two channels, whose handles are at rsp and rsp + 8
+    code.extend_from_slice(&[0x48, 0x89, 0xe7]); // mov rdi, rsp
+    code.extend_from_slice(&[0xb8, 0x00, 0x02, 0, 0]); // mov eax, CHANNEL_CREATE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x48, 0x8d, 0x7c, 0x24, 0x08]); // lea rdi, [rsp + 8]
+    code.extend_from_slice(&[0xb8, 0x00, 0x02, 0, 0]); // mov eax, CHANNEL_CREATE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // send the last handle through the first channel, 3 handles are left
+    code.extend_from_slice(&[0x8b, 0x3c, 0x24]); // mov edi, [rsp]
+    code.extend_from_slice(&[0x48, 0x89, 0xe6]); // mov rsi, rsp
+    code.extend_from_slice(&[0x31, 0xd2]); // xor edx, edx
+    code.extend_from_slice(&[0x4c, 0x8d, 0x54, 0x24, 0x0c]); // lea r10, [rsp + 12]
+    code.extend_from_slice(&[0x41, 0xb8, 1, 0, 0, 0]); // mov r8d, 1
+    code.extend_from_slice(&[0x45, 0x31, 0xc9]); // xor r9d, r9d
+    code.extend_from_slice(&[0xb8, 0x01, 0x02, 0, 0]); // mov eax, CHANNEL_WRITE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // setrlimit(RLIMIT_NOFILE, { 3, 3 })
+    code.extend_from_slice(&[0x48, 0xc7, 0x44, 0x24, 0x10, 3, 0, 0, 0]); // mov qword [rsp + 16], 3
+    code.extend_from_slice(&[0x48, 0xc7, 0x44, 0x24, 0x18, 3, 0, 0, 0]); // mov qword [rsp + 24], 3
+    code.extend_from_slice(&[0xbf, 7, 0, 0, 0]); // mov edi, RLIMIT_NOFILE
+    code.extend_from_slice(&[0x48, 0x8d, 0x74, 0x24, 0x10]); // lea rsi, [rsp + 16]
+    code.extend_from_slice(&[0xb8, 160, 0, 0, 0]); // mov eax, SETRLIMIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // the handle does not fit, then fits once another one is closed
+    code.extend_from_slice(&CHANNEL_READ);
+    code.extend_from_slice(&[0x4c, 0x89, 0xe3]); // mov rbx, r12
+    code.extend_from_slice(&[0x8b, 0x7c, 0x24, 0x08]); // mov edi, [rsp + 8]
+    code.extend_from_slice(&[0xb8, 0x03, 0x02, 0, 0]); // mov eax, HANDLE_CLOSE
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&CHANNEL_READ);
+
+    // exit with the first result plus 100 times the received handles
+    code.extend_from_slice(&[0x4c, 0x89, 0xe0]); // mov rax, r12
+    code.extend_from_slice(&[0x48, 0xc1, 0xe8, 0x20]); // shr rax, 32
+    code.extend_from_slice(&[0x6b, 0xc0, 100]); // imul eax, eax, 100
+    code.extend_from_slice(&[0x01, 0xd8]); // add eax, ebx
+    code.extend_from_slice(&[0x89, 0xc7]); // mov edi, eax
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+
+    // -EMFILE, and the message is still received
+    assert_eq!(run(&executable(&code)), ProcessState::Exited(-24 + 100));
+    serial_println!("[ok]");
+}
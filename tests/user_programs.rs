@@ -0,0 +1,171 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nit_os::architecture::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// enable the builtin alloc crate
+extern crate alloc;
+
+// internal functions used
+use nit_os::{
+    architecture::{
+        self,
+        testing::executable::{code_offset, executable_with, run, Segment},
+    },
+    memory::{self, address_space::AddressSpace, layout::USER_SPACE_START},
+    serial_print, serial_println,
+    task::{
+        self,
+        elf::{ElfError, ElfFile, PF_R, PF_W, PF_X, PT_TLS},
+        exec,
+        process::{self, ProcessState},
+        scheduler,
+    },
+};
+
+// external crates used
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::{panic::PanicInfo, ptr};
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    architecture::init();
+    memory::init(boot_info);
+    task::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    nit_os::architecture::testing::test_panic_handler(info)
+}
+
+/// Offset of the code in the test executables, which have two segments.
+const CODE_OFFSET: u64 = code_offset(2) as u64;
+/// Address of the counter incremented by the test executable.
+const COUNTER_ADDRESS: u64 = USER_SPACE_START + 0x1000;
+
+/// Build an executable incrementing a counter in a loop.
+fn counter_program() -> Vec<u8> {
+    // inc qword ptr [rip + COUNTER_ADDRESS - next instruction]
+    // jmp to the previous instruction
+    let displacement = (COUNTER_ADDRESS - (USER_SPACE_START + CODE_OFFSET + 7)) as u32;
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0xff, 0x05]);
+    code.extend_from_slice(&displacement.to_le_bytes());
+    code.extend_from_slice(&[0xeb, 0xf7]);
+
+    let file_size = CODE_OFFSET + code.len() as u64;
+    let segments = [
+        Segment::load(PF_R | PF_X, USER_SPACE_START, file_size, file_size),
+        Segment::load(PF_R | PF_W, COUNTER_ADDRESS, 0, 8),
+    ];
+    executable_with(&code, &segments)
+}
+
+#[test_case]
+fn invalid_executables() {
+    serial_print!("invalid_executables... ");
+    let mut image = counter_program();
+    assert_eq!(ElfFile::parse(&image[..32]).err(), Some(ElfError::Truncated));
+    image[25] += 1; // entry after the code segment
+    assert_eq!(ElfFile::parse(&image).err(), Some(ElfError::InvalidEntry));
+    image[0] = 0;
+    assert_eq!(ElfFile::parse(&image).err(), Some(ElfError::BadMagic));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn segment_flags() {
+    serial_print!("segment_flags... ");
+    let image = counter_program();
+    let elf = ElfFile::parse(&image).expect("parsing failed");
+    assert_eq!(elf.program_headers_address(), Some(VirtAddr::new(USER_SPACE_START + 64)));
+
+    let mut address_space = AddressSpace::new().expect("address space creation failed");
+    elf.load(&mut address_space).expect("loading failed");
+
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let code_page = Page::containing_address(VirtAddr::new(USER_SPACE_START));
+    let data_page = Page::containing_address(VirtAddr::new(COUNTER_ADDRESS));
+    assert_eq!(address_space.translate(code_page).unwrap().1, user);
+    assert_eq!(
+        address_space.translate(data_page).unwrap().1,
+        user | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+    );
+    assert_eq!(address_space.regions().len(), 2);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn user_mode() {
+    serial_print!("user_mode... ");
+    let image = counter_program();
+    let pid = exec::spawn(&image, &[&b"counter"[..]], &[]).expect("spawn failed");
+
+    let data_page = Page::containing_address(VirtAddr::new(COUNTER_ADDRESS));
+    let (frame, _) = process::with_process(pid, |process| {
+        process.address_space().unwrap().translate(data_page)
+    })
+    .unwrap()
+    .expect("counter is not mapped");
+    let counter: *const u64 = memory::phys_to_virt(frame.start_address()).as_ptr();
+
+    while unsafe { ptr::read_volatile(counter) } == 0 {
+        scheduler::yield_now();
+    }
+    serial_println!("[ok]");
+}
+
+/// Build an executable with a thread-local storage holding 42 then 0, exiting
+/// with their sum read through the thread pointer after a context switch.
+fn tls_program() -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0xb8, 24, 0, 0, 0]); // mov eax, SCHED_YIELD
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    code.extend_from_slice(&[0x64, 0x48, 0x8b, 0x04, 0x25, 0, 0, 0, 0]); // mov rax, fs:[0]
+    code.extend_from_slice(&[0x8b, 0x78, 0xf0]); // mov edi, [rax - 16]
+    code.extend_from_slice(&[0x03, 0x78, 0xf8]); // add edi, [rax - 8]
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0]); // mov eax, EXIT
+    code.extend_from_slice(&[0x0f, 0x05]); // syscall
+    let template_offset = CODE_OFFSET + code.len() as u64;
+    code.extend_from_slice(&42u64.to_le_bytes());
+
+    let file_size = CODE_OFFSET + code.len() as u64;
+    let template = Segment {
+        kind: PT_TLS,
+        flags: PF_R,
+        offset: template_offset,
+        address: USER_SPACE_START + template_offset,
+        file_size: 8,
+        memory_size: 16,
+        align: 8,
+    };
+    let segments = [
+        Segment::load(PF_R | PF_X, USER_SPACE_START, file_size, file_size),
+        template,
+    ];
+    executable_with(&code, &segments)
+}
+
+#[test_case]
+fn thread_local_storage() {
+    serial_print!("thread_local_storage... ");
+    let image = tls_program();
+    let elf = ElfFile::parse(&image).expect("parsing failed");
+    let template = elf.tls_template().expect("no thread-local storage");
+    assert_eq!(template.image, &42u64.to_le_bytes());
+    assert_eq!((template.memory_size, template.align), (16, 8));
+
+    assert_eq!(run(&image), ProcessState::Exited(42));
+    serial_println!("[ok]");
+}